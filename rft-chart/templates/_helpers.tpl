@@ -0,0 +1,130 @@
+{{/*
+Redis connection settings shared by the gateway and controller. The password is mounted from a
+secret as a file rather than passed in the environment.
+*/}}
+{{- define "rft.redisEnv" -}}
+- name: REDIS_HOST
+  value: {{ .Values.redisConnection.host | default (printf "%s-redis-master" .Release.Name) | quote }}
+- name: REDIS_PORT
+  value: {{ .Values.redisConnection.port | quote }}
+- name: REDIS_TLS
+  value: {{ .Values.redisConnection.tls | quote }}
+{{- if .Values.redisConnection.caSecret }}
+- name: REDIS_TLS_CA_FILE
+  value: /etc/rft/redis-ca/ca.crt
+{{- end }}
+{{- if .Values.redisConnection.username }}
+- name: REDIS_USERNAME
+  value: {{ .Values.redisConnection.username | quote }}
+{{- end }}
+- name: REDIS_PASSWORD_FILE
+  value: /etc/rft/redis/password
+- name: REDIS_DB
+  value: {{ .Values.redisConnection.db | quote }}
+{{- if .Values.redisConnection.keyPrefix }}
+- name: REDIS_KEY_PREFIX
+  value: {{ .Values.redisConnection.keyPrefix | quote }}
+{{- end }}
+{{- end }}
+
+{{- define "rft.redisVolumeMounts" -}}
+- name: redis-password
+  mountPath: /etc/rft/redis
+  readOnly: true
+{{- if .Values.redisConnection.caSecret }}
+- name: redis-ca
+  mountPath: /etc/rft/redis-ca
+  readOnly: true
+{{- end }}
+{{- end }}
+
+{{- define "rft.redisVolumes" -}}
+- name: redis-password
+  secret:
+    secretName: {{ .Values.redisConnection.passwordSecret | default (printf "%s-redis" .Release.Name) }}
+    items:
+      - key: {{ .Values.redisConnection.passwordKey }}
+        path: password
+{{- if .Values.redisConnection.caSecret }}
+- name: redis-ca
+  secret:
+    secretName: {{ .Values.redisConnection.caSecret }}
+    items:
+      - key: ca.crt
+        path: ca.crt
+{{- end }}
+{{- end }}
+
+{{/*
+Artifact bucket settings shared by the gateway and controller
+*/}}
+{{- define "rft.artifactsEnv" -}}
+{{- if .Values.artifacts.bucket }}
+- name: ARTIFACTS_BUCKET
+  value: {{ .Values.artifacts.bucket | quote }}
+{{- if .Values.artifacts.endpoint }}
+- name: ARTIFACTS_ENDPOINT
+  value: {{ .Values.artifacts.endpoint | quote }}
+{{- end }}
+- name: ARTIFACTS_REGION
+  value: {{ .Values.artifacts.region | quote }}
+{{- end }}
+{{- end }}
+
+{{/*
+Log store settings shared by the gateway and controller. The filesystem store mounts a shared
+volume at /var/lib/rft/logs
+*/}}
+{{- define "rft.logStoreEnv" -}}
+- name: LOG_STORE
+  value: {{ .Values.logStore.type | quote }}
+{{- if .Values.logStore.maxBytes }}
+- name: LOG_STORE_MAX_BYTES
+  value: {{ .Values.logStore.maxBytes | quote }}
+{{- end }}
+{{- if eq .Values.logStore.type "filesystem" }}
+- name: LOG_STORE_DIR
+  value: /var/lib/rft/logs
+{{- end }}
+{{- end }}
+
+{{- define "rft.artifactsCredentialsEnv" -}}
+- name: AWS_ACCESS_KEY_ID
+  valueFrom:
+    secretKeyRef:
+      name: {{ .Values.artifacts.credentialsSecret }}
+      key: AWS_ACCESS_KEY_ID
+- name: AWS_SECRET_ACCESS_KEY
+  valueFrom:
+    secretKeyRef:
+      name: {{ .Values.artifacts.credentialsSecret }}
+      key: AWS_SECRET_ACCESS_KEY
+{{- end }}
+
+{{- define "rft.logStoreVolumeMounts" -}}
+{{- if eq .Values.logStore.type "filesystem" }}
+- name: log-store
+  mountPath: /var/lib/rft/logs
+{{- end }}
+{{- end }}
+
+{{- define "rft.logStoreVolumes" -}}
+{{- if eq .Values.logStore.type "filesystem" }}
+- name: log-store
+  persistentVolumeClaim:
+    claimName: {{ .Values.logStore.claimName }}
+{{- end }}
+{{- end }}
+
+{{/*
+Key job tokens are signed with, shared by the gateway and controller
+*/}}
+{{- define "rft.jobTokenEnv" -}}
+{{- if .Values.jobReporting.secretName }}
+- name: JOB_TOKEN_SECRET
+  valueFrom:
+    secretKeyRef:
+      name: {{ .Values.jobReporting.secretName }}
+      key: {{ .Values.jobReporting.secretKey }}
+{{- end }}
+{{- end }}
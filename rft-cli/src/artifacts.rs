@@ -0,0 +1,88 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use isahc::prelude::*;
+use serde_json::Value;
+
+/// An artifact listed by the gateway
+#[derive(Debug, PartialEq)]
+pub struct Artifact {
+    pub job_id: String,
+    pub name: String,
+    pub size: u64,
+    pub url: String,
+}
+
+/// Reads the artifacts from a `GET /batch/<id>/artifacts` response
+pub fn from_response(response: &Value) -> Vec<Artifact> {
+    response["artifacts"]
+        .as_array()
+        .map(|artifacts| {
+            artifacts
+                .iter()
+                .map(|artifact| Artifact {
+                    job_id: artifact["job_id"].as_str().unwrap_or_default().to_string(),
+                    name: artifact["name"].as_str().unwrap_or_default().to_string(),
+                    size: artifact["size"].as_u64().unwrap_or_default(),
+                    url: artifact["url"].as_str().unwrap_or_default().to_string(),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Where an artifact is saved, `<output_dir>/<job_id>/<name>`. Returns None for names which
+/// would escape the job's directory, i.e. `../../.bashrc`.
+pub fn local_path(output_dir: &Path, artifact: &Artifact) -> Option<PathBuf> {
+    let relative = Path::new(&artifact.job_id).join(&artifact.name);
+    if artifact.job_id.is_empty()
+        || artifact.name.is_empty()
+        || !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return None;
+    }
+
+    Some(output_dir.join(relative))
+}
+
+/// Downloads an artifact from its presigned URL, creating parent directories as needed
+pub fn download(artifact: &Artifact, path: &Path) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+
+    let mut response = isahc::get(&artifact.url).map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("bucket responded {}", response.status()));
+    }
+    response.copy_to_file(path).map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::artifacts::{local_path, Artifact};
+    use std::path::Path;
+
+    #[test]
+    fn keeps_artifacts_in_the_output_dir() {
+        let mut artifact = Artifact {
+            job_id: "j1".to_string(),
+            name: "plots/loss.png".to_string(),
+            size: 10,
+            url: String::new(),
+        };
+        assert_eq!(
+            local_path(Path::new("out"), &artifact),
+            Some(Path::new("out/j1/plots/loss.png").to_path_buf())
+        );
+
+        artifact.name = "../../.bashrc".to_string();
+        assert_eq!(local_path(Path::new("out"), &artifact), None);
+        artifact.name = "/etc/passwd".to_string();
+        assert_eq!(local_path(Path::new("out"), &artifact), None);
+    }
+}
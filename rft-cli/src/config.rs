@@ -0,0 +1,287 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, env, fmt, fs, path::PathBuf};
+
+pub const DEFAULT_GATEWAY: &str = "http://127.0.0.1:8000";
+pub const DEFAULT_PROFILE: &str = "default";
+
+#[derive(Debug)]
+pub enum ConfigError {
+    NoConfigDir,
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Write(PathBuf, String),
+    UnknownKey(String),
+    InvalidValue(String, String),
+    UnknownProfile(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::NoConfigDir => write!(
+                f,
+                "Unable to find a config directory. Set RFT_CONFIG or HOME to choose one"
+            ),
+            ConfigError::Read(path, err) => {
+                write!(f, "Unable to read {}: {}", path.display(), err)
+            }
+            ConfigError::Parse(path, err) => {
+                write!(f, "Unable to parse {}: {}", path.display(), err)
+            }
+            ConfigError::Write(path, err) => {
+                write!(f, "Unable to write {}: {}", path.display(), err)
+            }
+            ConfigError::UnknownKey(key) => write!(
+                f,
+                "Unknown config key '{}'. Valid keys are: {}",
+                key,
+                SETTABLE_KEYS.join(", ")
+            ),
+            ConfigError::InvalidValue(key, value) => {
+                write!(f, "Invalid value '{}' for config key '{}'", value, key)
+            }
+            ConfigError::UnknownProfile(name) => write!(f, "No profile named '{}'", name),
+        }
+    }
+}
+
+const SETTABLE_KEYS: [&str; 7] = [
+    "gateway",
+    "token",
+    "defaults.format",
+    "defaults.strategy",
+    "defaults.samples",
+    "defaults.content_ids",
+    "defaults.priority",
+];
+
+/// The CLI config file, ~/.config/rft/config.toml by default:
+/// current_profile = "dev"
+///
+/// [profiles.dev]
+/// gateway = "http://127.0.0.1:8000"
+///
+/// [profiles.prod]
+/// gateway = "https://rft.example.com"
+/// token = "..."
+///
+/// [profiles.prod.defaults]
+/// format = "matrix"
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Config {
+    /// the profile used when --profile and RFT_PROFILE aren't set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub current_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Profile {
+    /// base URL of the rft gateway
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gateway: Option<String>,
+    /// API token sent to the gateway
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// options used by `rft-client run` when they aren't passed on the command line
+    #[serde(default)]
+    pub defaults: RunDefaults,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RunDefaults {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub samples: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_ids: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<String>,
+}
+
+/// Settings resolved from command line flags, environment variables and the config file
+#[derive(Debug)]
+pub struct Settings {
+    pub profile_name: String,
+    pub gateway: String,
+    /// API token from RFT_TOKEN or the profile
+    pub token: Option<String>,
+    pub profile: Profile,
+}
+
+impl Settings {
+    /// Builds a gateway URL for an API path, i.e. `url("/batch")`
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.gateway.trim_end_matches('/'), path)
+    }
+}
+
+/// Returns the path of the config file, preferring RFT_CONFIG then XDG_CONFIG_HOME
+pub fn config_path() -> Result<PathBuf, ConfigError> {
+    if let Ok(path) = env::var("RFT_CONFIG") {
+        return Ok(PathBuf::from(path));
+    }
+
+    let config_dir = match env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var("HOME").map_err(|_| ConfigError::NoConfigDir)?).join(".config"),
+    };
+
+    Ok(config_dir.join("rft").join("config.toml"))
+}
+
+impl Config {
+    pub fn load() -> Result<Config, ConfigError> {
+        let path = config_path()?;
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+
+        let contents = fs::read_to_string(&path).map_err(|e| ConfigError::Read(path.clone(), e))?;
+        toml::from_str(&contents).map_err(|e| ConfigError::Parse(path, e))
+    }
+
+    pub fn save(&self) -> Result<PathBuf, ConfigError> {
+        let path = config_path()?;
+        let contents = toml::to_string_pretty(self)
+            .map_err(|e| ConfigError::Write(path.clone(), e.to_string()))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| ConfigError::Write(path.clone(), e.to_string()))?;
+        }
+        fs::write(&path, contents).map_err(|e| ConfigError::Write(path.clone(), e.to_string()))?;
+
+        // The config holds API tokens, so keep it private to the current user
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o600))
+                .map_err(|e| ConfigError::Write(path.clone(), e.to_string()))?;
+        }
+
+        Ok(path)
+    }
+
+    /// Picks the active profile name: --profile, then RFT_PROFILE, then current_profile
+    pub fn active_profile_name(&self, profile_arg: Option<&str>) -> String {
+        profile_arg
+            .map(|p| p.to_string())
+            .or_else(|| env::var("RFT_PROFILE").ok())
+            .or_else(|| self.current_profile.clone())
+            .unwrap_or_else(|| DEFAULT_PROFILE.to_string())
+    }
+
+    /// Resolves settings, with --gateway taking precedence over RFT_GATEWAY and the profile
+    pub fn resolve(&self, profile_arg: Option<&str>, gateway_arg: Option<&str>) -> Settings {
+        let profile_name = self.active_profile_name(profile_arg);
+        let profile = self
+            .profiles
+            .get(&profile_name)
+            .cloned()
+            .unwrap_or_default();
+
+        let gateway = gateway_arg
+            .map(|g| g.to_string())
+            .or_else(|| env::var("RFT_GATEWAY").ok())
+            .or_else(|| profile.gateway.clone())
+            .unwrap_or_else(|| DEFAULT_GATEWAY.to_string());
+
+        let token = env::var("RFT_TOKEN").ok().or_else(|| profile.token.clone());
+
+        Settings {
+            profile_name,
+            gateway,
+            token,
+            profile,
+        }
+    }
+
+    /// Sets a single key on a profile, creating the profile if needed
+    pub fn set(&mut self, profile_name: &str, key: &str, value: &str) -> Result<(), ConfigError> {
+        let profile = self.profiles.entry(profile_name.to_string()).or_default();
+        let invalid = || ConfigError::InvalidValue(key.to_string(), value.to_string());
+        match key {
+            "gateway" => profile.gateway = Some(value.to_string()),
+            "token" => profile.token = Some(value.to_string()),
+            "defaults.format" => match value {
+                "pairs" | "matrix" => profile.defaults.format = Some(value.to_string()),
+                _ => return Err(invalid()),
+            },
+            "defaults.strategy" => match value {
+                "random" | "lhs" | "sobol" => profile.defaults.strategy = Some(value.to_string()),
+                _ => return Err(invalid()),
+            },
+            "defaults.samples" => {
+                profile.defaults.samples = Some(value.parse().map_err(|_| invalid())?)
+            }
+            "defaults.content_ids" => {
+                profile.defaults.content_ids = Some(value.parse().map_err(|_| invalid())?)
+            }
+            "defaults.priority" => match value {
+                "low" | "normal" | "high" | "urgent" => {
+                    profile.defaults.priority = Some(value.to_string())
+                }
+                _ => return Err(invalid()),
+            },
+            _ => return Err(ConfigError::UnknownKey(key.to_string())),
+        }
+
+        Ok(())
+    }
+
+    pub fn unset(&mut self, profile_name: &str, key: &str) -> Result<(), ConfigError> {
+        let profile = self
+            .profiles
+            .get_mut(profile_name)
+            .ok_or_else(|| ConfigError::UnknownProfile(profile_name.to_string()))?;
+        match key {
+            "gateway" => profile.gateway = None,
+            "token" => profile.token = None,
+            "defaults.format" => profile.defaults.format = None,
+            "defaults.strategy" => profile.defaults.strategy = None,
+            "defaults.samples" => profile.defaults.samples = None,
+            "defaults.content_ids" => profile.defaults.content_ids = None,
+            "defaults.priority" => profile.defaults.priority = None,
+            _ => return Err(ConfigError::UnknownKey(key.to_string())),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_and_resolve_profiles() {
+        let mut config = Config::default();
+        config
+            .set("prod", "gateway", "https://rft.example.com/")
+            .unwrap();
+        config.set("prod", "defaults.samples", "200").unwrap();
+        config
+            .set("prod", "defaults.format", "grid")
+            .expect_err("grid is not a valid format");
+        config
+            .set("prod", "gatway", "https://rft.example.com")
+            .expect_err("gatway is not a valid key");
+
+        let settings = config.resolve(Some("prod"), None);
+        assert_eq!(settings.url("/batch"), "https://rft.example.com/batch");
+        assert_eq!(settings.profile.defaults.samples, Some(200));
+
+        let settings = config.resolve(Some("prod"), Some("http://localhost:9000"));
+        assert_eq!(settings.url("/batch"), "http://localhost:9000/batch");
+
+        let serialized = toml::to_string_pretty(&config).unwrap();
+        let parsed: Config = toml::from_str(&serialized).unwrap();
+        assert_eq!(
+            parsed.profiles["prod"].gateway.as_deref(),
+            Some("https://rft.example.com/")
+        );
+    }
+}
@@ -0,0 +1,412 @@
+use serde_json::Value;
+use std::{cmp::Ordering, collections::HashMap, fmt};
+
+#[derive(Debug)]
+pub struct FilterError(pub String);
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+type Result<T, E = FilterError> = std::result::Result<T, E>;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Str(String),
+    Ident(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+}
+
+#[derive(Debug)]
+enum Expr {
+    Literal(Value),
+    Param(String),
+    Not(Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+/// Filters are simple boolean expressions over param values, i.e.
+/// `end_date > start_date && region != "eu-west-1"`
+///
+/// Supported syntax:
+/// - literals: numbers, "strings" or 'strings', true, false
+/// - param names: any identifier made of letters, digits and underscores
+/// - arithmetic: + - * /
+/// - comparisons: == != < <= > >=
+/// - boolean logic: && || ! (or `and`, `or`, `not`) and parentheses
+///
+/// A parsed filter is evaluated against each job's params during expansion
+#[derive(Debug)]
+pub struct Filter {
+    source: String,
+    expr: Expr,
+}
+
+impl Filter {
+    pub fn parse(source: &str) -> Result<Filter> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.parse_or()?;
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            return Err(FilterError(format!(
+                "Unexpected {:?} in filter '{}'",
+                token, source
+            )));
+        }
+
+        Ok(Filter {
+            source: source.to_string(),
+            expr,
+        })
+    }
+
+    /// Evaluates the filter against a job's params. The expression must produce a boolean.
+    pub fn matches(&self, params: &HashMap<String, Value>) -> Result<bool> {
+        match evaluate(&self.expr, params)? {
+            Value::Bool(result) => Ok(result),
+            other => Err(FilterError(format!(
+                "Filter '{}' produced {} instead of true or false",
+                self.source, other
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// Keeps combinations matching every `where` filter and none of the `exclude` filters.
+/// Returns the kept combinations and how many were dropped.
+pub fn apply_filters(
+    combos: Vec<HashMap<String, Value>>,
+    wheres: &[Filter],
+    excludes: &[Filter],
+) -> Result<(Vec<HashMap<String, Value>>, usize)> {
+    let total = combos.len();
+    let mut kept = Vec::with_capacity(total);
+    'combos: for combo in combos {
+        for filter in wheres {
+            if !filter.matches(&combo)? {
+                continue 'combos;
+            }
+        }
+        for filter in excludes {
+            if filter.matches(&combo)? {
+                continue 'combos;
+            }
+        }
+        kept.push(combo);
+    }
+
+    let dropped = total - kept.len();
+    Ok((kept, dropped))
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        match c {
+            _ if c.is_whitespace() => i += 1,
+            '(' => {
+                tokens.push(Token::LParen);
+                i += 1;
+            }
+            ')' => {
+                tokens.push(Token::RParen);
+                i += 1;
+            }
+            '"' | '\'' => {
+                let end = chars[i + 1..]
+                    .iter()
+                    .position(|&x| x == c)
+                    .ok_or_else(|| FilterError(format!("Unterminated string in '{}'", source)))?;
+                tokens.push(Token::Str(chars[i + 1..i + 1 + end].iter().collect()));
+                i += end + 2;
+            }
+            _ if c.is_ascii_digit() || (c == '.' && next.is_some_and(|n| n.is_ascii_digit())) => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                let number: String = chars[start..i].iter().collect();
+                let number = number
+                    .parse()
+                    .map_err(|_| FilterError(format!("Invalid number '{}'", number)))?;
+                tokens.push(Token::Number(number));
+            }
+            _ if c.is_alphabetic() || c == '_' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                let ident: String = chars[start..i].iter().collect();
+                tokens.push(match ident.as_str() {
+                    "and" => Token::Op("&&"),
+                    "or" => Token::Op("||"),
+                    "not" => Token::Op("!"),
+                    _ => Token::Ident(ident),
+                });
+            }
+            _ => {
+                let two: String = chars[i..chars.len().min(i + 2)].iter().collect();
+                let op = ["&&", "||", "==", "!=", "<=", ">="]
+                    .iter()
+                    .find(|op| **op == two)
+                    .or_else(|| {
+                        ["<", ">", "!", "+", "-", "*", "/"]
+                            .iter()
+                            .find(|op| op.starts_with(c))
+                    })
+                    .ok_or_else(|| {
+                        FilterError(format!("Unexpected character '{}' in '{}'", c, source))
+                    })?;
+                tokens.push(Token::Op(op));
+                i += op.len();
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn eat_op(&mut self, ops: &[&'static str]) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) if ops.contains(op) => {
+                self.pos += 1;
+                Some(op)
+            }
+            _ => None,
+        }
+    }
+
+    fn parse_binary(
+        &mut self,
+        ops: &[&'static str],
+        next: fn(&mut Parser) -> Result<Expr>,
+    ) -> Result<Expr> {
+        let mut left = next(self)?;
+        while let Some(op) = self.eat_op(ops) {
+            let right = next(self)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+
+        Ok(left)
+    }
+
+    fn parse_or(&mut self) -> Result<Expr> {
+        self.parse_binary(&["||"], Parser::parse_and)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr> {
+        self.parse_binary(&["&&"], Parser::parse_not)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr> {
+        if self.eat_op(&["!"]).is_some() {
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr> {
+        let left = self.parse_sum()?;
+        match self.eat_op(&["==", "!=", "<", "<=", ">", ">="]) {
+            Some(op) => Ok(Expr::Binary(
+                op,
+                Box::new(left),
+                Box::new(self.parse_sum()?),
+            )),
+            None => Ok(left),
+        }
+    }
+
+    fn parse_sum(&mut self) -> Result<Expr> {
+        self.parse_binary(&["+", "-"], Parser::parse_product)
+    }
+
+    fn parse_product(&mut self) -> Result<Expr> {
+        self.parse_binary(&["*", "/"], Parser::parse_primary)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        match token {
+            Some(Token::Number(n)) => Ok(Expr::Literal(Value::from(n))),
+            Some(Token::Str(s)) => Ok(Expr::Literal(Value::String(s))),
+            Some(Token::Ident(ident)) => Ok(match ident.as_str() {
+                "true" => Expr::Literal(Value::Bool(true)),
+                "false" => Expr::Literal(Value::Bool(false)),
+                _ => Expr::Param(ident),
+            }),
+            Some(Token::Op("-")) => Ok(Expr::Binary(
+                "-",
+                Box::new(Expr::Literal(Value::from(0.0))),
+                Box::new(self.parse_primary()?),
+            )),
+            Some(Token::LParen) => {
+                let expr = self.parse_or()?;
+                match self.tokens.get(self.pos) {
+                    Some(Token::RParen) => {
+                        self.pos += 1;
+                        Ok(expr)
+                    }
+                    _ => Err(FilterError("Missing closing parenthesis".to_string())),
+                }
+            }
+            Some(token) => Err(FilterError(format!("Unexpected {:?}", token))),
+            None => Err(FilterError("Unexpected end of filter".to_string())),
+        }
+    }
+}
+
+fn evaluate(expr: &Expr, params: &HashMap<String, Value>) -> Result<Value> {
+    match expr {
+        Expr::Literal(value) => Ok(value.clone()),
+        Expr::Param(name) => params
+            .get(name)
+            .cloned()
+            .ok_or_else(|| FilterError(format!("Unknown parameter '{}' in filter", name))),
+        Expr::Not(inner) => match evaluate(inner, params)? {
+            Value::Bool(b) => Ok(Value::Bool(!b)),
+            other => Err(FilterError(format!("Cannot negate {}", other))),
+        },
+        Expr::Binary(op, left, right) => {
+            let left = evaluate(left, params)?;
+
+            // Short circuit boolean logic so later clauses can assume earlier ones held
+            if *op == "&&" || *op == "||" {
+                let left = as_bool(&left)?;
+                if (*op == "&&" && !left) || (*op == "||" && left) {
+                    return Ok(Value::Bool(left));
+                }
+                return Ok(Value::Bool(as_bool(&evaluate(right, params)?)?));
+            }
+
+            let right = evaluate(right, params)?;
+            match *op {
+                "==" => Ok(Value::Bool(compare(&left, &right) == Some(Ordering::Equal))),
+                "!=" => Ok(Value::Bool(compare(&left, &right) != Some(Ordering::Equal))),
+                "<" | "<=" | ">" | ">=" => {
+                    let ordering = compare(&left, &right).ok_or_else(|| {
+                        FilterError(format!("Cannot compare {} with {}", left, right))
+                    })?;
+                    Ok(Value::Bool(match *op {
+                        "<" => ordering == Ordering::Less,
+                        "<=" => ordering != Ordering::Greater,
+                        ">" => ordering == Ordering::Greater,
+                        _ => ordering != Ordering::Less,
+                    }))
+                }
+                _ => {
+                    let (l, r) = match (left.as_f64(), right.as_f64()) {
+                        (Some(l), Some(r)) => (l, r),
+                        _ => {
+                            return Err(FilterError(format!(
+                                "Cannot apply '{}' to {} and {}",
+                                op, left, right
+                            )))
+                        }
+                    };
+                    Ok(Value::from(match *op {
+                        "+" => l + r,
+                        "-" => l - r,
+                        "*" => l * r,
+                        _ => l / r,
+                    }))
+                }
+            }
+        }
+    }
+}
+
+fn as_bool(value: &Value) -> Result<bool> {
+    value
+        .as_bool()
+        .ok_or_else(|| FilterError(format!("Expected true or false but found {}", value)))
+}
+
+/// Numbers compare numerically (so 1 == 1.0), everything else only compares with its own type
+fn compare(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left, right) {
+        (Value::Number(l), Value::Number(r)) => l.as_f64()?.partial_cmp(&r.as_f64()?),
+        (Value::String(l), Value::String(r)) => Some(l.cmp(r)),
+        (Value::Bool(l), Value::Bool(r)) => Some(l.cmp(r)),
+        (l, r) if l == r => Some(Ordering::Equal),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn params(start_date: i64, end_date: i64, region: &str) -> HashMap<String, Value> {
+        let mut params = HashMap::new();
+        params.insert("start_date".to_string(), json!(start_date));
+        params.insert("end_date".to_string(), json!(end_date));
+        params.insert("region".to_string(), json!(region));
+        params
+    }
+
+    #[test]
+    fn evaluates_expressions() {
+        let filter = Filter::parse("end_date - start_date >= 10 && region != 'eu-west-1'")
+            .expect("Should parse a valid filter");
+
+        assert!(filter.matches(&params(1980, 2020, "us-east-1")).unwrap());
+        assert!(!filter.matches(&params(2015, 2020, "us-east-1")).unwrap());
+        assert!(!filter.matches(&params(1980, 2020, "eu-west-1")).unwrap());
+
+        let filter = Filter::parse("not (start_date == 1980 or region == \"eu-west-1\")").unwrap();
+        assert!(!filter.matches(&params(1980, 2020, "us-east-1")).unwrap());
+        assert!(filter.matches(&params(1990, 2020, "us-east-1")).unwrap());
+    }
+
+    #[test]
+    fn reports_invalid_filters() {
+        Filter::parse("start_date >").expect_err("Missing right hand side");
+        Filter::parse("(start_date > 1").expect_err("Missing closing parenthesis");
+        Filter::parse("start_date $ 1").expect_err("Unknown operator");
+
+        let filter = Filter::parse("start_dat > 1").unwrap();
+        filter
+            .matches(&params(1980, 2020, "us-east-1"))
+            .expect_err("Unknown parameter");
+    }
+
+    #[test]
+    fn counts_dropped_combinations() {
+        let combos = vec![
+            params(1980, 2020, "us-east-1"),
+            params(2020, 1980, "us-east-1"),
+            params(1990, 2000, "eu-west-1"),
+        ];
+        let wheres = vec![Filter::parse("end_date > start_date").unwrap()];
+        let excludes = vec![Filter::parse("region == 'eu-west-1'").unwrap()];
+
+        let (kept, dropped) = apply_filters(combos, &wheres, &excludes).unwrap();
+        assert_eq!(kept.len(), 1);
+        assert_eq!(dropped, 2);
+    }
+}
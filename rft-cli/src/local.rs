@@ -0,0 +1,119 @@
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+
+use rft_core::batch::Batch;
+use rft_core::local::{JobDir, Reports};
+use serde_json::{json, Value};
+
+/// Directory local runs are written to when --run-dir isn't given, one directory per batch
+pub const DEFAULT_RUN_DIR: &str = "rft-runs";
+
+/// How a job run on the local machine finished
+pub struct JobOutcome {
+    /// None if the worker couldn't be started or was killed by a signal
+    pub exit_code: Option<i32>,
+    /// why the worker couldn't be started
+    pub error: Option<String>,
+    pub reports: Reports,
+}
+
+impl JobOutcome {
+    pub fn succeeded(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+}
+
+/// Runs every job in the batch as a subprocess from `workdir`, the repository root, with up to
+/// `concurrency` running at once. Each job gets a directory under `run_dir` laid out like its
+/// pod's volumes, see `JobDir`. Outcomes are returned in job order.
+pub fn run_batch(
+    batch: &Batch,
+    workdir: &Path,
+    run_dir: &Path,
+    concurrency: usize,
+) -> Vec<JobOutcome> {
+    let source_file = workdir.join(&batch.source_file);
+    let next = Mutex::new(0);
+    let outcomes = Mutex::new(Vec::new());
+
+    thread::scope(|scope| {
+        for _ in 0..concurrency.clamp(1, batch.jobs.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = {
+                    let mut next = next.lock().unwrap();
+                    *next += 1;
+                    *next - 1
+                };
+                let job = match batch.jobs.get(index) {
+                    Some(job) => job,
+                    None => return,
+                };
+
+                let job_dir = JobDir::new(run_dir, &job.job_id);
+                let status = job_dir
+                    .prepare(job)
+                    .and_then(|_| job_dir.command(batch, index, workdir, &source_file, &[]))
+                    .and_then(|mut command| command.status());
+                let outcome = match status {
+                    Ok(status) => JobOutcome {
+                        exit_code: status.code(),
+                        error: None,
+                        reports: job_dir.reports(),
+                    },
+                    Err(err) => JobOutcome {
+                        exit_code: None,
+                        error: Some(err.to_string()),
+                        reports: Reports::default(),
+                    },
+                };
+
+                match (&outcome.error, outcome.exit_code) {
+                    (Some(err), _) => println!("Job {} failed to start: {}", job.job_id, err),
+                    (None, Some(code)) => println!("Job {} exited with code {}", job.job_id, code),
+                    (None, None) => println!("Job {} was killed", job.job_id),
+                }
+                outcomes.lock().unwrap().push((index, outcome));
+            });
+        }
+    });
+
+    let mut outcomes = outcomes.into_inner().unwrap();
+    outcomes.sort_by_key(|(index, _)| *index);
+    outcomes.into_iter().map(|(_, outcome)| outcome).collect()
+}
+
+/// The run's results in the format of the gateway's `GET /batch/<id>/results`, with each job's
+/// exit code and log file added
+pub fn results(batch: &Batch, run_dir: &Path, outcomes: &[JobOutcome]) -> Value {
+    let jobs: Vec<Value> = batch
+        .jobs
+        .iter()
+        .zip(outcomes)
+        .map(|(job, outcome)| {
+            json!({
+                "job_id": job.job_id,
+                "params": job.params,
+                "result": outcome.reports.result,
+                "exit_code": outcome.exit_code,
+                "log_file": JobDir::new(run_dir, &job.job_id).log_path(),
+            })
+        })
+        .collect();
+
+    json!({
+        "batch_id": batch.batch_id,
+        "completed": outcomes.iter().filter(|o| o.reports.result.is_some()).count(),
+        "jobs": jobs,
+    })
+}
+
+/// Directory a batch's local run is written to. Made absolute since jobs run from the
+/// repository root rather than the current directory.
+pub fn batch_run_dir(run_dir: Option<&str>, batch: &Batch) -> PathBuf {
+    let run_dir = PathBuf::from(run_dir.unwrap_or(DEFAULT_RUN_DIR)).join(&batch.batch_id);
+    match std::env::current_dir() {
+        Ok(current_dir) => current_dir.join(run_dir),
+        Err(_) => run_dir,
+    }
+}
@@ -1,24 +1,55 @@
+mod artifacts;
+mod config;
+mod filter;
+mod local;
+mod params;
+mod project;
+mod results;
+mod sampling;
+
 use clap::{crate_version, App, Arg, Values};
+use config::Settings;
+use filter::{apply_filters, Filter};
 use git2::{Config, ErrorCode, Repository};
 use isahc::{prelude::*, Body, Error, HttpClient, Request, Response};
-use rft_core::batch::Batch;
+use params::{
+    generate_map_for_params, generate_param_combos, generate_param_pairs, remove_duplicate_combos,
+    ParamError, ParamFormat,
+};
+use rft_core::batch::{Batch, Priority};
 use rft_core::job::Job;
-use std::{collections::HashMap, process::exit};
-
-enum ParamError {
-    InvalidParam,
-}
-
-#[derive(PartialEq, Eq)]
-enum ParamFormat {
-    Pairs,
-    Matrix,
-}
+use rft_core::schema::ParamSchema;
+use sampling::{generate_space_for_params, sample_params, Strategy};
+use serde_json::Value;
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    process::exit,
+    time::Duration,
+};
 
 fn main() {
     let app = App::new("rft-client")
         .version(crate_version!())
         .about("rust data framework kubernetes operator queue thing")
+        .arg(
+            Arg::new("profile")
+                .about("Config profile to use. Defaults to RFT_PROFILE or the current profile in the config file")
+                .long("profile")
+                .value_name("name")
+                .takes_value(true)
+                .global(true)
+        )
+        .arg(
+            Arg::new("gateway")
+                .about("Gateway URL, i.e. http://127.0.0.1:8000. Overrides RFT_GATEWAY and the profile's gateway")
+                .long("gateway")
+                .value_name("url")
+                .takes_value(true)
+                .global(true)
+        )
         .subcommand(App::new("run")
             .about("Creates compute jobs using a source file and set of parameters")
             .arg(
@@ -50,121 +81,1141 @@ fn main() {
                     .required(true)
                     .possible_values(&["pairs", "matrix"])
                     .default_value("pairs")
+            )
+            .arg(
+                Arg::new("strategy")
+                    .about("Sample jobs from the parameter space instead of using --format. Parameters accept ranges (low..high) or value lists")
+                    .long("strategy")
+                    .takes_value(true)
+                    .possible_values(&["random", "lhs", "sobol"])
+            )
+            .arg(
+                Arg::new("samples")
+                    .about("Number of jobs to sample when using --strategy")
+                    .long("samples")
+                    .value_name("count")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::new("seed")
+                    .about("Seed for --strategy so samples can be reproduced. A random seed is used and printed if not provided")
+                    .long("seed")
+                    .value_name("seed")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::new("content-ids")
+                    .about("Derive job IDs from the current commit, source file and params so resubmitted jobs keep the same ID")
+                    .long("content-ids")
+            )
+            .arg(
+                Arg::new("priority")
+                    .about("How urgently the batch should run. Higher priority batches are started first and can preempt lower priority pods")
+                    .long("priority")
+                    .takes_value(true)
+                    .possible_values(&["low", "normal", "high", "urgent"])
+            )
+            .arg(
+                Arg::new("after")
+                    .about("Wait for this batch to complete before running, failing if it fails. May be repeated")
+                    .long("after")
+                    .value_name("batch_id")
+                    .multiple_occurrences(true)
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::new("where")
+                    .about("Only keep parameter combinations for which this expression is true, i.e. 'end_date > start_date'")
+                    .long("where")
+                    .value_name("expression")
+                    .multiple_occurrences(true)
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::new("exclude")
+                    .about("Drop parameter combinations for which this expression is true, i.e. 'region == \"eu-west-1\" && start_date < 2000'")
+                    .long("exclude")
+                    .value_name("expression")
+                    .multiple_occurrences(true)
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::new("dry-run")
+                    .about("Print the expanded jobs and resolved source without submitting anything to the gateway")
+                    .long("dry-run")
+            )
+            .arg(
+                Arg::new("show-manifest")
+                    .about("With --dry-run, also print the Kubernetes Job manifest that would run the batch")
+                    .long("show-manifest")
+                    .requires("dry-run")
+            )
+            .arg(
+                Arg::new("output")
+                    .about("Write the batch instead of submitting it, so it can be sent later with 'rft-client submit'")
+                    .long("output")
+                    .takes_value(true)
+                    .possible_values(&["json"])
+                    .requires("output-file")
+            )
+            .arg(
+                Arg::new("output-file")
+                    .about("File to write the batch to when using --output")
+                    .long("output-file")
+                    .value_name("filename")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::new("local")
+                    .about("Run the jobs as processes on this machine instead of submitting them to the gateway")
+                    .long("local")
+                    .conflicts_with_all(&["dry-run", "output", "after"])
+            )
+            .arg(
+                Arg::new("concurrency")
+                    .about("Jobs run at once with --local. Defaults to the number of CPUs")
+                    .long("concurrency")
+                    .value_name("count")
+                    .takes_value(true)
+                    .requires("local")
+            )
+            .arg(
+                Arg::new("run-dir")
+                    .about("Directory --local writes each job's input, output, log and the batch's results to, under <run-dir>/<batch_id>")
+                    .long("run-dir")
+                    .value_name("dir")
+                    .default_value(local::DEFAULT_RUN_DIR)
+                    .takes_value(true)
+            ))
+        .subcommand(App::new("submit")
+            .about("Submits a batch previously written with 'rft-client run --output json'")
+            .arg(
+                Arg::new("batch")
+                    .about("Path to the batch JSON file")
+                    .required(true)
+                    .value_name("batch.json")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::new("priority")
+                    .about("Override the priority stored in the batch file")
+                    .long("priority")
+                    .takes_value(true)
+                    .possible_values(&["low", "normal", "high", "urgent"])
+            ))
+        .subcommand(App::new("status")
+            .about("Shows whether a batch is waiting, queued, running or finished")
+            .arg(
+                Arg::new("batch_id")
+                    .about("ID of the batch, as printed when it was submitted")
+                    .required(true)
+                    .takes_value(true)
+            ))
+        .subcommand(App::new("results")
+            .about("Exports the results posted by a batch's jobs, joined with each job's params")
+            .arg(
+                Arg::new("batch_id")
+                    .about("ID of the batch, as printed when it was submitted")
+                    .required(true)
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::new("format")
+                    .about("Format to export in. Parquet is written to --output-file")
+                    .long("format")
+                    .takes_value(true)
+                    .default_value("csv")
+                    .possible_values(&results::FORMATS)
+            )
+            .arg(
+                Arg::new("output_file")
+                    .about("Write results to a file instead of stdout")
+                    .short('o')
+                    .long("output-file")
+                    .value_name("filename")
+                    .takes_value(true)
+            ))
+        .subcommand(App::new("artifacts")
+            .about("Lists and downloads the files jobs wrote to their output directory")
+            .subcommand(App::new("list")
+                .about("Lists a batch's artifacts")
+                .arg(Arg::new("batch_id").required(true).takes_value(true))
+                .arg(
+                    Arg::new("job")
+                        .about("Only list artifacts from this job")
+                        .long("job")
+                        .value_name("job_id")
+                        .takes_value(true)
+                ))
+            .subcommand(App::new("download")
+                .about("Downloads a batch's artifacts to <output-dir>/<job_id>/")
+                .arg(Arg::new("batch_id").required(true).takes_value(true))
+                .arg(
+                    Arg::new("job")
+                        .about("Only download artifacts from this job")
+                        .long("job")
+                        .value_name("job_id")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::new("output_dir")
+                        .about("Directory to download to, defaults to ./artifacts/<batch_id>")
+                        .short('o')
+                        .long("output-dir")
+                        .value_name("dir")
+                        .takes_value(true)
+                )))
+        .subcommand(App::new("logs")
+            .about("Prints a job's logs from the most recent pod that ran it")
+            .arg(Arg::new("batch_id").required(true).takes_value(true))
+            .arg(
+                Arg::new("job")
+                    .about("ID of the job, needed unless the batch has a single job")
+                    .long("job")
+                    .value_name("job_id")
+                    .takes_value(true)
+                    .conflicts_with("index")
+            )
+            .arg(
+                Arg::new("index")
+                    .about("Position of the job in the batch, starting at 0, i.e. the pod's completion index")
+                    .long("index")
+                    .value_name("N")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::new("follow")
+                    .about("Keep printing new output until the job's container exits")
+                    .short('f')
+                    .long("follow")
+            )
+            .arg(
+                Arg::new("tail")
+                    .about("Only print the last N lines")
+                    .long("tail")
+                    .value_name("N")
+                    .takes_value(true)
+            ))
+        .subcommand(App::new("login")
+            .about("Stores an API token for the active profile after checking it with the gateway")
+            .arg(
+                Arg::new("token")
+                    .about("API token to log in with. Read from stdin if not provided")
+                    .long("token")
+                    .value_name("token")
+                    .takes_value(true)
             ))
+        .subcommand(App::new("config")
+            .about("Manages CLI profiles stored in ~/.config/rft/config.toml")
+            .subcommand(App::new("list")
+                .about("Lists configured profiles"))
+            .subcommand(App::new("show")
+                .about("Prints the settings resolved for the active profile"))
+            .subcommand(App::new("use")
+                .about("Sets the profile used when --profile isn't passed")
+                .arg(Arg::new("name").required(true).takes_value(true)))
+            .subcommand(App::new("set")
+                .about("Sets a value on the active profile, i.e. 'rft-client --profile prod config set gateway https://rft.example.com'")
+                .arg(Arg::new("key").required(true).takes_value(true)
+                    .possible_values(&["gateway", "token", "defaults.format", "defaults.strategy", "defaults.samples", "defaults.content_ids", "defaults.priority"]))
+                .arg(Arg::new("value").required(true).takes_value(true)))
+            .subcommand(App::new("unset")
+                .about("Removes a value from the active profile")
+                .arg(Arg::new("key").required(true).takes_value(true)))
+            .subcommand(App::new("remove")
+                .about("Deletes a profile")
+                .arg(Arg::new("name").required(true).takes_value(true))))
+        .subcommand(App::new("params")
+            .about("Inspects the parameters declared in a source file's rft.toml")
+            .subcommand(App::new("describe")
+                .about("Prints the parameter schema that applies to a source file")
+                .arg(
+                    Arg::new("file")
+                        .about("Source file to describe parameters for. Accepts a path relative to the current directory")
+                        .short('f')
+                        .long("file")
+                        .required(true)
+                        .value_name("filename")
+                        .takes_value(true)
+                )))
         .get_matches();
 
+    let mut config = config::Config::load().unwrap_or_else(|err| {
+        println!("Error! - {}", err);
+        exit(1);
+    });
+    let (profile_arg, gateway_arg) = match app.subcommand() {
+        Some((_, sub_matches)) => (
+            sub_matches.value_of("profile"),
+            sub_matches.value_of("gateway"),
+        ),
+        None => (app.value_of("profile"), app.value_of("gateway")),
+    };
+    let settings = config.resolve(profile_arg, gateway_arg);
+    let defaults = settings.profile.defaults.clone();
+
     // Handle RUN command logic
     if let Some(run_matches) = app.subcommand_matches("run") {
         if let Some(filename) = run_matches.value_of("file") {
-            if let Some(format) = run_matches.value_of("format") {
+            let format = match (run_matches.occurrences_of("format"), &defaults.format) {
+                (0, Some(format)) => Some(format.as_str()),
+                _ => run_matches.value_of("format"),
+            };
+            if let Some(format) = format {
                 if let Some(params) = run_matches.values_of("params") {
-                    match format {
-                        "pairs" => match generate_map_for_params(params, ParamFormat::Pairs) {
-                            Ok((param_map, num_of_pairs)) => {
-                                let repo = match Repository::discover(".") {
-                                    Ok(repo) => repo,
-                                    Err(e) => {
-                                        eprintln!("Failed to open a Git repository: {}", e);
-                                        std::process::exit(1);
-                                    }
-                                };
-
-                                let author = get_current_author();
-                                let full_path = get_full_source_path(&repo, filename);
-                                let origin_url = get_repository_url(&repo);
-                                let current_branch = get_current_branch(&repo);
-                                let mut batch =
-                                    Batch::new(&author, &full_path, &origin_url, &current_branch);
-                                for i in 0..num_of_pairs {
-                                    let mut single_job_params: HashMap<String, String> =
-                                        HashMap::new();
-                                    for (key, val) in &param_map {
-                                        single_job_params
-                                            .insert(key.clone(), val.get(i).unwrap().clone());
-                                    }
-
-                                    let job = Job::new(single_job_params);
-                                    batch.jobs.push(job.clone());
-                                }
+                    let wheres = parse_filters(run_matches.values_of("where"));
+                    let excludes = parse_filters(run_matches.values_of("exclude"));
+                    let declared_schema = load_declared_schema(filename);
 
-                                println!("Batch has {} jobs", batch.jobs.len());
-                                let json = serde_json::to_string(&batch)
-                                    .unwrap_or_else(|_| "".to_string());
-                                match post_batch("http://127.0.0.1:8000/batch", json) {
-                                    Ok(mut body) => {
-                                        if let Ok(response) = body.text() {
-                                            println!("Successfully posted job batch to gateway with response: {}", response);
-                                        }
-                                    }
-                                    Err(err) => println!(
-                                        "Error! - Failed to post job batch to gateway: {}",
-                                        err
-                                    ),
+                    let strategy = run_matches
+                        .value_of("strategy")
+                        .or(defaults.strategy.as_deref());
+                    let combos = if let Some(strategy) = strategy {
+                        let strategy = Strategy::from_name(strategy).unwrap_or_else(|| {
+                            println!("Error! - Unknown sampling strategy: {}", strategy);
+                            exit(1);
+                        });
+                        let samples = match (run_matches.value_of("samples"), defaults.samples) {
+                            (None, Some(samples)) => samples,
+                            (samples, _) => parse_number_arg(samples, "samples"),
+                        };
+                        let seed = match run_matches.value_of("seed") {
+                            Some(seed) => parse_number_arg(Some(seed), "seed"),
+                            None => rand::random::<u64>(),
+                        };
+                        println!("Sampling {} jobs using seed: {}", samples, seed);
+
+                        match generate_space_for_params(params, declared_schema.as_ref())
+                            .and_then(|space| sample_params(&space, strategy, samples, seed))
+                        {
+                            Ok(combos) => combos,
+                            Err(err) => exit_on_param_error(err, "sampling"),
+                        }
+                    } else {
+                        match format {
+                            "pairs" => match generate_map_for_params(
+                                params,
+                                ParamFormat::Pairs,
+                                declared_schema.as_ref(),
+                            ) {
+                                Ok((param_map, num_of_pairs)) => {
+                                    generate_param_pairs(param_map, num_of_pairs)
                                 }
-                            }
-                            Err(err) => match err {
-                                ParamError::InvalidParam => {
+                                Err(ParamError::InvalidParam) => {
                                     println!("Error! - Number of values across parameters must be equal when in 'pairs' format");
                                     exit(1);
                                 }
+                                Err(err) => exit_on_param_error(err, "pairs"),
                             },
-                        },
-                        "matrix" => match generate_map_for_params(params, ParamFormat::Matrix) {
-                            Ok((param_map, _)) => {
-                                let repo = match Repository::discover(".") {
-                                    Ok(repo) => repo,
-                                    Err(e) => {
-                                        eprintln!("Failed to open a Git repository: {}", e);
-                                        std::process::exit(1);
-                                    }
-                                };
-
-                                let combos = generate_param_combos(param_map);
-
-                                let author = get_current_author();
-                                let full_path = get_full_source_path(&repo, filename);
-                                let origin_url = get_repository_url(&repo);
-                                let current_branch = get_current_branch(&repo);
-                                let mut batch =
-                                    Batch::new(&author, &full_path, &origin_url, &current_branch);
-                                for combo in combos {
-                                    let job = Job::new(combo);
-                                    batch.jobs.push(job.clone());
-                                }
-
-                                println!("Batch has {} jobs", batch.jobs.len());
-
-                                let json = serde_json::to_string(&batch)
-                                    .unwrap_or_else(|_| "".to_string());
-                                match post_batch("http://127.0.0.1:8000/batch", json) {
-                                    Ok(mut body) => {
-                                        if let Ok(response) = body.text() {
-                                            println!("Successfully posted job batch to gateway with response: {}", response);
-                                        }
-                                    }
-                                    Err(err) => println!(
-                                        "Error! - Failed to post job batch to gateway: {}",
-                                        err
-                                    ),
+                            "matrix" => {
+                                match generate_map_for_params(
+                                    params,
+                                    ParamFormat::Matrix,
+                                    declared_schema.as_ref(),
+                                ) {
+                                    Ok((param_map, _)) => generate_param_combos(param_map),
+                                    Err(err) => exit_on_param_error(err, "matrix"),
                                 }
                             }
-                            Err(err) => match err {
-                                ParamError::InvalidParam => {
-                                    println!("Error! - Invalid parameter for 'matrix' format");
-                                    exit(1);
-                                }
-                            },
-                        },
-                        _ => {
-                            println!("Error! - Unknown Error")
+                            _ => {
+                                println!("Error! - Unknown Error");
+                                exit(1);
+                            }
+                        }
+                    };
+
+                    let content_ids = run_matches.is_present("content-ids")
+                        || defaults.content_ids.unwrap_or(false);
+                    let mut batch = build_batch(
+                        filename,
+                        declared_schema,
+                        combos,
+                        &wheres,
+                        &excludes,
+                        content_ids,
+                    );
+                    if let Some(priority) = run_matches
+                        .value_of("priority")
+                        .or(defaults.priority.as_deref())
+                    {
+                        batch.priority = parse_priority(priority);
+                    }
+                    if let Some(after) = run_matches.values_of("after") {
+                        batch.depends_on = after.map(|batch_id| batch_id.to_string()).collect();
+                    }
+
+                    let output = run_matches.value_of("output");
+                    if run_matches.is_present("local") {
+                        let concurrency = match run_matches.value_of("concurrency") {
+                            Some(concurrency) => parse_number_arg(Some(concurrency), "concurrency"),
+                            None => std::thread::available_parallelism()
+                                .map(|cpus| cpus.get())
+                                .unwrap_or(1),
+                        };
+                        run_local(&batch, run_matches.value_of("run-dir"), concurrency);
+                    } else if run_matches.is_present("dry-run") || output.is_some() {
+                        match output {
+                            Some(_) => write_batch_json(
+                                &batch,
+                                run_matches.value_of("output-file").unwrap_or_default(),
+                            ),
+                            None => print_batch_preview(&batch),
+                        }
+
+                        if run_matches.is_present("show-manifest") {
+                            print_manifest(&batch);
                         }
+                    } else {
+                        submit_batch(&batch, &settings);
+                    }
+                }
+            }
+        }
+    }
+
+    // Handle SUBMIT command logic
+    if let Some(submit_matches) = app.subcommand_matches("submit") {
+        if let Some(path) = submit_matches.value_of("batch") {
+            let json = std::fs::read_to_string(path).unwrap_or_else(|e| {
+                println!("Error! - Unable to read {}: {}", path, e);
+                exit(1);
+            });
+
+            match Batch::from_json(&json) {
+                Ok(mut batch) => {
+                    if let Some(priority) = submit_matches.value_of("priority") {
+                        batch.priority = parse_priority(priority);
                     }
+                    submit_batch(&batch, &settings)
+                }
+                Err(err) => {
+                    println!("Error! - {} is not a valid batch: {}", path, err);
+                    exit(1);
+                }
+            }
+        }
+    }
+
+    // Handle STATUS command logic
+    if let Some(status_matches) = app.subcommand_matches("status") {
+        let batch_id = status_matches.value_of("batch_id").unwrap_or_default();
+        match get_json(
+            &settings.url(&format!("/batch/{}", batch_id)),
+            settings.token.as_deref(),
+        ) {
+            Ok(status) => {
+                for key in &[
+                    "batch_id",
+                    "state",
+                    "owner",
+                    "team",
+                    "priority",
+                    "source_file",
+                    "depends_on",
+                    "jobs",
+                ] {
+                    match &status[*key] {
+                        Value::Null => {}
+                        Value::Array(values) if values.is_empty() => {}
+                        Value::String(value) => println!("{}: {}", key, value),
+                        value => println!("{}: {}", key, value),
+                    }
+                }
+            }
+            Err(err) => {
+                println!(
+                    "Error! - Unable to get the status of batch {}: {}",
+                    batch_id, err
+                );
+                exit(1);
+            }
+        }
+    }
+
+    // Handle RESULTS command logic
+    if let Some(results_matches) = app.subcommand_matches("results") {
+        let batch_id = results_matches.value_of("batch_id").unwrap_or_default();
+        let format = results_matches.value_of("format").unwrap_or("csv");
+        let output_file = results_matches.value_of("output_file");
+        let response = get_json(
+            &settings.url(&format!("/batch/{}/results", batch_id)),
+            settings.token.as_deref(),
+        )
+        .unwrap_or_else(|err| {
+            println!(
+                "Error! - Unable to get the results of batch {}: {}",
+                batch_id, err
+            );
+            exit(1);
+        });
+        let table = results::ResultsTable::from_response(&response);
+
+        let written = match (format, output_file) {
+            ("parquet", None) => {
+                println!("Error! - Parquet results must be written to a file, pass --output-file");
+                exit(1);
+            }
+            ("parquet", Some(path)) => File::create(path)
+                .map_err(|e| e.to_string())
+                .and_then(|file| table.write_parquet(file).map_err(|e| e.to_string())),
+            (format, Some(path)) => File::create(path)
+                .and_then(|mut file| write_results(&table, format, &mut file))
+                .map_err(|e| e.to_string()),
+            (format, None) => {
+                write_results(&table, format, &mut std::io::stdout()).map_err(|e| e.to_string())
+            }
+        };
+        if let Err(err) = written {
+            println!("Error! - Unable to write results: {}", err);
+            exit(1);
+        }
+        if let Some(path) = output_file {
+            println!(
+                "Wrote results for {} of {} jobs to {}",
+                response["completed"],
+                table.rows.len(),
+                path
+            );
+        }
+    }
+
+    // Handle ARTIFACTS command logic
+    if let Some(artifacts_matches) = app.subcommand_matches("artifacts") {
+        if let Some((command, matches)) = artifacts_matches.subcommand() {
+            let batch_id = matches.value_of("batch_id").unwrap_or_default();
+            let mut uri = settings.url(&format!("/batch/{}/artifacts", batch_id));
+            if let Some(job_id) = matches.value_of("job") {
+                uri = format!("{}?job={}", uri, job_id);
+            }
+            let listed = match get_json(&uri, settings.token.as_deref()) {
+                Ok(response) => artifacts::from_response(&response),
+                Err(err) => {
+                    println!(
+                        "Error! - Unable to list the artifacts of batch {}: {}",
+                        batch_id, err
+                    );
+                    exit(1);
+                }
+            };
+
+            if command == "list" {
+                if listed.is_empty() {
+                    println!("No artifacts uploaded for batch {}", batch_id);
+                }
+                for artifact in &listed {
+                    println!(
+                        "{}\t{:>12}\t{}",
+                        artifact.job_id, artifact.size, artifact.name
+                    );
+                }
+            } else {
+                let output_dir = matches
+                    .value_of("output_dir")
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| Path::new("artifacts").join(batch_id));
+                let mut failed = 0;
+                for artifact in &listed {
+                    let path = match artifacts::local_path(&output_dir, artifact) {
+                        Some(path) => path,
+                        None => {
+                            println!(
+                                "Skipping {}/{}, the name isn't a relative path",
+                                artifact.job_id, artifact.name
+                            );
+                            failed += 1;
+                            continue;
+                        }
+                    };
+                    match artifacts::download(artifact, &path) {
+                        Ok(()) => println!("Downloaded {}", path.display()),
+                        Err(err) => {
+                            println!("Unable to download {}: {}", path.display(), err);
+                            failed += 1;
+                        }
+                    }
+                }
+                println!(
+                    "Downloaded {} of {} artifacts to {}",
+                    listed.len() - failed,
+                    listed.len(),
+                    output_dir.display()
+                );
+                if failed > 0 {
+                    exit(1);
+                }
+            }
+        }
+    }
+
+    // Handle LOGS command logic
+    if let Some(logs_matches) = app.subcommand_matches("logs") {
+        let batch_id = logs_matches.value_of("batch_id").unwrap_or_default();
+        let job_id = match logs_matches.value_of("job") {
+            Some(job_id) => job_id.to_string(),
+            None => {
+                let index = logs_matches
+                    .value_of("index")
+                    .map(|index| parse_number_arg::<usize>(Some(index), "index"));
+                resolve_job_id(&settings, batch_id, index)
+            }
+        };
+
+        let mut uri = settings.url(&format!("/batch/{}/jobs/{}/logs", batch_id, job_id));
+        uri = format!("{}?follow={}", uri, logs_matches.is_present("follow"));
+        if let Some(tail) = logs_matches.value_of("tail") {
+            uri = format!(
+                "{}&tail={}",
+                uri,
+                parse_number_arg::<u64>(Some(tail), "tail")
+            );
+        }
+        if let Err(err) = stream_text(&uri, settings.token.as_deref()) {
+            println!("Error! - Unable to get the logs of job {}: {}", job_id, err);
+            exit(1);
+        }
+    }
+
+    // Handle LOGIN command logic
+    if let Some(login_matches) = app.subcommand_matches("login") {
+        let token = match login_matches.value_of("token") {
+            Some(token) => token.to_string(),
+            None => {
+                println!("Paste the API token for {}:", settings.gateway);
+                let mut token = String::new();
+                if let Err(e) = std::io::stdin().read_line(&mut token) {
+                    println!("Error! - Unable to read token: {}", e);
+                    exit(1);
+                }
+                token.trim().to_string()
+            }
+        };
+
+        match get_principal(&settings.url("/whoami"), &token) {
+            Ok(principal) => {
+                if let Err(err) = config.set(&settings.profile_name, "token", &token) {
+                    println!("Error! - {}", err);
+                    exit(1);
+                }
+                save_config(&config);
+                println!(
+                    "Logged in to {} as '{}' using profile '{}'",
+                    settings.gateway, principal, settings.profile_name
+                );
+            }
+            Err(err) => {
+                println!("Error! - Login failed: {}", err);
+                exit(1);
+            }
+        }
+    }
+
+    // Handle CONFIG command logic
+    if let Some(config_matches) = app.subcommand_matches("config") {
+        let profile_name = settings.profile_name.clone();
+        match config_matches.subcommand() {
+            Some(("list", _)) => {
+                if config.profiles.is_empty() {
+                    println!("No profiles configured");
+                }
+                for name in config.profiles.keys() {
+                    let marker = if *name == profile_name { "*" } else { " " };
+                    println!("{} {}", marker, name);
+                }
+            }
+            Some(("show", _)) => {
+                println!("profile: {}", settings.profile_name);
+                println!("gateway: {}", settings.gateway);
+                println!(
+                    "token: {}",
+                    if settings.profile.token.is_some() {
+                        "<set>"
+                    } else {
+                        "<not set>"
+                    }
+                );
+                let defaults = toml::to_string(&settings.profile.defaults).unwrap_or_default();
+                for line in defaults.lines() {
+                    println!("defaults.{}", line.replacen(" = ", ": ", 1));
+                }
+            }
+            Some(("use", use_matches)) => {
+                let name = use_matches.value_of("name").unwrap_or_default();
+                config.profiles.entry(name.to_string()).or_default();
+                config.current_profile = Some(name.to_string());
+                save_config(&config);
+                println!("Now using profile '{}'", name);
+            }
+            Some(("set", set_matches)) => {
+                let key = set_matches.value_of("key").unwrap_or_default();
+                let value = set_matches.value_of("value").unwrap_or_default();
+                if let Err(err) = config.set(&profile_name, key, value) {
+                    println!("Error! - {}", err);
+                    exit(1);
+                }
+                save_config(&config);
+                println!("Set {} on profile '{}'", key, profile_name);
+            }
+            Some(("unset", unset_matches)) => {
+                let key = unset_matches.value_of("key").unwrap_or_default();
+                if let Err(err) = config.unset(&profile_name, key) {
+                    println!("Error! - {}", err);
+                    exit(1);
+                }
+                save_config(&config);
+                println!("Unset {} on profile '{}'", key, profile_name);
+            }
+            Some(("remove", remove_matches)) => {
+                let name = remove_matches.value_of("name").unwrap_or_default();
+                if config.profiles.remove(name).is_none() {
+                    println!("Error! - No profile named '{}'", name);
+                    exit(1);
+                }
+                if config.current_profile.as_deref() == Some(name) {
+                    config.current_profile = None;
+                }
+                save_config(&config);
+                println!("Removed profile '{}'", name);
+            }
+            _ => println!("Run 'rft-client config --help' to see available commands"),
+        }
+    }
+
+    // Handle PARAMS command logic
+    if let Some(params_matches) = app.subcommand_matches("params") {
+        if let Some(describe_matches) = params_matches.subcommand_matches("describe") {
+            if let Some(filename) = describe_matches.value_of("file") {
+                match load_declared_schema(filename) {
+                    Some(schema) => {
+                        println!(
+                            "Parameters declared in {}:",
+                            project::project_file_for(filename).display()
+                        );
+                        project::describe_schema(&schema);
+                    }
+                    None => println!(
+                        "No {} found beside {}, parameter types will be inferred from -p values",
+                        project::PROJECT_FILE,
+                        filename
+                    ),
                 }
             }
         }
     }
 }
 
+fn build_batch(
+    filename: &str,
+    declared_schema: Option<ParamSchema>,
+    mut combos: Vec<HashMap<String, Value>>,
+    wheres: &[Filter],
+    excludes: &[Filter],
+    content_ids: bool,
+) -> Batch {
+    if let Some(schema) = &declared_schema {
+        for combo in combos.iter_mut() {
+            schema.apply_defaults(combo);
+        }
+    }
+
+    if !wheres.is_empty() || !excludes.is_empty() {
+        let total = combos.len();
+        combos = match apply_filters(combos, wheres, excludes) {
+            Ok((kept, dropped)) => {
+                println!(
+                    "Filters dropped {} of {} parameter combinations",
+                    dropped, total
+                );
+                kept
+            }
+            Err(err) => {
+                println!("Error! - {}", err);
+                exit(1);
+            }
+        };
+    }
+
+    let (unique, duplicates) = remove_duplicate_combos(combos);
+    if duplicates > 0 {
+        println!(
+            "Warning - Removed {} duplicate parameter combinations",
+            duplicates
+        );
+    }
+    combos = unique;
+
+    if let Some(schema) = &declared_schema {
+        for combo in combos.iter() {
+            if let Err(err) = schema.validate(combo) {
+                println!(
+                    "Error! - {} (declared in {})",
+                    err,
+                    project::project_file_for(filename).display()
+                );
+                exit(1);
+            }
+        }
+    }
+
+    let repo = match Repository::discover(".") {
+        Ok(repo) => repo,
+        Err(e) => {
+            eprintln!("Failed to open a Git repository: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let author = get_current_author();
+    let full_path = get_full_source_path(&repo, filename);
+    let origin_url = get_repository_url(&repo);
+    let current_branch = get_current_branch(&repo);
+    let current_commit = get_current_commit(&repo);
+    let mut batch = Batch::new(&author, &full_path, &origin_url, &current_branch);
+    batch.commit = Some(current_commit.clone());
+    for combo in combos {
+        let job = if content_ids {
+            Job::with_content_id(&current_commit, &full_path, combo)
+        } else {
+            Job::new(combo)
+        };
+        batch.jobs.push(job.clone());
+    }
+    batch.schema = Some(declared_schema.unwrap_or_else(|| ParamSchema::infer(&batch.jobs)));
+
+    batch
+}
+
+/// Attempts made at submitting a batch before giving up on transient failures
+const SUBMIT_ATTEMPTS: u32 = 4;
+/// Responses worth retrying: the gateway is restarting, Redis is unavailable, or a previous
+/// attempt with the same idempotency key is still being handled
+const RETRY_STATUSES: [u16; 4] = [409, 502, 503, 504];
+
+fn submit_batch(batch: &Batch, settings: &Settings) {
+    println!("Batch has {} jobs", batch.jobs.len());
+
+    let json = serde_json::to_string(batch).unwrap_or_else(|_| "".to_string());
+    // Every attempt sends the same key, so a retry after a lost response returns the original
+    // response instead of queueing the batch a second time
+    let idempotency_key = format!("{:032x}", rand::random::<u128>());
+    let mut delay = Duration::from_secs(1);
+    let mut attempt = 1;
+    let response = loop {
+        let response = post_batch(
+            &settings.url("/batch"),
+            json.clone(),
+            settings.token.as_deref(),
+            &idempotency_key,
+        );
+        let retry_reason = match &response {
+            Err(err) if err.is_network() || err.kind() == isahc::error::ErrorKind::Timeout => {
+                Some(err.to_string())
+            }
+            Ok(response) if RETRY_STATUSES.contains(&response.status().as_u16()) => {
+                Some(format!("gateway responded with {}", response.status()))
+            }
+            _ => None,
+        };
+
+        match retry_reason {
+            Some(reason) if attempt < SUBMIT_ATTEMPTS => {
+                println!(
+                    "Failed to submit batch ({}), retrying in {}s",
+                    reason,
+                    delay.as_secs()
+                );
+                std::thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+            _ => break response,
+        }
+    };
+
+    match response {
+        Ok(mut response) => {
+            let body = response.text().unwrap_or_default();
+            if !response.status().is_success() {
+                println!(
+                    "Error! - Gateway rejected the batch: {}",
+                    describe_gateway_error(response.status().as_u16(), &body)
+                );
+                exit(1);
+            }
+
+            let json = serde_json::from_str::<Value>(&body).unwrap_or_default();
+            let skipped_jobs = json["skipped_jobs"].as_u64().unwrap_or(0);
+            if skipped_jobs > 0 {
+                println!("Skipped {} jobs which already succeeded", skipped_jobs);
+            }
+            match json["batch_id"].as_str() {
+                Some(batch_id) => {
+                    println!("Submitted batch {}", batch_id);
+                    if let Some(status_url) = json["status_url"].as_str() {
+                        println!("Status: {}", settings.url(status_url));
+                    }
+                    println!(
+                        "Run 'rft-client status {}' to follow its progress",
+                        batch_id
+                    );
+                }
+                None => println!("Nothing to queue, every job has already succeeded"),
+            }
+        }
+        Err(err) => {
+            println!("Error! - Failed to post job batch to gateway: {}", err);
+            exit(1);
+        }
+    }
+}
+
+/// Formats a gateway error response, i.e. `{"error": {"code": ..., "message": ..., "details": [...]}}`,
+/// falling back to the raw body for responses from older gateways or proxies
+fn describe_gateway_error(status: u16, body: &str) -> String {
+    let json = match serde_json::from_str::<Value>(body) {
+        Ok(json) => json,
+        Err(_) => return format!("HTTP {}: {}", status, body),
+    };
+
+    match &json["error"] {
+        Value::Object(error) => {
+            let mut description = format!(
+                "{} ({})",
+                error
+                    .get("message")
+                    .and_then(Value::as_str)
+                    .unwrap_or("request failed"),
+                error
+                    .get("code")
+                    .and_then(Value::as_str)
+                    .unwrap_or("unknown_error")
+            );
+            for detail in error
+                .get("details")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+            {
+                description.push_str(&format!("\n    {}", detail));
+            }
+            description
+        }
+        Value::String(message) => message.clone(),
+        _ => format!("HTTP {}: {}", status, body),
+    }
+}
+
+fn write_results(
+    table: &results::ResultsTable,
+    format: &str,
+    out: &mut dyn Write,
+) -> std::io::Result<()> {
+    match format {
+        "json" => table.write_json(out),
+        _ => table.write_csv(out),
+    }
+}
+
+fn parse_priority(priority: &str) -> Priority {
+    priority.parse().unwrap_or_else(|err| {
+        println!("Error! - {}", err);
+        exit(1);
+    })
+}
+
+fn print_batch_preview(batch: &Batch) {
+    println!("Repository: {}", batch.repository_url);
+    println!("Branch: {}", batch.branch);
+    println!("Commit: {}", batch.commit.as_deref().unwrap_or("unknown"));
+    println!("Source file: {}", batch.source_file);
+    println!("Priority: {}", batch.priority);
+    if !batch.depends_on.is_empty() {
+        println!("After: {}", batch.depends_on.join(", "));
+    }
+    println!("Jobs: {}", batch.jobs.len());
+    println!();
+
+    let mut param_names: Vec<&String> = batch
+        .jobs
+        .iter()
+        .flat_map(|job| job.params.keys())
+        .collect::<HashSet<&String>>()
+        .into_iter()
+        .collect();
+    param_names.sort();
+
+    let mut rows = vec![std::iter::once("job_id".to_string())
+        .chain(param_names.iter().map(|name| name.to_string()))
+        .collect::<Vec<String>>()];
+    for job in &batch.jobs {
+        rows.push(
+            std::iter::once(job.job_id.clone())
+                .chain(param_names.iter().map(|name| match job.params.get(*name) {
+                    Some(Value::String(value)) => value.clone(),
+                    Some(value) => value.to_string(),
+                    None => "-".to_string(),
+                }))
+                .collect(),
+        );
+    }
+
+    let widths: Vec<usize> = (0..rows[0].len())
+        .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0))
+        .collect();
+    for row in rows {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        println!("{}", cells.join("  ").trim_end());
+    }
+}
+
+/// Runs the batch with `local::run_batch`, then writes its results beside the jobs' directories
+/// as results.json, in the gateway's format, and results.csv
+fn run_local(batch: &Batch, run_dir: Option<&str>, concurrency: usize) {
+    let workdir = match Repository::discover(".")
+        .ok()
+        .and_then(|repo| repo.workdir().map(Path::to_path_buf))
+    {
+        Some(workdir) => workdir,
+        None => {
+            println!("Error! - Local runs must be started from a git repository's working tree");
+            exit(1);
+        }
+    };
+    let run_dir = local::batch_run_dir(run_dir, batch);
+    println!(
+        "Running {} jobs locally, {} at a time, in {}",
+        batch.jobs.len(),
+        concurrency,
+        run_dir.display()
+    );
+
+    let outcomes = local::run_batch(batch, &workdir, &run_dir, concurrency);
+    let results = local::results(batch, &run_dir, &outcomes);
+    let json_path = run_dir.join("results.json");
+    let csv_path = run_dir.join("results.csv");
+    let written = std::fs::create_dir_all(&run_dir)
+        .and_then(|_| {
+            std::fs::write(
+                &json_path,
+                serde_json::to_string_pretty(&results).unwrap_or_default(),
+            )
+        })
+        .and_then(|_| File::create(&csv_path))
+        .and_then(|mut file| results::ResultsTable::from_response(&results).write_csv(&mut file));
+    if let Err(err) = written {
+        println!("Error! - Unable to write results: {}", err);
+        exit(1);
+    }
+
+    let failed = outcomes
+        .iter()
+        .filter(|outcome| !outcome.succeeded())
+        .count();
+    println!(
+        "{} of {} jobs succeeded. Results: {}",
+        outcomes.len() - failed,
+        outcomes.len(),
+        csv_path.display()
+    );
+    if failed > 0 {
+        println!(
+            "Error! - {} jobs failed, see their {} files in {}",
+            failed,
+            rft_core::local::LOG_FILE,
+            run_dir.display()
+        );
+        exit(1);
+    }
+}
+
+fn print_manifest(batch: &Batch) {
+    let manifest = rft_core::manifest::indexed_job(batch, None, None);
+    println!();
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&manifest).unwrap_or_default()
+    );
+}
+
+fn write_batch_json(batch: &Batch, path: &str) {
+    let json = serde_json::to_string_pretty(batch).unwrap_or_default();
+    if let Err(e) = std::fs::write(path, json) {
+        println!("Error! - Unable to write batch to {}: {}", path, e);
+        exit(1);
+    }
+
+    println!(
+        "Wrote batch with {} jobs to {}. Submit it with: rft-client submit {}",
+        batch.jobs.len(),
+        path,
+        path
+    );
+}
+
+fn save_config(config: &config::Config) {
+    if let Err(err) = config.save() {
+        println!("Error! - {}", err);
+        exit(1);
+    }
+}
+
+fn parse_filters(expressions: Option<Values>) -> Vec<Filter> {
+    expressions
+        .map(|values| {
+            values
+                .map(|expression| {
+                    Filter::parse(expression).unwrap_or_else(|err| {
+                        println!("Error! - Invalid filter: {}", err);
+                        exit(1);
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn load_declared_schema(filename: &str) -> Option<ParamSchema> {
+    match project::load_schema(filename) {
+        Ok(schema) => schema,
+        Err(err) => {
+            println!("Error! - {}", err);
+            exit(1);
+        }
+    }
+}
+
+fn parse_number_arg<T: std::str::FromStr>(value: Option<&str>, name: &str) -> T {
+    match value.map(|v| v.parse::<T>()) {
+        Some(Ok(number)) => number,
+        _ => {
+            println!("Error! - '{}' must be a positive whole number", name);
+            exit(1);
+        }
+    }
+}
+
+fn exit_on_param_error(err: ParamError, format: &str) -> ! {
+    match err {
+        ParamError::InvalidParam => {
+            println!("Error! - Invalid parameter for '{}' format", format)
+        }
+        ParamError::RangeNotSupported(key) => println!(
+            "Error! - Parameter '{}' is a range, ranges require a sampling --strategy",
+            key
+        ),
+        ParamError::TooManyDimensions(max) => println!(
+            "Error! - The '{}' strategy supports at most {} parameters",
+            format, max
+        ),
+    }
+
+    exit(1);
+}
+
 fn get_current_author() -> String {
     let gitconfig = Config::open(
         &Config::find_global()
@@ -254,66 +1305,124 @@ fn get_current_branch(repo: &Repository) -> String {
     branch.to_string()
 }
 
-fn post_batch(uri: &str, batch_json: String) -> Result<Response<Body>, Error> {
+fn get_current_commit(repo: &Repository) -> String {
+    match repo.head().and_then(|head| head.peel_to_commit()) {
+        Ok(commit) => commit.id().to_string(),
+        Err(e) => {
+            eprintln!("Unable to find the current commit: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn post_batch(
+    uri: &str,
+    batch_json: String,
+    token: Option<&str>,
+    idempotency_key: &str,
+) -> Result<Response<Body>, Error> {
     let client = HttpClient::new()?;
 
-    let request = Request::post(uri)
+    let mut request = Request::post(uri)
         .header("Content-Type", "application/json")
-        .body(batch_json)?;
+        .header("Idempotency-Key", idempotency_key);
+    if let Some(token) = token {
+        request = request.header("Authorization", format!("Bearer {}", token));
+    }
 
-    client.send(request)
+    client.send(request.body(batch_json)?)
 }
 
-fn generate_param_combos(params: HashMap<String, Vec<String>>) -> Vec<HashMap<String, String>> {
-    let mut combos = Vec::<HashMap<String, String>>::new();
-    for (key, values) in params {
-        let mut new_combos = Vec::<HashMap<String, String>>::new();
-        for val in values {
-            if combos.is_empty() {
-                let mut temp = HashMap::new();
-                temp.insert(key.clone(), val);
-                new_combos.push(temp);
-            } else {
-                for mut combo in combos.clone() {
-                    combo.insert(key.clone(), val.clone());
-                    new_combos.push(combo);
-                }
-            }
+/// Asks the gateway who a token belongs to
+fn get_principal(uri: &str, token: &str) -> Result<String, String> {
+    let json = get_json(uri, Some(token))?;
+    json["principal"]
+        .as_str()
+        .map(|p| p.to_string())
+        .ok_or_else(|| format!("unexpected response from gateway: {}", json))
+}
+
+/// Finds the ID of the job at `index` in a batch, or of its only job if no index is given
+fn resolve_job_id(settings: &Settings, batch_id: &str, index: Option<usize>) -> String {
+    let response = get_json(
+        &settings.url(&format!("/batch/{}/jobs", batch_id)),
+        settings.token.as_deref(),
+    )
+    .unwrap_or_else(|err| {
+        println!(
+            "Error! - Unable to get the jobs of batch {}: {}",
+            batch_id, err
+        );
+        exit(1);
+    });
+    let job_ids: Vec<&str> = response["jobs"]
+        .as_array()
+        .map(|jobs| {
+            jobs.iter()
+                .filter_map(|job| job["job_id"].as_str())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let job_id = match index {
+        Some(index) => job_ids.get(index).copied(),
+        None if job_ids.len() == 1 => job_ids.first().copied(),
+        None => {
+            println!(
+                "Error! - Batch {} has {} jobs, choose one with --job or --index",
+                batch_id,
+                job_ids.len()
+            );
+            exit(1);
+        }
+    };
+    match job_id {
+        Some(job_id) => job_id.to_string(),
+        None => {
+            println!(
+                "Error! - Batch {} has {} jobs, --index must be below that",
+                batch_id,
+                job_ids.len()
+            );
+            exit(1);
         }
-        combos = new_combos;
     }
-
-    combos
 }
 
-fn generate_map_for_params(
-    params: Values,
-    format: ParamFormat,
-) -> Result<(HashMap<String, Vec<String>>, usize), ParamError> {
-    let mut map: HashMap<String, Vec<String>> = HashMap::new();
-    let mut param_length = 0;
-    for param in params {
-        let (key, val) = param.split_once("=").unwrap();
-        let values: Vec<String> = val.split(',').map(|x| x.to_string()).collect();
-
-        // Ensure parameter has at least one value
-        if values.is_empty() {
-            return Err(ParamError::InvalidParam);
-        }
+/// Sends a GET request to the gateway and copies the response to stdout as it arrives
+fn stream_text(uri: &str, token: Option<&str>) -> Result<(), String> {
+    let client = HttpClient::new().map_err(|e| e.to_string())?;
+    let mut request = Request::get(uri);
+    if let Some(token) = token {
+        request = request.header("Authorization", format!("Bearer {}", token));
+    }
+    let request = request.body(()).map_err(|e| e.to_string())?;
 
-        // Ensure all parameters are of the same length
-        if format == ParamFormat::Pairs {
-            if param_length != 0 {
-                if values.len() != param_length {
-                    return Err(ParamError::InvalidParam);
-                }
-            } else {
-                param_length = values.len();
-            }
-        }
+    let mut response = client.send(request).map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        let body = response.text().map_err(|e| e.to_string())?;
+        return Err(describe_gateway_error(response.status().as_u16(), &body));
+    }
+    std::io::copy(response.body_mut(), &mut std::io::stdout()).map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+/// Sends a GET request to the gateway and parses the JSON response
+fn get_json(uri: &str, token: Option<&str>) -> Result<Value, String> {
+    let client = HttpClient::new().map_err(|e| e.to_string())?;
+    let mut request = Request::get(uri);
+    if let Some(token) = token {
+        request = request.header("Authorization", format!("Bearer {}", token));
+    }
+    let request = request.body(()).map_err(|e| e.to_string())?;
 
-        map.insert(key.to_string(), values);
+    let mut response = client.send(request).map_err(|e| e.to_string())?;
+    let body = response.text().map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(describe_gateway_error(response.status().as_u16(), &body));
     }
 
-    Ok((map, param_length))
+    serde_json::from_str::<Value>(&body)
+        .map_err(|_| format!("unexpected response from gateway: {}", body))
 }
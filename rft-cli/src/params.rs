@@ -0,0 +1,211 @@
+use clap::Values;
+use rft_core::job::canonical_params;
+use rft_core::schema::ParamSchema;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug)]
+pub enum ParamError {
+    InvalidParam,
+    RangeNotSupported(String),
+    TooManyDimensions(usize),
+}
+
+#[derive(PartialEq, Eq)]
+pub enum ParamFormat {
+    Pairs,
+    Matrix,
+}
+
+pub fn generate_param_combos(params: HashMap<String, Vec<Value>>) -> Vec<HashMap<String, Value>> {
+    let mut combos = Vec::<HashMap<String, Value>>::new();
+    for (key, values) in params {
+        let mut new_combos = Vec::<HashMap<String, Value>>::new();
+        for val in values {
+            if combos.is_empty() {
+                let mut temp = HashMap::new();
+                temp.insert(key.clone(), val);
+                new_combos.push(temp);
+            } else {
+                for mut combo in combos.clone() {
+                    combo.insert(key.clone(), val.clone());
+                    new_combos.push(combo);
+                }
+            }
+        }
+        combos = new_combos;
+    }
+
+    combos
+}
+
+pub fn generate_param_pairs(
+    params: HashMap<String, Vec<Value>>,
+    num_of_pairs: usize,
+) -> Vec<HashMap<String, Value>> {
+    let mut pairs = Vec::<HashMap<String, Value>>::new();
+    for i in 0..num_of_pairs {
+        let mut single_job_params: HashMap<String, Value> = HashMap::new();
+        for (key, val) in &params {
+            single_job_params.insert(key.clone(), val.get(i).unwrap().clone());
+        }
+
+        pairs.push(single_job_params);
+    }
+
+    pairs
+}
+
+pub fn generate_map_for_params(
+    params: Values,
+    format: ParamFormat,
+    schema: Option<&ParamSchema>,
+) -> Result<(HashMap<String, Vec<Value>>, usize), ParamError> {
+    let mut map: HashMap<String, Vec<Value>> = HashMap::new();
+    let mut param_length = 0;
+    for param in params {
+        let (key, val) = param.split_once("=").ok_or(ParamError::InvalidParam)?;
+
+        // Ranges only make sense when sampling, a grid needs explicit values
+        if crate::sampling::parse_range(val).is_some() {
+            return Err(ParamError::RangeNotSupported(key.to_string()));
+        }
+
+        let values: Vec<Value> = split_values(val)
+            .iter()
+            .map(|x| parse_value(schema, key, x))
+            .collect();
+
+        // Ensure parameter has at least one value
+        if values.is_empty() {
+            return Err(ParamError::InvalidParam);
+        }
+
+        // Ensure all parameters are of the same length
+        if format == ParamFormat::Pairs {
+            if param_length != 0 {
+                if values.len() != param_length {
+                    return Err(ParamError::InvalidParam);
+                }
+            } else {
+                param_length = values.len();
+            }
+        }
+
+        map.insert(key.to_string(), values);
+    }
+
+    Ok((map, param_length))
+}
+
+/// Removes repeated parameter combinations, keeping the first occurrence of each.
+/// Returns the unique combinations and how many duplicates were removed.
+pub fn remove_duplicate_combos(
+    combos: Vec<HashMap<String, Value>>,
+) -> (Vec<HashMap<String, Value>>, usize) {
+    let total = combos.len();
+    let mut seen = HashSet::new();
+    let unique: Vec<HashMap<String, Value>> = combos
+        .into_iter()
+        .filter(|combo| seen.insert(canonical_params(combo)))
+        .collect();
+
+    let duplicates = total - unique.len();
+    (unique, duplicates)
+}
+
+/// Splits a comma separated list of values, ignoring commas nested inside
+/// JSON lists, objects or quoted strings, i.e. `[1,2],[3,4]` is two values
+pub fn split_values(values: &str) -> Vec<String> {
+    let mut split = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut in_quotes = false;
+    let mut escaped = false;
+    for c in values.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            '[' | '{' if !in_quotes => depth += 1,
+            ']' | '}' if !in_quotes => depth -= 1,
+            ',' if !in_quotes && depth == 0 => {
+                split.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    split.push(current);
+
+    split
+}
+
+/// Infers a typed value from CLI input. Anything that parses as a JSON number, boolean,
+/// list, object or quoted string is used as-is, everything else is treated as a plain string.
+pub fn infer_value(value: &str) -> Value {
+    match serde_json::from_str::<Value>(value) {
+        Ok(Value::Null) | Err(_) => Value::String(value.to_string()),
+        Ok(typed) => typed,
+    }
+}
+
+/// Reads a CLI value as the type declared for its param, values of undeclared params, or
+/// that aren't valid for the declared type, are inferred and left for the schema to reject
+pub fn parse_value(schema: Option<&ParamSchema>, name: &str, value: &str) -> Value {
+    schema
+        .and_then(|schema| schema.get(name))
+        .and_then(|spec| spec.param_type.coerce(value))
+        .unwrap_or_else(|| infer_value(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn splits_values_outside_nested_json() {
+        assert_eq!(split_values("1980,1990"), vec!["1980", "1990"]);
+        assert_eq!(
+            split_values(r#"[1,2],{"a":1,"b":2},"x,y""#),
+            vec!["[1,2]", r#"{"a":1,"b":2}"#, r#""x,y""#]
+        );
+    }
+
+    #[test]
+    fn infers_value_types() {
+        assert_eq!(infer_value("1980"), json!(1980));
+        assert_eq!(infer_value("0.5"), json!(0.5));
+        assert_eq!(infer_value("true"), json!(true));
+        assert_eq!(infer_value("[1,2]"), json!([1, 2]));
+        assert_eq!(infer_value(r#""1980""#), json!("1980"));
+        assert_eq!(infer_value("us-east-1"), json!("us-east-1"));
+        assert_eq!(infer_value("null"), json!("null"));
+    }
+
+    #[test]
+    fn parses_values_as_declared_types() {
+        use rft_core::schema::{ParamSpec, ParamType};
+
+        let schema = ParamSchema {
+            params: vec![ParamSpec::new("version", ParamType::String)],
+            strict: false,
+        };
+        assert_eq!(parse_value(Some(&schema), "version", "1.0"), json!("1.0"));
+        assert_eq!(parse_value(Some(&schema), "seed", "1.0"), json!(1.0));
+        assert_eq!(parse_value(None, "version", "123456"), json!(123456));
+    }
+
+    #[test]
+    fn removes_duplicate_combos() {
+        let mut values = HashMap::new();
+        values.insert("start_date".to_string(), vec![json!(1980), json!(1980)]);
+        values.insert("end_date".to_string(), vec![json!(2020), json!(2020)]);
+
+        let (unique, duplicates) = remove_duplicate_combos(generate_param_pairs(values, 2));
+        assert_eq!(unique.len(), 1);
+        assert_eq!(duplicates, 1);
+    }
+}
@@ -0,0 +1,175 @@
+use rft_core::schema::{ParamSchema, ParamSpec, ParamType};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+pub const PROJECT_FILE: &str = "rft.toml";
+
+#[derive(Debug)]
+pub enum ProjectError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Schema(PathBuf, Box<rft_core::schema::Error>),
+}
+
+impl fmt::Display for ProjectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProjectError::Read(path, err) => {
+                write!(f, "Unable to read {}: {}", path.display(), err)
+            }
+            ProjectError::Parse(path, err) => {
+                write!(f, "Unable to parse {}: {}", path.display(), err)
+            }
+            ProjectError::Schema(path, err) => {
+                write!(f, "{} (declared in {})", err, path.display())
+            }
+        }
+    }
+}
+
+/// The rft.toml file checked in beside a source file:
+/// [params.start_date]
+/// type = "integer"
+/// required = true
+/// description = "First year of data to process"
+///
+/// [params.region]
+/// type = "string"
+/// default = "us-east-1"
+/// allowed = ["us-east-1", "eu-west-1"]
+#[derive(Debug, Deserialize)]
+struct ProjectFile {
+    #[serde(default)]
+    params: BTreeMap<String, ParamDeclaration>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ParamDeclaration {
+    #[serde(rename = "type")]
+    param_type: ParamType,
+    default: Option<toml::Value>,
+    allowed: Option<Vec<toml::Value>>,
+    #[serde(default)]
+    required: bool,
+    description: Option<String>,
+}
+
+/// Returns the path of the rft.toml that applies to a source file
+pub fn project_file_for(source_file: &str) -> PathBuf {
+    Path::new(source_file)
+        .parent()
+        .unwrap_or_else(|| Path::new(""))
+        .join(PROJECT_FILE)
+}
+
+/// Loads the param schema declared beside a source file, if there is one
+pub fn load_schema(source_file: &str) -> Result<Option<ParamSchema>, ProjectError> {
+    let path = project_file_for(source_file);
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let contents = fs::read_to_string(&path).map_err(|e| ProjectError::Read(path.clone(), e))?;
+    let project: ProjectFile =
+        toml::from_str(&contents).map_err(|e| ProjectError::Parse(path.clone(), e))?;
+
+    let params = project
+        .params
+        .into_iter()
+        .map(|(name, declaration)| ParamSpec {
+            name,
+            param_type: declaration.param_type,
+            default: declaration.default.map(to_json),
+            allowed: declaration
+                .allowed
+                .map(|values| values.into_iter().map(to_json).collect()),
+            required: declaration.required,
+            description: declaration.description,
+        })
+        .collect();
+
+    let schema = ParamSchema {
+        params,
+        strict: true,
+    };
+    schema
+        .check_defaults()
+        .map_err(|e| ProjectError::Schema(path, Box::new(e)))?;
+
+    Ok(Some(schema))
+}
+
+fn to_json(value: toml::Value) -> serde_json::Value {
+    serde_json::to_value(value).unwrap_or(serde_json::Value::Null)
+}
+
+/// Prints a declared schema as a human readable table
+pub fn describe_schema(schema: &ParamSchema) {
+    let width = schema
+        .params
+        .iter()
+        .map(|spec| spec.name.len())
+        .max()
+        .unwrap_or(0);
+
+    for spec in &schema.params {
+        let mut details = vec![spec.param_type.to_string()];
+        if spec.required {
+            details.push("required".to_string());
+        }
+        if let Some(default) = &spec.default {
+            details.push(format!("default: {}", default));
+        }
+        if let Some(allowed) = &spec.allowed {
+            details.push(format!(
+                "allowed: {}",
+                serde_json::Value::from(allowed.clone())
+            ));
+        }
+
+        println!(
+            "  {:width$}  {}",
+            spec.name,
+            details.join(", "),
+            width = width
+        );
+        if let Some(description) = &spec.description {
+            println!("  {:width$}  {}", "", description, width = width);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parses_project_file() {
+        let project: ProjectFile = toml::from_str(
+            r#"
+            [params.start_date]
+            type = "integer"
+            required = true
+
+            [params.region]
+            type = "string"
+            default = "us-east-1"
+            allowed = ["us-east-1", "eu-west-1"]
+            "#,
+        )
+        .expect("Should parse a valid rft.toml");
+
+        let region = &project.params["region"];
+        assert_eq!(region.param_type, ParamType::String);
+        assert_eq!(
+            region.default.clone().map(to_json),
+            Some(json!("us-east-1"))
+        );
+        assert!(project.params["start_date"].required);
+    }
+}
@@ -0,0 +1,254 @@
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::Write;
+use std::sync::Arc;
+
+use parquet::basic::{LogicalType, Repetition, Type as PhysicalType};
+use parquet::data_type::{BoolType, ByteArray, ByteArrayType, DoubleType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::types::Type;
+use serde_json::{Map, Value};
+
+/// Formats results can be exported in
+pub const FORMATS: [&str; 3] = ["csv", "json", "parquet"];
+
+/// A batch's results flattened to one row per job: the job ID, each param, then each result
+/// value. Result names which clash with a param are prefixed with `result.`
+#[derive(Debug, PartialEq)]
+pub struct ResultsTable {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+impl ResultsTable {
+    /// Builds the table from the gateway's `GET /batch/<id>/results` response
+    pub fn from_response(response: &Value) -> ResultsTable {
+        let empty = Vec::new();
+        let jobs = response["jobs"].as_array().unwrap_or(&empty);
+        let names = |field: &str| {
+            jobs.iter()
+                .filter_map(|job| job[field].as_object())
+                .flat_map(|values| values.keys().cloned())
+                .collect::<BTreeSet<String>>()
+        };
+        let params = names("params");
+        let results = names("result");
+
+        let mut columns = vec!["job_id".to_string()];
+        columns.extend(params.iter().cloned());
+        columns.extend(results.iter().map(|name| {
+            if name == "job_id" || params.contains(name) {
+                format!("result.{}", name)
+            } else {
+                name.clone()
+            }
+        }));
+
+        let rows = jobs
+            .iter()
+            .map(|job| {
+                let mut row = vec![job["job_id"].clone()];
+                row.extend(params.iter().map(|name| job["params"][name].clone()));
+                row.extend(results.iter().map(|name| job["result"][name].clone()));
+                row
+            })
+            .collect();
+
+        ResultsTable { columns, rows }
+    }
+
+    pub fn write_csv(&self, out: &mut dyn Write) -> std::io::Result<()> {
+        let header: Vec<String> = self.columns.iter().map(|c| csv_field(c)).collect();
+        writeln!(out, "{}", header.join(","))?;
+        for row in &self.rows {
+            let fields: Vec<String> = row
+                .iter()
+                .map(|value| match value {
+                    Value::Null => String::new(),
+                    Value::String(value) => csv_field(value),
+                    value => csv_field(&value.to_string()),
+                })
+                .collect();
+            writeln!(out, "{}", fields.join(","))?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the rows as a JSON array of objects keyed by column, omitting missing values
+    pub fn write_json(&self, out: &mut dyn Write) -> std::io::Result<()> {
+        let records: Vec<Value> = self
+            .rows
+            .iter()
+            .map(|row| {
+                let record: Map<String, Value> = self
+                    .columns
+                    .iter()
+                    .zip(row)
+                    .filter(|(_, value)| !value.is_null())
+                    .map(|(column, value)| (column.clone(), value.clone()))
+                    .collect();
+                Value::Object(record)
+            })
+            .collect();
+        serde_json::to_writer_pretty(&mut *out, &records)?;
+        writeln!(out)
+    }
+
+    /// Writes a single row group with an optional column per table column. Each column's type
+    /// is inferred from its values: integers, floats and booleans keep their type, anything
+    /// else is stored as a UTF-8 string, with objects and arrays as JSON.
+    pub fn write_parquet(&self, file: File) -> Result<(), parquet::errors::ParquetError> {
+        let kinds: Vec<ColumnKind> = (0..self.columns.len())
+            .map(|index| ColumnKind::infer(self.rows.iter().map(|row| &row[index])))
+            .collect();
+
+        let fields = self
+            .columns
+            .iter()
+            .zip(&kinds)
+            .map(|(column, kind)| {
+                let field = match kind {
+                    ColumnKind::Int => Type::primitive_type_builder(column, PhysicalType::INT64),
+                    ColumnKind::Float => Type::primitive_type_builder(column, PhysicalType::DOUBLE),
+                    ColumnKind::Bool => Type::primitive_type_builder(column, PhysicalType::BOOLEAN),
+                    ColumnKind::Text => {
+                        Type::primitive_type_builder(column, PhysicalType::BYTE_ARRAY)
+                            .with_logical_type(Some(LogicalType::String))
+                    }
+                };
+                field
+                    .with_repetition(Repetition::OPTIONAL)
+                    .build()
+                    .map(Arc::new)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let schema = Type::group_type_builder("results")
+            .with_fields(fields)
+            .build()?;
+
+        let properties = Arc::new(WriterProperties::builder().build());
+        let mut writer = SerializedFileWriter::new(file, Arc::new(schema), properties)?;
+        let mut row_group = writer.next_row_group()?;
+        let mut index = 0;
+        while let Some(mut column) = row_group.next_column()? {
+            let values: Vec<&Value> = self.rows.iter().map(|row| &row[index]).collect();
+            let levels: Vec<i16> = values.iter().map(|v| !v.is_null() as i16).collect();
+            let present = values.iter().filter(|value| !value.is_null());
+            match kinds[index] {
+                ColumnKind::Int => {
+                    let data: Vec<i64> = present.filter_map(|v| v.as_i64()).collect();
+                    column
+                        .typed::<Int64Type>()
+                        .write_batch(&data, Some(&levels), None)?;
+                }
+                ColumnKind::Float => {
+                    let data: Vec<f64> = present.filter_map(|v| v.as_f64()).collect();
+                    column
+                        .typed::<DoubleType>()
+                        .write_batch(&data, Some(&levels), None)?;
+                }
+                ColumnKind::Bool => {
+                    let data: Vec<bool> = present.filter_map(|v| v.as_bool()).collect();
+                    column
+                        .typed::<BoolType>()
+                        .write_batch(&data, Some(&levels), None)?;
+                }
+                ColumnKind::Text => {
+                    let data: Vec<ByteArray> = present
+                        .map(|value| match value {
+                            Value::String(value) => ByteArray::from(value.as_str()),
+                            value => ByteArray::from(value.to_string().as_str()),
+                        })
+                        .collect();
+                    column
+                        .typed::<ByteArrayType>()
+                        .write_batch(&data, Some(&levels), None)?;
+                }
+            }
+            column.close()?;
+            index += 1;
+        }
+        row_group.close()?;
+        writer.close()?;
+
+        Ok(())
+    }
+}
+
+/// The Parquet type a column is stored as
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ColumnKind {
+    Int,
+    Float,
+    Bool,
+    Text,
+}
+
+impl ColumnKind {
+    fn infer<'a>(values: impl Iterator<Item = &'a Value>) -> ColumnKind {
+        let mut kind = None;
+        for value in values {
+            let value_kind = match value {
+                Value::Null => continue,
+                Value::Number(n) if n.is_i64() => ColumnKind::Int,
+                Value::Number(_) => ColumnKind::Float,
+                Value::Bool(_) => ColumnKind::Bool,
+                _ => ColumnKind::Text,
+            };
+            kind = Some(match (kind, value_kind) {
+                (None, value_kind) => value_kind,
+                (Some(kind), value_kind) if kind == value_kind => kind,
+                // Columns mixing integers and floats are stored as floats
+                (Some(ColumnKind::Int), ColumnKind::Float)
+                | (Some(ColumnKind::Float), ColumnKind::Int) => ColumnKind::Float,
+                _ => ColumnKind::Text,
+            });
+        }
+
+        kind.unwrap_or(ColumnKind::Text)
+    }
+}
+
+/// Quotes a field if it contains a delimiter, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::results::ResultsTable;
+    use serde_json::json;
+
+    #[test]
+    fn joins_results_with_params() {
+        let table = ResultsTable::from_response(&json!({
+            "batch_id": "abc",
+            "jobs": [
+                {"job_id": "a", "params": {"lr": 0.1, "name": "x"}, "result": {"loss": 1.5, "lr": 0.2}},
+                {"job_id": "b", "params": {"lr": 0.2, "name": "y, z"}, "result": null},
+            ],
+        }));
+        assert_eq!(
+            table.columns,
+            vec!["job_id", "lr", "name", "loss", "result.lr"]
+        );
+
+        let mut csv = Vec::new();
+        table.write_csv(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "job_id,lr,name,loss,result.lr\na,0.1,x,1.5,0.2\nb,0.2,\"y, z\",,\n"
+        );
+
+        let file = std::env::temp_dir().join("rft-results-test.parquet");
+        table
+            .write_parquet(std::fs::File::create(&file).unwrap())
+            .expect("Should write parquet");
+    }
+}
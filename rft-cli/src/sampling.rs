@@ -0,0 +1,337 @@
+use clap::Values;
+use rand::{seq::SliceRandom, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rft_core::schema::ParamSchema;
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::params::{parse_value, split_values, ParamError};
+
+/// Sobol direction numbers (s, a, m_1..m_s) for dimensions 2 and up, taken from
+/// the Joe & Kuo `new-joe-kuo-6.21201` table. Dimension 1 is the van der Corput sequence.
+const SOBOL_DIRECTIONS: [(u32, u32, &[u32]); 15] = [
+    (1, 0, &[1]),
+    (2, 1, &[1, 3]),
+    (3, 1, &[1, 3, 1]),
+    (3, 2, &[1, 1, 1]),
+    (4, 1, &[1, 1, 3, 3]),
+    (4, 4, &[1, 3, 5, 13]),
+    (5, 2, &[1, 1, 5, 5, 17]),
+    (5, 4, &[1, 1, 5, 5, 5]),
+    (5, 7, &[1, 1, 7, 11, 19]),
+    (5, 11, &[1, 1, 5, 1, 1]),
+    (5, 13, &[1, 1, 1, 3, 11]),
+    (5, 14, &[1, 3, 5, 5, 31]),
+    (6, 1, &[1, 3, 3, 9, 7, 49]),
+    (6, 13, &[1, 1, 1, 15, 21, 21]),
+    (6, 16, &[1, 3, 1, 13, 27, 49]),
+];
+
+const SOBOL_BITS: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    Random,
+    LatinHypercube,
+    Sobol,
+}
+
+impl Strategy {
+    pub fn from_name(name: &str) -> Option<Strategy> {
+        match name {
+            "random" => Some(Strategy::Random),
+            "lhs" => Some(Strategy::LatinHypercube),
+            "sobol" => Some(Strategy::Sobol),
+            _ => None,
+        }
+    }
+}
+
+/// A single axis of the parameter space being sampled
+#[derive(Clone, Debug, PartialEq)]
+pub enum Dimension {
+    /// an inclusive numeric range written as `low..high`
+    Range { low: f64, high: f64, integer: bool },
+    /// a discrete set of values written as `a,b,c`
+    Choice(Vec<Value>),
+}
+
+impl Dimension {
+    /// Maps a point in [0, 1) onto a concrete value along this dimension
+    fn value_at(&self, u: f64) -> Value {
+        match self {
+            Dimension::Range {
+                low,
+                high,
+                integer: true,
+            } => {
+                let span = high - low + 1.0;
+                let value = (low + (u * span).floor()).min(*high);
+                Value::from(value as i64)
+            }
+            Dimension::Range { low, high, .. } => Value::from(low + u * (high - low)),
+            Dimension::Choice(values) => {
+                let index = ((u * values.len() as f64) as usize).min(values.len() - 1);
+                values[index].clone()
+            }
+        }
+    }
+}
+
+/// Parses a `low..high` range, returning `None` if the value is not a numeric range
+pub fn parse_range(value: &str) -> Option<Dimension> {
+    let (low, high) = value.split_once("..")?;
+    let (low, high) = (low.trim(), high.trim());
+
+    if let (Ok(low), Ok(high)) = (low.parse::<i64>(), high.parse::<i64>()) {
+        return Some(Dimension::Range {
+            low: low as f64,
+            high: high as f64,
+            integer: true,
+        });
+    }
+
+    match (low.parse::<f64>(), high.parse::<f64>()) {
+        (Ok(low), Ok(high)) => Some(Dimension::Range {
+            low,
+            high,
+            integer: false,
+        }),
+        _ => None,
+    }
+}
+
+/// Builds the sampling space from `key=low..high` and `key=a,b,c` parameters.
+/// Dimensions are sorted by name so a given seed always produces the same samples.
+pub fn generate_space_for_params(
+    params: Values,
+    schema: Option<&ParamSchema>,
+) -> Result<Vec<(String, Dimension)>, ParamError> {
+    let mut space = Vec::<(String, Dimension)>::new();
+    for param in params {
+        let (key, val) = param.split_once("=").ok_or(ParamError::InvalidParam)?;
+
+        let dimension = match parse_range(val) {
+            Some(Dimension::Range { low, high, .. }) if low > high => {
+                return Err(ParamError::InvalidParam)
+            }
+            Some(range) => range,
+            None => {
+                let values: Vec<Value> = split_values(val)
+                    .iter()
+                    .map(|x| parse_value(schema, key, x))
+                    .collect();
+                Dimension::Choice(values)
+            }
+        };
+
+        space.push((key.to_string(), dimension));
+    }
+
+    space.sort_by(|a, b| a.0.cmp(&b.0));
+
+    Ok(space)
+}
+
+/// Draws `samples` parameter combinations from the space using the given strategy
+pub fn sample_params(
+    space: &[(String, Dimension)],
+    strategy: Strategy,
+    samples: usize,
+    seed: u64,
+) -> Result<Vec<HashMap<String, Value>>, ParamError> {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let points = match strategy {
+        Strategy::Random => uniform_points(&mut rng, samples, space.len()),
+        Strategy::LatinHypercube => latin_hypercube_points(&mut rng, samples, space.len()),
+        Strategy::Sobol => sobol_points(&mut rng, samples, space.len())?,
+    };
+
+    Ok(points
+        .iter()
+        .map(|point| {
+            space
+                .iter()
+                .zip(point)
+                .map(|((key, dimension), u)| (key.clone(), dimension.value_at(*u)))
+                .collect()
+        })
+        .collect())
+}
+
+fn uniform_points(rng: &mut ChaCha8Rng, samples: usize, dims: usize) -> Vec<Vec<f64>> {
+    (0..samples)
+        .map(|_| (0..dims).map(|_| rng.gen::<f64>()).collect())
+        .collect()
+}
+
+/// Splits each dimension into `samples` equal strata and places exactly one point in each
+fn latin_hypercube_points(rng: &mut ChaCha8Rng, samples: usize, dims: usize) -> Vec<Vec<f64>> {
+    let mut points = vec![vec![0.0; dims]; samples];
+    for dim in 0..dims {
+        let mut strata: Vec<usize> = (0..samples).collect();
+        strata.shuffle(rng);
+        for (point, stratum) in points.iter_mut().zip(strata) {
+            point[dim] = (stratum as f64 + rng.gen::<f64>()) / samples as f64;
+        }
+    }
+
+    points
+}
+
+/// Generates a Sobol sequence using Gray code ordering, randomized with a per-dimension
+/// digital shift so different seeds give different (but equally well spread) point sets
+fn sobol_points(
+    rng: &mut ChaCha8Rng,
+    samples: usize,
+    dims: usize,
+) -> Result<Vec<Vec<f64>>, ParamError> {
+    if dims > SOBOL_DIRECTIONS.len() + 1 {
+        return Err(ParamError::TooManyDimensions(SOBOL_DIRECTIONS.len() + 1));
+    }
+
+    let directions: Vec<[u32; SOBOL_BITS]> = (0..dims).map(sobol_direction_numbers).collect();
+    let shifts: Vec<u32> = (0..dims).map(|_| rng.gen::<u32>()).collect();
+
+    let mut current = vec![0u32; dims];
+    let mut points = Vec::with_capacity(samples);
+    for i in 0..samples {
+        if i > 0 {
+            // index of the rightmost zero bit of i - 1
+            let bit = (!(i - 1)).trailing_zeros() as usize;
+            for (value, direction) in current.iter_mut().zip(&directions) {
+                *value ^= direction[bit];
+            }
+        }
+
+        points.push(
+            current
+                .iter()
+                .zip(&shifts)
+                .map(|(value, shift)| (value ^ shift) as f64 / 2f64.powi(SOBOL_BITS as i32))
+                .collect(),
+        );
+    }
+
+    Ok(points)
+}
+
+fn sobol_direction_numbers(dim: usize) -> [u32; SOBOL_BITS] {
+    let mut v = [0u32; SOBOL_BITS];
+    if dim == 0 {
+        for (k, value) in v.iter_mut().enumerate() {
+            *value = 1 << (SOBOL_BITS - 1 - k);
+        }
+        return v;
+    }
+
+    let (s, a, m) = SOBOL_DIRECTIONS[dim - 1];
+    let s = s as usize;
+    for k in 0..SOBOL_BITS {
+        if k < s {
+            v[k] = m[k] << (SOBOL_BITS - 1 - k);
+        } else {
+            v[k] = v[k - s] ^ (v[k - s] >> s);
+            for l in 1..s {
+                if (a >> (s - 1 - l)) & 1 == 1 {
+                    v[k] ^= v[k - l];
+                }
+            }
+        }
+    }
+
+    v
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(
+            parse_range("1..8"),
+            Some(Dimension::Range {
+                low: 1.0,
+                high: 8.0,
+                integer: true
+            })
+        );
+        assert_eq!(
+            parse_range("0.001..0.1"),
+            Some(Dimension::Range {
+                low: 0.001,
+                high: 0.1,
+                integer: false
+            })
+        );
+        assert_eq!(parse_range("1980,1990"), None);
+        assert_eq!(parse_range("a..b"), None);
+    }
+
+    #[test]
+    fn unshifted_sobol_matches_reference_sequence() {
+        let directions: Vec<[u32; SOBOL_BITS]> = (0..2).map(sobol_direction_numbers).collect();
+        let mut current = [0u32; 2];
+        let mut points = vec![];
+        for i in 0..4usize {
+            if i > 0 {
+                let bit = (!(i - 1)).trailing_zeros() as usize;
+                for (value, direction) in current.iter_mut().zip(&directions) {
+                    *value ^= direction[bit];
+                }
+            }
+            points.push(current.map(|v| v as f64 / 2f64.powi(32)));
+        }
+
+        assert_eq!(
+            points,
+            vec![[0.0, 0.0], [0.5, 0.5], [0.75, 0.25], [0.25, 0.75]]
+        );
+    }
+
+    #[test]
+    fn latin_hypercube_covers_every_stratum() {
+        let space = vec![(
+            "layers".to_string(),
+            Dimension::Range {
+                low: 1.0,
+                high: 10.0,
+                integer: true,
+            },
+        )];
+        let samples = sample_params(&space, Strategy::LatinHypercube, 10, 7).unwrap();
+        let mut values: Vec<i64> = samples
+            .iter()
+            .map(|s| s["layers"].as_i64().unwrap())
+            .collect();
+        values.sort_unstable();
+
+        assert_eq!(values, (1..=10).collect::<Vec<i64>>());
+    }
+
+    #[test]
+    fn same_seed_gives_same_samples() {
+        let space = vec![
+            (
+                "lr".to_string(),
+                Dimension::Range {
+                    low: 0.001,
+                    high: 0.1,
+                    integer: false,
+                },
+            ),
+            (
+                "optimizer".to_string(),
+                Dimension::Choice(vec![Value::from("adam"), Value::from("sgd")]),
+            ),
+        ];
+
+        for strategy in &[Strategy::Random, Strategy::LatinHypercube, Strategy::Sobol] {
+            let first = sample_params(&space, *strategy, 20, 42).unwrap();
+            let second = sample_params(&space, *strategy, 20, 42).unwrap();
+            assert_eq!(first.len(), 20);
+            assert_eq!(first, second);
+        }
+    }
+}
@@ -0,0 +1,56 @@
+mod kubernetes;
+mod local;
+
+use std::error::Error;
+use std::fmt::Display;
+
+use async_trait::async_trait;
+use rft_core::batch::Batch;
+
+pub use kubernetes::KubernetesExecutor;
+pub use local::LocalExecutor;
+
+/// Runs the jobs of a batch somewhere: as a Kubernetes Indexed Job, or as processes on the
+/// controller's machine. The controller decides when batches run, an executor only starts,
+/// watches and stops them.
+#[async_trait(?Send)]
+pub trait Executor: Display {
+    /// Starts every job in the batch
+    async fn submit(&self, batch: &Batch) -> Result<(), Box<dyn Error>>;
+
+    /// Where the batch's jobs are at. A batch the executor doesn't know about is "deleted".
+    async fn status(&self, batch: &Batch) -> Result<BatchStatus, Box<dyn Error>>;
+
+    /// Stops the batch's jobs, if any are still running
+    async fn cancel(&self, batch_id: &str) -> Result<(), Box<dyn Error>>;
+
+    /// Lets go of a finished batch once the controller has recorded its outcome
+    async fn forget(&self, _batch_id: &str) {}
+
+    /// The logs of one run of a job
+    async fn logs(&self, batch: &Batch, run: &JobRun) -> Result<String, Box<dyn Error>>;
+}
+
+/// A batch as seen by its executor
+#[derive(Debug, Default, PartialEq)]
+pub struct BatchStatus {
+    /// "completed", "failed" or "deleted" once the batch is over, None while it runs
+    pub outcome: Option<&'static str>,
+    /// indexes of the jobs that succeeded so far
+    pub succeeded: Vec<usize>,
+    /// every attempt at running a job, a retried job has several
+    pub runs: Vec<JobRun>,
+    /// CPU time used by the jobs that finished, weighted by the CPU they requested
+    pub cpu_seconds: f64,
+}
+
+/// One attempt at running a job, i.e. a pod
+#[derive(Clone, Debug, PartialEq)]
+pub struct JobRun {
+    pub job_index: usize,
+    /// unique among the batch's runs, the gateway reads live logs of the pod with this name
+    pub name: String,
+    /// when the run started, runs of a job sort oldest first by it
+    pub created: String,
+    pub finished: bool,
+}
@@ -0,0 +1,224 @@
+use std::error::Error;
+use std::fmt;
+
+use async_trait::async_trait;
+use k8s_openapi::api::batch::v1::Job as K8S_JOB;
+use k8s_openapi::api::core::v1::{Pod, Secret};
+use kube::{
+    api::{DeleteParams, ListParams, LogParams, PostParams, PropagationPolicy},
+    Api, Client, ResourceExt,
+};
+use rft_core::batch::Batch;
+use rft_core::manifest::{self, ArtifactUpload, JobReporting};
+
+use crate::executor::{BatchStatus, Executor, JobRun};
+
+/// Runs each batch as a Kubernetes Indexed Job in the default namespace, one pod per job
+pub struct KubernetesExecutor {
+    jobs: Api<K8S_JOB>,
+    pods: Api<Pod>,
+    secrets: Api<Secret>,
+    artifact_upload: Option<ArtifactUpload>,
+    job_reporting: Option<JobReporting>,
+}
+
+impl KubernetesExecutor {
+    pub fn new(
+        client: Client,
+        artifact_upload: Option<ArtifactUpload>,
+        job_reporting: Option<JobReporting>,
+    ) -> KubernetesExecutor {
+        KubernetesExecutor {
+            jobs: Api::namespaced(client.clone(), "default"),
+            pods: Api::namespaced(client.clone(), "default"),
+            secrets: Api::namespaced(client, "default"),
+            artifact_upload,
+            job_reporting,
+        }
+    }
+}
+
+impl fmt::Display for KubernetesExecutor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "as Kubernetes Indexed Jobs")
+    }
+}
+
+#[async_trait(?Send)]
+impl Executor for KubernetesExecutor {
+    async fn submit(&self, batch: &Batch) -> Result<(), Box<dyn Error>> {
+        let indexed_job = serde_json::from_value(manifest::indexed_job(
+            batch,
+            self.artifact_upload.as_ref(),
+            self.job_reporting.as_ref(),
+        ))?;
+        let k8s_job = self
+            .jobs
+            .create(&PostParams::default(), &indexed_job)
+            .await?;
+
+        // Pods wait for the tokens Secret to exist before starting, it is created once the
+        // Job has a UID to be owned by
+        if let Some(reporting) = &self.job_reporting {
+            let secret = serde_json::from_value(manifest::job_tokens_secret(
+                batch,
+                &reporting.tokens,
+                k8s_job.metadata.uid.as_deref().unwrap_or_default(),
+            ))?;
+            if let Err(err) = self.secrets.create(&PostParams::default(), &secret).await {
+                self.cancel(&batch.batch_id).await?;
+                return Err(err.into());
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn status(&self, batch: &Batch) -> Result<BatchStatus, Box<dyn Error>> {
+        let job_name = manifest::job_name(&batch.batch_id);
+        let k8s_job = match self.jobs.get(&job_name).await {
+            Ok(k8s_job) => k8s_job,
+            Err(kube::Error::Api(e)) if e.code == 404 => {
+                return Ok(BatchStatus {
+                    outcome: Some("deleted"),
+                    ..BatchStatus::default()
+                })
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let completed_indexes = k8s_job
+            .status
+            .as_ref()
+            .and_then(|s| s.completed_indexes.clone())
+            .unwrap_or_default();
+
+        let mut status = BatchStatus {
+            outcome: job_outcome(&k8s_job),
+            succeeded: parse_completed_indexes(&completed_indexes),
+            ..BatchStatus::default()
+        };
+        let lp = ListParams::default().labels(&format!("job-name={}", job_name));
+        for pod in self.pods.list(&lp).await? {
+            status.cpu_seconds += pod_cpu_seconds(&pod);
+            let job_index = match pod
+                .annotations()
+                .get(manifest::COMPLETION_INDEX_ANNOTATION)
+                .and_then(|index| index.parse::<usize>().ok())
+            {
+                Some(job_index) => job_index,
+                None => continue,
+            };
+            status.runs.push(JobRun {
+                job_index,
+                name: pod.name(),
+                created: pod
+                    .metadata
+                    .creation_timestamp
+                    .as_ref()
+                    .map(|time| time.0.to_rfc3339())
+                    .unwrap_or_default(),
+                finished: pod
+                    .status
+                    .as_ref()
+                    .and_then(|status| status.phase.as_deref())
+                    .is_some_and(|phase| phase == "Succeeded" || phase == "Failed"),
+            });
+        }
+
+        Ok(status)
+    }
+
+    async fn cancel(&self, batch_id: &str) -> Result<(), Box<dyn Error>> {
+        // Without background propagation deleting a Job leaves its pods running
+        let params = DeleteParams {
+            propagation_policy: Some(PropagationPolicy::Background),
+            ..DeleteParams::default()
+        };
+        match self
+            .jobs
+            .delete(&manifest::job_name(batch_id), &params)
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(kube::Error::Api(e)) if e.code == 404 => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn logs(&self, _batch: &Batch, run: &JobRun) -> Result<String, Box<dyn Error>> {
+        let params = LogParams {
+            container: Some(manifest::WORKER_CONTAINER.to_string()),
+            ..LogParams::default()
+        };
+
+        Ok(self.pods.logs(&run.name, &params).await?)
+    }
+}
+
+/// Returns how a Kubernetes Job finished, or None if it is still running
+fn job_outcome(k8s_job: &K8S_JOB) -> Option<&'static str> {
+    let conditions = k8s_job.status.as_ref()?.conditions.as_ref()?;
+    conditions
+        .iter()
+        .filter(|condition| condition.status == "True")
+        .find_map(|condition| match condition.type_.as_str() {
+            "Complete" => Some("completed"),
+            "Failed" => Some("failed"),
+            _ => None,
+        })
+}
+
+/// How long the pod's worker container ran, weighted by the CPU it requested
+fn pod_cpu_seconds(pod: &Pod) -> f64 {
+    let statuses = pod
+        .status
+        .as_ref()
+        .and_then(|status| status.container_statuses.as_ref());
+    statuses
+        .into_iter()
+        .flatten()
+        .filter(|status| status.name == manifest::WORKER_CONTAINER)
+        .filter_map(|status| status.state.as_ref()?.terminated.as_ref())
+        .filter_map(|terminated| {
+            let (started, finished) = (
+                terminated.started_at.as_ref()?,
+                terminated.finished_at.as_ref()?,
+            );
+            let seconds = (finished.0 - started.0).num_milliseconds() as f64 / 1000.0;
+            Some(seconds.max(0.0) * manifest::WORKER_CPU_REQUEST)
+        })
+        .sum()
+}
+
+/// Parses a Kubernetes completed indexes string, i.e. "1,3-5,7", into a list of indexes
+fn parse_completed_indexes(completed_indexes: &str) -> Vec<usize> {
+    let mut indexes = Vec::new();
+    for part in completed_indexes.split(',') {
+        match part.split_once('-') {
+            Some((start, end)) => {
+                if let (Ok(start), Ok(end)) = (start.parse::<usize>(), end.parse::<usize>()) {
+                    indexes.extend(start..=end);
+                }
+            }
+            None => {
+                if let Ok(index) = part.parse::<usize>() {
+                    indexes.push(index);
+                }
+            }
+        }
+    }
+
+    indexes
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::executor::kubernetes::parse_completed_indexes;
+
+    #[test]
+    fn parse_indexes() {
+        assert_eq!(parse_completed_indexes("1,3-5,7"), vec![1, 3, 4, 5, 7]);
+        assert_eq!(parse_completed_indexes("0"), vec![0]);
+        assert!(parse_completed_indexes("").is_empty());
+    }
+}
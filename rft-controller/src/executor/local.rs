@@ -0,0 +1,471 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use async_trait::async_trait;
+use futures::future::join_all;
+use rft_core::batch::Batch;
+use rft_core::local::JobDir;
+use rft_core::manifest::{self, JobReporting};
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+
+use crate::executor::{BatchStatus, Executor, JobRun};
+use crate::unix_now;
+
+/// Directory batches run in when LOCAL_RUN_DIR isn't set
+const DEFAULT_RUN_DIR: &str = "rft-runs";
+
+/// Runs each job as a process on the controller's machine, or in a Docker container when an
+/// image is configured, so rft can run on a single workstation without Kubernetes. Each batch
+/// gets a directory under the run directory with a job directory per job, see `JobDir`.
+///
+/// Jobs are tracked in memory: batches that were running when the controller stopped are
+/// reported as deleted once it restarts.
+pub struct LocalExecutor {
+    settings: Arc<Settings>,
+    batches: Mutex<HashMap<String, LocalBatch>>,
+}
+
+struct Settings {
+    run_dir: PathBuf,
+    /// the repository jobs run from, otherwise each batch's branch is cloned into its directory
+    workdir: Option<PathBuf>,
+    docker_image: Option<String>,
+    job_reporting: Option<JobReporting>,
+    /// limits how many jobs run at once across all batches
+    slots: Semaphore,
+}
+
+struct LocalBatch {
+    task: JoinHandle<()>,
+    jobs: Arc<Mutex<Vec<JobState>>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum JobState {
+    Pending,
+    Running {
+        started: u64,
+    },
+    /// `exit_code` is None if the worker couldn't be started or was killed by a signal
+    Finished {
+        started: u64,
+        exit_code: Option<i32>,
+        seconds: f64,
+    },
+}
+
+impl LocalExecutor {
+    pub fn new(
+        run_dir: PathBuf,
+        workdir: Option<PathBuf>,
+        docker_image: Option<String>,
+        concurrency: usize,
+        job_reporting: Option<JobReporting>,
+    ) -> LocalExecutor {
+        LocalExecutor {
+            settings: Arc::new(Settings {
+                run_dir: absolute(run_dir),
+                workdir: workdir.map(absolute),
+                docker_image,
+                job_reporting,
+                slots: Semaphore::new(concurrency.max(1)),
+            }),
+            batches: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Reads the executor's settings through `lookup`: LOCAL_RUN_DIR, LOCAL_WORKDIR,
+    /// LOCAL_CONCURRENCY, which defaults to the number of CPUs, and LOCAL_DOCKER_IMAGE
+    pub fn from_lookup<F>(
+        lookup: F,
+        job_reporting: Option<JobReporting>,
+    ) -> Result<LocalExecutor, String>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let lookup = |name: &str| lookup(name).filter(|value| !value.is_empty());
+        let concurrency = match lookup("LOCAL_CONCURRENCY") {
+            Some(concurrency) => concurrency
+                .parse::<usize>()
+                .ok()
+                .filter(|concurrency| *concurrency > 0)
+                .ok_or_else(|| {
+                    format!(
+                        "LOCAL_CONCURRENCY must be a positive number, not '{}'",
+                        concurrency
+                    )
+                })?,
+            None => std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1),
+        };
+
+        Ok(LocalExecutor::new(
+            PathBuf::from(lookup("LOCAL_RUN_DIR").unwrap_or_else(|| DEFAULT_RUN_DIR.to_string())),
+            lookup("LOCAL_WORKDIR").map(PathBuf::from),
+            lookup("LOCAL_DOCKER_IMAGE"),
+            concurrency,
+            job_reporting,
+        ))
+    }
+}
+
+impl fmt::Display for LocalExecutor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.settings.docker_image {
+            Some(image) => write!(f, "in {} containers", image)?,
+            None => write!(f, "as local processes")?,
+        }
+        write!(f, " under {}", self.settings.run_dir.display())
+    }
+}
+
+#[async_trait(?Send)]
+impl Executor for LocalExecutor {
+    async fn submit(&self, batch: &Batch) -> Result<(), Box<dyn Error>> {
+        let jobs = Arc::new(Mutex::new(vec![JobState::Pending; batch.jobs.len()]));
+        let task = tokio::spawn(run_batch(
+            self.settings.clone(),
+            batch.clone(),
+            jobs.clone(),
+        ));
+        self.batches
+            .lock()
+            .unwrap()
+            .insert(batch.batch_id.clone(), LocalBatch { task, jobs });
+
+        Ok(())
+    }
+
+    /// A finished batch keeps reporting its outcome until the controller forgets it, so its
+    /// runs and logs aren't lost if recording them fails
+    async fn status(&self, batch: &Batch) -> Result<BatchStatus, Box<dyn Error>> {
+        let batches = self.batches.lock().unwrap();
+        let jobs = match batches.get(&batch.batch_id) {
+            Some(local_batch) => local_batch.jobs.lock().unwrap().clone(),
+            None => {
+                return Ok(BatchStatus {
+                    outcome: Some("deleted"),
+                    ..BatchStatus::default()
+                })
+            }
+        };
+
+        let mut status = BatchStatus::default();
+        let (mut running, mut failed) = (false, false);
+        for (job_index, state) in jobs.into_iter().enumerate() {
+            let (started, finished) = match state {
+                JobState::Pending => {
+                    running = true;
+                    continue;
+                }
+                JobState::Running { started } => {
+                    running = true;
+                    (started, false)
+                }
+                JobState::Finished {
+                    started,
+                    exit_code,
+                    seconds,
+                } => {
+                    status.cpu_seconds += seconds * manifest::WORKER_CPU_REQUEST;
+                    match exit_code {
+                        Some(0) => status.succeeded.push(job_index),
+                        _ => failed = true,
+                    }
+                    (started, true)
+                }
+            };
+            status.runs.push(JobRun {
+                job_index,
+                name: run_name(&batch.batch_id, job_index),
+                created: format!("{:020}", started),
+                finished,
+            });
+        }
+
+        if !running {
+            status.outcome = Some(if failed { "failed" } else { "completed" });
+        }
+
+        Ok(status)
+    }
+
+    async fn cancel(&self, batch_id: &str) -> Result<(), Box<dyn Error>> {
+        let local_batch = match self.batches.lock().unwrap().remove(batch_id) {
+            Some(local_batch) => local_batch,
+            None => return Ok(()),
+        };
+        // Dropping the jobs' futures kills their processes, but not the containers they started
+        local_batch.task.abort();
+        if self.settings.docker_image.is_some() {
+            let jobs = local_batch.jobs.lock().unwrap().len();
+            let names = (0..jobs).map(|job_index| run_name(batch_id, job_index));
+            tokio::process::Command::new("docker")
+                .args(["rm", "--force"])
+                .args(names)
+                .output()
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn forget(&self, batch_id: &str) {
+        self.batches.lock().unwrap().remove(batch_id);
+    }
+
+    async fn logs(&self, batch: &Batch, run: &JobRun) -> Result<String, Box<dyn Error>> {
+        let job = batch
+            .jobs
+            .get(run.job_index)
+            .ok_or_else(|| format!("Batch {} has no job {}", batch.batch_id, run.job_index))?;
+        let run_dir = self.settings.run_dir.join(&batch.batch_id);
+
+        Ok(tokio::fs::read_to_string(JobDir::new(&run_dir, &job.job_id).log_path()).await?)
+    }
+}
+
+/// Runs every job in the batch, as slots become free
+async fn run_batch(settings: Arc<Settings>, batch: Batch, jobs: Arc<Mutex<Vec<JobState>>>) {
+    let run_dir = settings.run_dir.join(&batch.batch_id);
+    let workdir = match &settings.workdir {
+        Some(workdir) => Ok(workdir.clone()),
+        None => checkout(&batch, &run_dir).await,
+    };
+
+    join_all((0..batch.jobs.len()).map(|index| {
+        run_job(
+            &settings,
+            &batch,
+            &run_dir,
+            workdir.as_deref().map_err(|err| err.as_str()),
+            index,
+            &jobs,
+        )
+    }))
+    .await;
+}
+
+/// Runs a single job once a slot is free, recording its state as it goes. Failures to start
+/// the worker are written to its log.
+async fn run_job(
+    settings: &Settings,
+    batch: &Batch,
+    run_dir: &Path,
+    workdir: Result<&Path, &str>,
+    index: usize,
+    jobs: &Mutex<Vec<JobState>>,
+) {
+    let _slot = settings.slots.acquire().await;
+    let started = unix_now();
+    jobs.lock().unwrap()[index] = JobState::Running { started };
+
+    let job_dir = JobDir::new(run_dir, &batch.jobs[index].job_id);
+    let timer = Instant::now();
+    let exit_code = match workdir {
+        Ok(workdir) => {
+            let status = match job_command(settings, batch, index, &job_dir, workdir) {
+                Ok(command) => {
+                    tokio::process::Command::from(command)
+                        .kill_on_drop(true)
+                        .status()
+                        .await
+                }
+                Err(err) => Err(err),
+            };
+            match status {
+                Ok(status) => status.code(),
+                Err(err) => {
+                    write_log(&job_dir, &format!("Failed to start the worker: {}", err));
+                    None
+                }
+            }
+        }
+        Err(err) => {
+            write_log(&job_dir, err);
+            None
+        }
+    };
+
+    jobs.lock().unwrap()[index] = JobState::Finished {
+        started,
+        exit_code,
+        seconds: timer.elapsed().as_secs_f64(),
+    };
+}
+
+/// Prepares the job's directory and builds the command running its worker. With reporting
+/// configured the worker reports to the gateway with its job token.
+fn job_command(
+    settings: &Settings,
+    batch: &Batch,
+    index: usize,
+    job_dir: &JobDir,
+    workdir: &Path,
+) -> io::Result<Command> {
+    let job = &batch.jobs[index];
+    job_dir.prepare(job)?;
+
+    let mut env = Vec::new();
+    if let Some(reporting) = &settings.job_reporting {
+        env.push(("RFT_GATEWAY_URL".to_string(), reporting.gateway_url.clone()));
+        env.push((
+            "RFT_JOB_TOKEN".to_string(),
+            reporting.tokens.token(&batch.batch_id, &job.job_id),
+        ));
+    }
+
+    match &settings.docker_image {
+        Some(image) => job_dir.docker_command(
+            batch,
+            index,
+            workdir,
+            &batch.source_file,
+            image,
+            &run_name(&batch.batch_id, index),
+            &env,
+        ),
+        None => job_dir.command(
+            batch,
+            index,
+            workdir,
+            &workdir.join(&batch.source_file),
+            &env,
+        ),
+    }
+}
+
+/// Clones the batch's branch into `<run_dir>/source`, checking out its commit if known
+async fn checkout(batch: &Batch, run_dir: &Path) -> Result<PathBuf, String> {
+    let source = run_dir.join("source");
+    if source.exists() {
+        fs::remove_dir_all(&source)
+            .map_err(|err| format!("Unable to remove {}: {}", source.display(), err))?;
+    }
+
+    // The URL and branch come from the submitter, `--` and `--branch=` stop git from reading
+    // them as options
+    git(&[
+        "clone",
+        "--quiet",
+        &format!("--branch={}", batch.branch),
+        "--",
+        &batch.repository_url,
+        &source.to_string_lossy(),
+    ])
+    .await?;
+    if let Some(commit) = &batch.commit {
+        if commit.is_empty() || !commit.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("'{}' is not a commit hash", commit));
+        }
+        git(&[
+            "-C",
+            &source.to_string_lossy(),
+            "checkout",
+            "--quiet",
+            "--detach",
+            commit,
+        ])
+        .await?;
+    }
+
+    Ok(source)
+}
+
+async fn git(args: &[&str]) -> Result<(), String> {
+    let output = tokio::process::Command::new("git")
+        .args(args)
+        .output()
+        .await
+        .map_err(|err| format!("Unable to run git: {}", err))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "git {} failed: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+/// Name of a job's run, and of its container when running in Docker
+fn run_name(batch_id: &str, job_index: usize) -> String {
+    format!("rft-{}-{}", batch_id, job_index).to_lowercase()
+}
+
+fn write_log(job_dir: &JobDir, message: &str) {
+    let _ = fs::create_dir_all(&job_dir.root);
+    if let Err(err) = fs::write(job_dir.log_path(), format!("{}\n", message)) {
+        eprintln!("Unable to write {}: {}", job_dir.log_path().display(), err);
+    }
+}
+
+/// Docker only mounts absolute paths, and jobs run from the repository rather than the
+/// controller's working directory
+fn absolute(path: PathBuf) -> PathBuf {
+    match std::env::current_dir() {
+        Ok(current_dir) => current_dir.join(path),
+        Err(_) => path,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::executor::{Executor, LocalExecutor};
+    use rft_core::batch::Batch;
+    use rft_core::job::Job;
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn runs_batches_locally() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().to_path_buf();
+        std::fs::write(
+            root.join("main.sh"),
+            "grep -q '\"fail\":true' \"$RFT_DATA_PATH\" && exit 3; echo \"job $JOB_COMPLETION_INDEX\"",
+        )
+        .unwrap();
+
+        let mut batch = Batch::new("matt", "main.sh", "unused", "master");
+        for fail in &[false, true] {
+            let params = vec![("fail".to_string(), json!(fail))]
+                .into_iter()
+                .collect();
+            batch.jobs.push(Job::new(params));
+        }
+
+        let executor = LocalExecutor::new(root.join("runs"), Some(root.clone()), None, 1, None);
+        executor.submit(&batch).await.unwrap();
+        let status = loop {
+            let status = executor.status(&batch).await.unwrap();
+            if status.outcome.is_some() {
+                break status;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        };
+
+        assert_eq!(status.outcome, Some("failed"));
+        assert_eq!(status.succeeded, vec![0]);
+        assert_eq!(status.runs.len(), 2);
+        assert!(status.runs.iter().all(|run| run.finished));
+        let logs = executor.logs(&batch, &status.runs[0]).await.unwrap();
+        assert_eq!(logs, "job 0\n");
+        assert_eq!(executor.status(&batch).await.unwrap(), status);
+        executor.forget(&batch.batch_id).await;
+        assert_eq!(
+            executor.status(&batch).await.unwrap().outcome,
+            Some("deleted")
+        );
+    }
+}
@@ -0,0 +1,64 @@
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method, Request};
+use hyper_tls::HttpsConnector;
+use redis::Commands;
+use rft_core::log_store::LogStore;
+use rft_core::queue::Keys;
+
+/// Writes job logs to the configured `LogStore`
+pub struct LogArchive {
+    pub store: LogStore,
+    client: Client<HttpsConnector<HttpConnector>, Body>,
+}
+
+impl LogArchive {
+    pub fn new(store: LogStore) -> LogArchive {
+        LogArchive {
+            store,
+            client: Client::builder().build(HttpsConnector::new()),
+        }
+    }
+
+    /// Archives a job's logs, replacing any archived from an earlier attempt
+    pub async fn write<C: redis::ConnectionLike>(
+        &self,
+        conn: &mut C,
+        keys: &Keys,
+        batch_id: &str,
+        job_id: &str,
+        logs: String,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let logs = self.store.prepare(logs);
+        match &self.store {
+            LogStore::Redis { .. } => {
+                let _: () = conn.hset(keys.job_logs(batch_id), job_id, logs)?;
+            }
+            LogStore::Filesystem { dir, .. } => {
+                let path = LogStore::file_path(dir, batch_id, job_id);
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(path, logs)?;
+            }
+            LogStore::ObjectStorage { bucket, .. } => {
+                let unix_now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+                let url =
+                    bucket.upload_url(&LogStore::object_key(batch_id, job_id), 60, unix_now)?;
+                let request = Request::builder()
+                    .method(Method::PUT)
+                    .uri(url)
+                    .header("Content-Type", "text/plain; charset=utf-8")
+                    .body(Body::from(logs))?;
+                let response = self.client.request(request).await?;
+                if !response.status().is_success() {
+                    return Err(format!("bucket responded {}", response.status()).into());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,143 +1,114 @@
+mod executor;
+mod log_archive;
+
 use std::env;
 use std::process::exit;
 
-use futures::{StreamExt, TryStreamExt};
-use k8s_openapi::api::batch::v1::Job as K8S_JOB;
-use kube::{
-    api::{ListParams, PostParams, WatchEvent},
-    Api, Client, ResourceExt,
-};
-use redis::{Commands, ConnectionAddr, ConnectionInfo, RedisConnectionInfo};
-use rft_core::batch::{Batch, Error::DeserializeFailed};
+use std::collections::{HashMap, HashSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use executor::{Executor, JobRun, KubernetesExecutor, LocalExecutor};
+use kube::Client;
+use log_archive::LogArchive;
+use redis::Commands;
+use rft_core::artifacts::ArtifactStore;
+use rft_core::batch::{self, Batch};
+use rft_core::job_token::JobTokens;
+use rft_core::log_store::LogStore;
+use rft_core::manifest::{self, ArtifactUpload, JobReporting};
+use rft_core::queue::{self, Dependencies, HeldQuota, Keys, QueuedBatch};
+use rft_core::redis_config::RedisConfig;
 use tokio::time::Duration;
 
+/// Number of batches run at once when RFT_MAX_RUNNING_BATCHES isn't set
+const DEFAULT_MAX_RUNNING_BATCHES: usize = 5;
+
 #[tokio::main]
 async fn main() -> Result<(), kube::Error> {
-    let redis_host = env::var("REDIS_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
-    let redis_password = env::var("REDIS_PASSWORD").unwrap_or_else(|_| "Mxu168c6OL".to_string());
-    let connection_details = ConnectionInfo {
-        addr: ConnectionAddr::Tcp(redis_host, 6379),
-        redis: RedisConnectionInfo {
-            db: 0,
-            username: None,
-            password: Some(redis_password),
-        },
+    let redis_config = match RedisConfig::from_env() {
+        Ok(redis_config) => redis_config,
+        Err(err) => {
+            eprintln!("FATAL - {}!", err);
+            exit(1)
+        }
     };
+    let keys = redis_config.keys();
+    let artifact_upload = match artifact_upload_from_env() {
+        Ok(artifact_upload) => artifact_upload,
+        Err(err) => {
+            eprintln!("FATAL - {}!", err);
+            exit(1)
+        }
+    };
+    let job_reporting = match job_reporting_from_env() {
+        Ok(job_reporting) => job_reporting,
+        Err(err) => {
+            eprintln!("FATAL - {}!", err);
+            exit(1)
+        }
+    };
+    let log_archive = match LogStore::from_lookup(|name| env::var(name).ok()) {
+        Ok(log_store) => LogArchive::new(log_store),
+        Err(err) => {
+            eprintln!("FATAL - {}!", err);
+            exit(1)
+        }
+    };
+    let max_running_batches = env::var("RFT_MAX_RUNNING_BATCHES")
+        .ok()
+        .and_then(|max| max.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_MAX_RUNNING_BATCHES);
 
-    let kube_client = Client::try_default().await?;
-    let jobs: Api<K8S_JOB> = Api::namespaced(kube_client, "default");
-
-    // TODO: improve unwrap() error handling here
-    if let Ok(redis_client) = redis::Client::open(connection_details) {
+    println!("Using Redis at {}", redis_config);
+    if let Some(upload) = &artifact_upload {
+        println!("Uploading artifacts to {}", upload.store);
+    }
+    if let Some(reporting) = &job_reporting {
+        println!("Jobs report to the gateway at {}", reporting.gateway_url);
+    }
+    let executor: Box<dyn Executor> = match env::var("RFT_EXECUTOR").as_deref() {
+        Ok("kubernetes") | Ok("") | Err(_) => Box::new(KubernetesExecutor::new(
+            Client::try_default().await?,
+            artifact_upload,
+            job_reporting,
+        )),
+        Ok("local") => {
+            if artifact_upload.is_some() {
+                println!("Artifacts aren't uploaded by the local executor, they stay in the run directory");
+            }
+            match LocalExecutor::from_lookup(|name| env::var(name).ok(), job_reporting) {
+                Ok(executor) => Box::new(executor),
+                Err(err) => {
+                    eprintln!("FATAL - {}!", err);
+                    exit(1)
+                }
+            }
+        }
+        Ok(other) => {
+            eprintln!(
+                "FATAL - Unknown RFT_EXECUTOR '{}', expected kubernetes or local!",
+                other
+            );
+            exit(1)
+        }
+    };
+    println!("Running batches {}", executor);
+    println!("Archiving job logs to {}", log_archive.store);
+    if let Ok(redis_client) = redis::Client::open(redis_config.connection_info()) {
         match redis_client.get_connection() {
             Ok(mut conn) => loop {
-                let mut queued_batches: Vec<String> = conn.lrange("queued_batches", 0, -1).unwrap();
-
-                if queued_batches.is_empty() {
-                    tokio::time::sleep(Duration::from_millis(5000)).await;
-                    continue;
+                if let Err(err) =
+                    reconcile_running_batches(executor.as_ref(), &mut conn, &keys, &log_archive)
+                        .await
+                {
+                    eprintln!("Failed to reconcile running batches: {}", err);
                 }
 
-                while !queued_batches.is_empty() {
-                    let json_batch: String = conn.lpop("queued_batches", None).unwrap();
-
-                    match Batch::from_json(&json_batch) {
-                        Ok(batch) => {
-                            println!("Processing batch: \n{}", batch);
-
-                            let indexed_job = serde_json::from_value(serde_json::json!({
-                                "apiVersion": "batch/v1",
-                                "kind": "Job",
-                                "metadata": {
-                                    "name": format!("rft-indexed-job-{}", batch.batch_id)
-                                },
-                                "spec": {
-                                    "completions": batch.jobs.len(),
-                                    "parallelism": 3,
-                                    "completionMode": "Indexed",
-                                    "template": {
-                                        "spec": {
-                                            "restartPolicy": "Never",
-                                            "volumes": [
-                                                {
-                                                    "name": "input",
-                                                    "emptyDir": {}
-                                                },
-                                            ],
-                                            "initContainers": [
-                                                {
-                                                    "name": "input-mapping",
-                                                    "image": "public.ecr.aws/e1q1z8n5/alpine-jq",
-                                                    "command": [
-                                                        "/bin/sh",
-                                                        "-c",
-                                                        format!("echo '{}' | jq '.jobs['\"$JOB_COMPLETION_INDEX\"']' > /input/data.json", json_batch)
-                                                    ],
-                                                    "volumeMounts": [
-                                                        {
-                                                            "name": "input",
-                                                            "mountPath": "/input"
-                                                        }
-                                                    ]
-                                                }
-                                            ],
-                                            "containers": [
-                                                {
-                                                    "name": "worker",
-                                                    "image": "docker.io/library/bash",
-                                                    "command": [
-                                                        "bash",
-                                                        "-c",
-                                                        "cat /input/data.json"
-                                                    ],
-                                                    "volumeMounts": [
-                                                        {
-                                                            "name": "input",
-                                                            "mountPath": "/input"
-                                                        }
-                                                    ]
-                                                }
-                                            ],
-                                        }
-                                    }
-                                },
-                            }))?;
-
-                            jobs.create(&PostParams::default(), &indexed_job).await?;
-                            let lp = ListParams::default()
-                                .fields(&format!("metadata.name={}", "rft-indexed-job"))
-                                .timeout(10);
-
-                            let mut stream = jobs.watch(&lp, "0").await?.boxed();
-
-                            while let Some(status) = stream.try_next().await? {
-                                match status {
-                                    WatchEvent::Added(o) => println!("Added {}", o.name()),
-                                    WatchEvent::Modified(o) => {
-                                        let s = o.status.as_ref().expect("status exists on job");
-                                        let indices =
-                                            s.completed_indexes.clone().unwrap_or_default();
-                                        println!(
-                                            "Modified: {} with indices completed: {}",
-                                            o.name(),
-                                            indices
-                                        );
-                                    }
-                                    WatchEvent::Deleted(o) => println!("Deleted Job: {}", o.name()),
-                                    WatchEvent::Error(e) => println!("Error {}", e),
-                                    _ => {}
-                                }
-                            }
-
-                            queued_batches = conn.lrange("queued_batches", 0, -1).unwrap();
-                        }
-                        Err(e) => match e {
-                            DeserializeFailed { batch, source } => eprintln!(
-                                "Failed to deserialize batch: {} with source: {}",
-                                &batch, &source
-                            ),
-                        },
-                    }
+                if let Err(err) =
+                    launch_queued_batches(executor.as_ref(), &mut conn, &keys, max_running_batches)
+                        .await
+                {
+                    eprintln!("Failed to launch queued batches: {}", err);
                 }
 
                 tokio::time::sleep(Duration::from_millis(5000)).await;
@@ -151,3 +122,776 @@ async fn main() -> Result<(), kube::Error> {
 
     Ok(())
 }
+
+/// Reads where job pods upload artifacts, see `ArtifactStore`. Pods authenticate with the
+/// Kubernetes Secret named by ARTIFACTS_CREDENTIALS_SECRET, which is required when a bucket is
+/// configured, and run the `aws` CLI from ARTIFACTS_UPLOAD_IMAGE.
+fn artifact_upload_from_env() -> Result<Option<ArtifactUpload>, String> {
+    let store = match ArtifactStore::from_lookup(|name| env::var(name).ok()) {
+        Ok(Some(store)) => store,
+        Ok(None) => return Ok(None),
+        Err(err) => return Err(err.to_string()),
+    };
+    let credentials_secret = env::var("ARTIFACTS_CREDENTIALS_SECRET")
+        .ok()
+        .filter(|secret| !secret.is_empty())
+        .ok_or_else(|| {
+            "ARTIFACTS_CREDENTIALS_SECRET must name the Secret holding the bucket's credentials"
+                .to_string()
+        })?;
+
+    Ok(Some(ArtifactUpload {
+        store,
+        credentials_secret,
+        image: env::var("ARTIFACTS_UPLOAD_IMAGE")
+            .unwrap_or_else(|_| manifest::DEFAULT_UPLOAD_IMAGE.to_string()),
+    }))
+}
+
+/// Reads where jobs report progress, metrics and results. Reporting is enabled by
+/// JOB_TOKEN_SECRET, the key shared with the gateway to sign each job's token, and requires
+/// RFT_WORKER_GATEWAY_URL, the gateway's address as seen from job pods.
+fn job_reporting_from_env() -> Result<Option<JobReporting>, String> {
+    let tokens = match JobTokens::from_lookup(|name| env::var(name).ok()) {
+        Some(tokens) => tokens,
+        None => return Ok(None),
+    };
+    let gateway_url = env::var("RFT_WORKER_GATEWAY_URL")
+        .ok()
+        .filter(|url| !url.is_empty())
+        .ok_or_else(|| {
+            "RFT_WORKER_GATEWAY_URL must be set when JOB_TOKEN_SECRET is, i.e. http://rft-gateway:8000"
+                .to_string()
+        })?;
+
+    Ok(Some(JobReporting {
+        gateway_url: gateway_url.trim_end_matches('/').to_string(),
+        tokens,
+    }))
+}
+
+/// Starts queued batches until `max_running_batches` are running. Each time a slot is free
+/// the highest priority batch runs next, see `queue::next_batch`. Between batches of the same
+/// priority the user with the fewest jobs running goes first, so batches from different users
+/// interleave instead of one large sweep holding up everyone else.
+async fn launch_queued_batches<C: redis::ConnectionLike>(
+    executor: &dyn Executor,
+    conn: &mut C,
+    keys: &Keys,
+    max_running_batches: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    loop {
+        // Batches waiting on others are left in the queue, and failed if one of them fails,
+        // before checking for a free slot so dependents don't wait on a busy cluster to fail
+        let users: Vec<String> = conn.smembers(keys.queued_users())?;
+        let mut queued = Vec::new();
+        for user in users {
+            let batch_ids: Vec<String> = conn.lrange(keys.user_queue(&user), 0, -1)?;
+            for batch_id in batch_ids {
+                // A batch that can't be read will never run, fail it rather than trip over it
+                // on every pass
+                let batch = match get_batch(conn, keys, &batch_id)? {
+                    Some(Ok(batch)) => Some(batch),
+                    Some(Err(err)) => {
+                        eprintln!("Batch {} failed, it can't be read: {}", &batch_id, err);
+                        drop_queued_batch(conn, keys, &user, &batch_id, None, Some("failed"))?;
+                        continue;
+                    }
+                    None => None,
+                };
+                if let Some(batch) = &batch {
+                    match dependencies(conn, keys, batch)? {
+                        Dependencies::Ready => {}
+                        Dependencies::Waiting => continue,
+                        Dependencies::Failed(predecessor) => {
+                            fail_dependent_batch(conn, keys, &user, batch, &predecessor)?;
+                            continue;
+                        }
+                    }
+                }
+                queued.push(QueuedBatch {
+                    batch_id,
+                    user: user.clone(),
+                    priority: batch.as_ref().map(|b| b.priority).unwrap_or_default(),
+                    submitted_at: batch.and_then(|b| b.submitted_at).unwrap_or_default(),
+                });
+            }
+        }
+
+        let running: Vec<String> = conn.smembers(keys.running_batches())?;
+        if running.len() >= max_running_batches {
+            return Ok(());
+        }
+
+        let mut running_jobs: HashMap<String, usize> = HashMap::new();
+        for batch_id in &running {
+            if let Some(Ok(batch)) = get_batch(conn, keys, batch_id)? {
+                *running_jobs.entry(batch.owner().to_string()).or_default() += batch.jobs.len();
+            }
+        }
+
+        let next = match queue::next_batch(&queued, &running_jobs, unix_now()) {
+            Some(next) => next.clone(),
+            None => return Ok(()),
+        };
+        match get_batch(conn, keys, &next.batch_id)? {
+            Some(Ok(batch)) => {
+                println!("Processing batch: \n{}", batch);
+
+                let held = held_quota(conn, keys, &batch.batch_id, Some(&batch))?;
+                let mut pipe = redis::pipe();
+                pipe.atomic()
+                    .lrem(keys.user_queue(&next.user), 1, &batch.batch_id)
+                    .sadd(keys.running_batches(), &batch.batch_id);
+                for account in held.iter().flat_map(|held| &held.accounts) {
+                    pipe.hincr(keys.queued_batches_count(), account, -1);
+                }
+                pipe.query::<()>(conn)?;
+
+                // A batch that can't be started isn't retried, fail it and let the others run
+                if let Err(err) = executor.submit(&batch).await {
+                    eprintln!(
+                        "Batch {} failed, it can't be started: {}",
+                        &batch.batch_id, err
+                    );
+                    if let Err(err) = executor.cancel(&batch.batch_id).await {
+                        eprintln!("Unable to cancel batch {}: {}", &batch.batch_id, err);
+                    }
+                    let mut pipe = redis::pipe();
+                    pipe.atomic()
+                        .srem(keys.running_batches(), &batch.batch_id)
+                        .hset(keys.batch_outcomes(), &batch.batch_id, "failed");
+                    release_quota(&mut pipe, keys, &batch.batch_id, held.as_ref(), false);
+                    pipe.query::<()>(conn)?;
+                }
+            }
+            None => {
+                println!("Batch {} was deleted while queued", &next.batch_id);
+                drop_queued_batch(conn, keys, &next.user, &next.batch_id, None, None)?;
+            }
+            Some(Err(err)) => {
+                eprintln!("Batch {} failed, it can't be read: {}", &next.batch_id, err);
+                drop_queued_batch(conn, keys, &next.user, &next.batch_id, None, Some("failed"))?;
+            }
+        }
+    }
+}
+
+/// Checks whether the batches a batch depends on have completed. A predecessor whose batch
+/// no longer exists counts as deleted.
+fn dependencies<C: redis::ConnectionLike>(
+    conn: &mut C,
+    keys: &Keys,
+    batch: &Batch,
+) -> Result<Dependencies, Box<dyn std::error::Error>> {
+    let mut outcomes = Vec::new();
+    for predecessor in &batch.depends_on {
+        let (outcome, exists): (Option<String>, bool) = redis::pipe()
+            .hget(keys.batch_outcomes(), predecessor)
+            .exists(keys.batch(predecessor))
+            .query(conn)?;
+        let outcome = match (outcome, exists) {
+            (None, false) => Some("deleted".to_string()),
+            (outcome, _) => outcome,
+        };
+        outcomes.push((predecessor.clone(), outcome));
+    }
+
+    Ok(queue::dependencies(&outcomes))
+}
+
+/// Takes a batch out of its owner's queue as failed because a batch it depends on didn't
+/// complete, releasing the quota it held
+fn fail_dependent_batch<C: redis::ConnectionLike>(
+    conn: &mut C,
+    keys: &Keys,
+    user: &str,
+    batch: &Batch,
+    predecessor: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!(
+        "Batch {} failed, it depends on batch {} which didn't complete",
+        &batch.batch_id, predecessor
+    );
+
+    drop_queued_batch(
+        conn,
+        keys,
+        user,
+        &batch.batch_id,
+        Some(batch),
+        Some("failed"),
+    )
+}
+
+/// Takes a batch that will never run out of its owner's queue, releasing the quota it held
+/// and recording its outcome, if it has one
+fn drop_queued_batch<C: redis::ConnectionLike>(
+    conn: &mut C,
+    keys: &Keys,
+    user: &str,
+    batch_id: &str,
+    batch: Option<&Batch>,
+    outcome: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let held = held_quota(conn, keys, batch_id, batch)?;
+    let mut pipe = redis::pipe();
+    pipe.atomic().lrem(keys.user_queue(user), 1, batch_id);
+    if let Some(outcome) = outcome {
+        pipe.hset(keys.batch_outcomes(), batch_id, outcome);
+    }
+    release_quota(&mut pipe, keys, batch_id, held.as_ref(), true);
+    pipe.query::<()>(conn)?;
+
+    Ok(())
+}
+
+/// Finds running batches whose jobs have finished, releases their quota and records the CPU
+/// time their jobs used against the owner's daily quota. Batches deleted from the queue are
+/// cancelled.
+async fn reconcile_running_batches<C: redis::ConnectionLike>(
+    executor: &dyn Executor,
+    conn: &mut C,
+    keys: &Keys,
+    log_archive: &LogArchive,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let running: Vec<String> = conn.smembers(keys.running_batches())?;
+    for batch_id in running {
+        let batch = match get_batch(conn, keys, &batch_id)? {
+            Some(Ok(batch)) => batch,
+            unreadable => {
+                let mut pipe = redis::pipe();
+                pipe.atomic().srem(keys.running_batches(), &batch_id);
+                if let Some(Err(err)) = unreadable {
+                    eprintln!("Batch {} failed, it can't be read: {}", &batch_id, err);
+                    pipe.hset(keys.batch_outcomes(), &batch_id, "failed");
+                }
+                if let Err(err) = executor.cancel(&batch_id).await {
+                    eprintln!("Unable to cancel batch {}: {}", batch_id, err);
+                }
+                let held = held_quota(conn, keys, &batch_id, None)?;
+                release_quota(&mut pipe, keys, &batch_id, held.as_ref(), false);
+                pipe.query::<()>(conn)?;
+                continue;
+            }
+        };
+
+        // A batch that can't be checked on is left running and tried again on the next pass,
+        // without holding up the others
+        let status = match executor.status(&batch).await {
+            Ok(status) => status,
+            Err(err) => {
+                eprintln!("Unable to get the status of batch {}: {}", &batch_id, err);
+                continue;
+            }
+        };
+        record_succeeded_jobs(conn, keys, &batch, &status.succeeded)?;
+        if let Err(err) =
+            record_job_runs(executor, conn, keys, log_archive, &batch, &status.runs).await
+        {
+            eprintln!("Unable to record the runs of batch {}: {}", &batch_id, err);
+            continue;
+        }
+
+        if let Some(outcome) = status.outcome {
+            println!(
+                "Batch {} {} after using {:.2} CPU hours",
+                &batch.batch_id,
+                outcome,
+                status.cpu_seconds / 3600.0
+            );
+
+            let now = unix_now();
+            let held = held_quota(conn, keys, &batch.batch_id, Some(&batch))?;
+            let mut pipe = redis::pipe();
+            pipe.atomic()
+                .srem(keys.running_batches(), &batch.batch_id)
+                .hset(keys.batch_outcomes(), &batch.batch_id, outcome);
+            for account in held.iter().flat_map(|held| &held.accounts) {
+                pipe.cmd("HINCRBYFLOAT")
+                    .arg(keys.cpu_seconds(queue::epoch_day(now)))
+                    .arg(account)
+                    .arg(status.cpu_seconds)
+                    .ignore();
+            }
+            release_quota(&mut pipe, keys, &batch.batch_id, held.as_ref(), false);
+            pipe.query::<()>(conn)?;
+            executor.forget(&batch.batch_id).await;
+        }
+    }
+
+    Ok(())
+}
+
+/// Records which runs, i.e. pods, ran each job in a batch, so the gateway can find a job's
+/// logs, and archives the logs of runs that have finished. Retried jobs have several runs, the
+/// logs of the latest to finish are kept.
+async fn record_job_runs<C: redis::ConnectionLike>(
+    executor: &dyn Executor,
+    conn: &mut C,
+    keys: &Keys,
+    log_archive: &LogArchive,
+    batch: &Batch,
+    runs: &[JobRun],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut job_runs: HashMap<&str, Vec<&JobRun>> = HashMap::new();
+    for run in runs {
+        if let Some(job) = batch.jobs.get(run.job_index) {
+            job_runs.entry(&job.job_id).or_default().push(run);
+        }
+    }
+    if job_runs.is_empty() {
+        return Ok(());
+    }
+
+    for job_runs in job_runs.values_mut() {
+        job_runs.sort_by(|a, b| (&a.created, &a.name).cmp(&(&b.created, &b.name)));
+    }
+    let fields: Vec<(&str, String)> = job_runs
+        .iter()
+        .map(|(job_id, runs)| {
+            let names: Vec<&String> = runs.iter().map(|run| &run.name).collect();
+            (*job_id, serde_json::to_string(&names).unwrap_or_default())
+        })
+        .collect();
+    let _: () = conn.hset_multiple(keys.job_pods(&batch.batch_id), &fields)?;
+
+    let archived: HashSet<String> = conn.smembers(keys.archived_pods(&batch.batch_id))?;
+    for (job_id, job_runs) in &job_runs {
+        for run in job_runs
+            .iter()
+            .filter(|run| run.finished && !archived.contains(&run.name))
+        {
+            match executor.logs(batch, run).await {
+                Ok(logs) => {
+                    log_archive
+                        .write(conn, keys, &batch.batch_id, job_id, logs)
+                        .await?;
+                    let _: () = conn.sadd(keys.archived_pods(&batch.batch_id), &run.name)?;
+                }
+                Err(err) => eprintln!("Unable to archive the logs of {}: {}", run.name, err),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the quota a queued or running batch holds. Batches queued before the gateway recorded
+/// it fall back to the batch itself, when it can still be read.
+fn held_quota<C: redis::ConnectionLike>(
+    conn: &mut C,
+    keys: &Keys,
+    batch_id: &str,
+    batch: Option<&Batch>,
+) -> Result<Option<HeldQuota>, Box<dyn std::error::Error>> {
+    let held: Option<String> = conn.hget(keys.held_quota(), batch_id)?;
+    let held = held
+        .and_then(|held| serde_json::from_str(&held).ok())
+        .or_else(|| batch.map(HeldQuota::for_batch));
+    if held.is_none() {
+        eprintln!("Unable to release the quota held by batch {}", batch_id);
+    }
+
+    Ok(held)
+}
+
+/// Adds releasing the quota a batch holds to a pipeline, once it leaves the queue or the
+/// running set for good. A batch leaving the queue also frees its place in the queue quota.
+fn release_quota(
+    pipe: &mut redis::Pipeline,
+    keys: &Keys,
+    batch_id: &str,
+    held: Option<&HeldQuota>,
+    queued: bool,
+) {
+    if let Some(held) = held {
+        for account in &held.accounts {
+            if queued {
+                pipe.hincr(keys.queued_batches_count(), account, -1);
+            }
+            pipe.hincr(keys.active_jobs_count(), account, -(held.jobs as i64));
+        }
+    }
+    pipe.hdel(keys.held_quota(), batch_id);
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Reads a batch, None if it was deleted. Failing to deserialize the batch is kept apart from
+/// failing to reach Redis, a batch that can't be read never will be.
+fn get_batch<C: redis::ConnectionLike>(
+    conn: &mut C,
+    keys: &Keys,
+    batch_id: &str,
+) -> redis::RedisResult<Option<Result<Batch, batch::Error>>> {
+    let json_batch: Option<String> = conn.get(keys.batch(batch_id))?;
+
+    Ok(json_batch.map(|json_batch| Batch::from_json(&json_batch)))
+}
+
+/// Adds the IDs of content addressed jobs that completed successfully to their owner's
+/// `succeeded_jobs` set, so the gateway can skip them if the owner submits them again
+fn record_succeeded_jobs<C: redis::ConnectionLike>(
+    conn: &mut C,
+    keys: &Keys,
+    batch: &Batch,
+    succeeded: &[usize],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let succeeded: Vec<&String> = succeeded
+        .iter()
+        .filter_map(|index| batch.jobs.get(*index))
+        .filter(|job| batch.is_content_addressed(job))
+        .map(|job| &job.job_id)
+        .collect();
+    if !succeeded.is_empty() {
+        let _: () = conn.sadd(keys.succeeded_jobs(batch.owner()), succeeded)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::executor::{BatchStatus, Executor, JobRun};
+    use crate::log_archive::LogArchive;
+    use crate::{launch_queued_batches, reconcile_running_batches, unix_now};
+    use async_trait::async_trait;
+    use redis::Commands;
+    use rft_core::batch::Batch;
+    use rft_core::fake_redis::{FakeConnection, FakeRedis};
+    use rft_core::job::Job;
+    use rft_core::log_store::LogStore;
+    use rft_core::queue::{self, HeldQuota, Keys};
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::error::Error;
+    use std::fmt;
+
+    /// Runs nothing, a batch finishes once a test sets its status
+    #[derive(Default)]
+    struct FakeExecutor {
+        /// batches that fail to start
+        broken: Vec<String>,
+        /// batches whose status can't be read
+        unreachable: Vec<String>,
+        submitted: RefCell<Vec<String>>,
+        cancelled: RefCell<Vec<String>>,
+        forgotten: RefCell<Vec<String>>,
+        statuses: RefCell<HashMap<String, BatchStatus>>,
+    }
+
+    impl fmt::Display for FakeExecutor {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "nowhere")
+        }
+    }
+
+    #[async_trait(?Send)]
+    impl Executor for FakeExecutor {
+        async fn submit(&self, batch: &Batch) -> Result<(), Box<dyn Error>> {
+            if self.broken.contains(&batch.batch_id) {
+                return Err(format!("batch {} is broken", batch.batch_id).into());
+            }
+            self.submitted.borrow_mut().push(batch.batch_id.clone());
+            Ok(())
+        }
+
+        async fn status(&self, batch: &Batch) -> Result<BatchStatus, Box<dyn Error>> {
+            if self.unreachable.contains(&batch.batch_id) {
+                return Err(format!("batch {} is unreachable", batch.batch_id).into());
+            }
+            Ok(self
+                .statuses
+                .borrow_mut()
+                .remove(&batch.batch_id)
+                .unwrap_or_default())
+        }
+
+        async fn cancel(&self, batch_id: &str) -> Result<(), Box<dyn Error>> {
+            self.cancelled.borrow_mut().push(batch_id.to_string());
+            Ok(())
+        }
+
+        async fn forget(&self, batch_id: &str) {
+            self.forgotten.borrow_mut().push(batch_id.to_string());
+        }
+
+        async fn logs(&self, _batch: &Batch, run: &JobRun) -> Result<String, Box<dyn Error>> {
+            Ok(format!("logs of {}", run.name))
+        }
+    }
+
+    fn batch(batch_id: &str, jobs: usize) -> Batch {
+        let mut batch = Batch::new("matt", "main.py", "git@github.com:x/y.git", "main");
+        batch.batch_id = batch_id.to_string();
+        for _ in 0..jobs {
+            batch.jobs.push(Job::new(HashMap::new()));
+        }
+        batch
+    }
+
+    /// Queues a batch the way the gateway does
+    fn queue_batch(conn: &mut FakeConnection, keys: &Keys, batch: &Batch) {
+        let held = HeldQuota::for_batch(batch);
+        let _: () = conn
+            .set(
+                keys.batch(&batch.batch_id),
+                serde_json::to_string(batch).unwrap(),
+            )
+            .unwrap();
+        let _: () = conn
+            .rpush(keys.user_queue(batch.owner()), &batch.batch_id)
+            .unwrap();
+        let _: () = conn.sadd(keys.queued_users(), batch.owner()).unwrap();
+        let _: () = conn
+            .hset(
+                keys.held_quota(),
+                &batch.batch_id,
+                serde_json::to_string(&held).unwrap(),
+            )
+            .unwrap();
+        let _: () = conn.hincr(keys.queued_batches_count(), "matt", 1).unwrap();
+        let _: () = conn
+            .hincr(keys.active_jobs_count(), "matt", held.jobs)
+            .unwrap();
+    }
+
+    /// matt's queued batches and active jobs
+    fn usage(conn: &mut FakeConnection, keys: &Keys) -> (i64, i64) {
+        let queued: Option<i64> = conn.hget(keys.queued_batches_count(), "matt").unwrap();
+        let active: Option<i64> = conn.hget(keys.active_jobs_count(), "matt").unwrap();
+        (queued.unwrap_or_default(), active.unwrap_or_default())
+    }
+
+    fn completed(cpu_seconds: f64, runs: Vec<JobRun>) -> BatchStatus {
+        BatchStatus {
+            outcome: Some("completed"),
+            cpu_seconds,
+            runs,
+            ..BatchStatus::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn launches_batches_up_to_the_limit() {
+        let mut conn = FakeRedis::new().connection();
+        let keys = Keys::new("rft:");
+        let executor = FakeExecutor::default();
+        queue_batch(&mut conn, &keys, &batch("b1", 2));
+        queue_batch(&mut conn, &keys, &batch("b2", 1));
+
+        launch_queued_batches(&executor, &mut conn, &keys, 1)
+            .await
+            .unwrap();
+
+        let submitted = executor.submitted.borrow().clone();
+        assert_eq!(submitted.len(), 1);
+        let running: Vec<String> = conn.smembers(keys.running_batches()).unwrap();
+        assert_eq!(running, submitted);
+        let queued: Vec<String> = conn.lrange(keys.user_queue("matt"), 0, -1).unwrap();
+        assert_eq!(queued.len(), 1);
+        assert_eq!(usage(&mut conn, &keys), (1, 3));
+    }
+
+    #[tokio::test]
+    async fn completed_batches_release_their_quota() {
+        let mut conn = FakeRedis::new().connection();
+        let keys = Keys::new("rft:");
+        let executor = FakeExecutor::default();
+        let log_archive = LogArchive::new(LogStore::Redis { max_bytes: None });
+        let first = batch("b1", 2);
+        queue_batch(&mut conn, &keys, &first);
+        launch_queued_batches(&executor, &mut conn, &keys, 5)
+            .await
+            .unwrap();
+
+        let run = JobRun {
+            job_index: 1,
+            name: "b1-1".to_string(),
+            created: "2021-06-01T00:00:00Z".to_string(),
+            finished: true,
+        };
+        executor
+            .statuses
+            .borrow_mut()
+            .insert("b1".to_string(), completed(7200.0, vec![run]));
+        reconcile_running_batches(&executor, &mut conn, &keys, &log_archive)
+            .await
+            .unwrap();
+
+        let running: Vec<String> = conn.smembers(keys.running_batches()).unwrap();
+        assert!(running.is_empty());
+        assert_eq!(*executor.forgotten.borrow(), vec!["b1"]);
+        let outcome: Option<String> = conn.hget(keys.batch_outcomes(), "b1").unwrap();
+        assert_eq!(outcome.as_deref(), Some("completed"));
+        assert_eq!(usage(&mut conn, &keys), (0, 0));
+        let held: bool = conn.hexists(keys.held_quota(), "b1").unwrap();
+        assert!(!held);
+        let cpu_seconds: f64 = conn
+            .hget(keys.cpu_seconds(queue::epoch_day(unix_now())), "matt")
+            .unwrap();
+        assert_eq!(cpu_seconds, 7200.0);
+        let logs: Option<String> = conn
+            .hget(keys.job_logs("b1"), &first.jobs[1].job_id)
+            .unwrap();
+        assert!(logs.unwrap_or_default().contains("logs of b1-1"));
+    }
+
+    #[tokio::test]
+    async fn dependent_batches_wait_for_their_predecessors() {
+        let mut conn = FakeRedis::new().connection();
+        let keys = Keys::new("rft:");
+        let executor = FakeExecutor::default();
+        let log_archive = LogArchive::new(LogStore::Redis { max_bytes: None });
+        let mut second = batch("b2", 1);
+        second.depends_on = vec!["b1".to_string()];
+        queue_batch(&mut conn, &keys, &batch("b1", 1));
+        queue_batch(&mut conn, &keys, &second);
+
+        launch_queued_batches(&executor, &mut conn, &keys, 5)
+            .await
+            .unwrap();
+        assert_eq!(*executor.submitted.borrow(), vec!["b1"]);
+        let queued: Vec<String> = conn.lrange(keys.user_queue("matt"), 0, -1).unwrap();
+        assert_eq!(queued, vec!["b2"]);
+
+        executor
+            .statuses
+            .borrow_mut()
+            .insert("b1".to_string(), completed(0.0, Vec::new()));
+        reconcile_running_batches(&executor, &mut conn, &keys, &log_archive)
+            .await
+            .unwrap();
+        launch_queued_batches(&executor, &mut conn, &keys, 5)
+            .await
+            .unwrap();
+        assert_eq!(*executor.submitted.borrow(), vec!["b1", "b2"]);
+    }
+
+    #[tokio::test]
+    async fn unreadable_and_deleted_batches_release_their_quota() {
+        let mut conn = FakeRedis::new().connection();
+        let keys = Keys::new("rft:");
+        let executor = FakeExecutor::default();
+        let log_archive = LogArchive::new(LogStore::Redis { max_bytes: None });
+        queue_batch(&mut conn, &keys, &batch("b1", 2));
+        let _: () = conn.set(keys.batch("b1"), "{not a batch").unwrap();
+        queue_batch(&mut conn, &keys, &batch("b2", 1));
+
+        launch_queued_batches(&executor, &mut conn, &keys, 5)
+            .await
+            .unwrap();
+        assert_eq!(*executor.submitted.borrow(), vec!["b2"]);
+        let outcome: Option<String> = conn.hget(keys.batch_outcomes(), "b1").unwrap();
+        assert_eq!(outcome.as_deref(), Some("failed"));
+        assert_eq!(usage(&mut conn, &keys), (0, 1));
+
+        let _: () = conn.del(keys.batch("b2")).unwrap();
+        reconcile_running_batches(&executor, &mut conn, &keys, &log_archive)
+            .await
+            .unwrap();
+        assert_eq!(*executor.cancelled.borrow(), vec!["b2"]);
+        let running: Vec<String> = conn.smembers(keys.running_batches()).unwrap();
+        assert!(running.is_empty());
+        assert_eq!(usage(&mut conn, &keys), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn batches_that_fail_to_start_release_their_quota() {
+        let mut conn = FakeRedis::new().connection();
+        let keys = Keys::new("rft:");
+        let executor = FakeExecutor {
+            broken: vec!["b1".to_string()],
+            ..FakeExecutor::default()
+        };
+        queue_batch(&mut conn, &keys, &batch("b1", 2));
+        queue_batch(&mut conn, &keys, &batch("b2", 1));
+
+        launch_queued_batches(&executor, &mut conn, &keys, 5)
+            .await
+            .unwrap();
+
+        assert_eq!(*executor.submitted.borrow(), vec!["b2"]);
+        assert_eq!(*executor.cancelled.borrow(), vec!["b1"]);
+        let running: Vec<String> = conn.smembers(keys.running_batches()).unwrap();
+        assert_eq!(running, vec!["b2"]);
+        let outcome: Option<String> = conn.hget(keys.batch_outcomes(), "b1").unwrap();
+        assert_eq!(outcome.as_deref(), Some("failed"));
+        assert_eq!(usage(&mut conn, &keys), (0, 1));
+    }
+
+    #[tokio::test]
+    async fn unreachable_batches_dont_hold_up_the_others() {
+        let mut conn = FakeRedis::new().connection();
+        let keys = Keys::new("rft:");
+        let executor = FakeExecutor {
+            unreachable: vec!["b1".to_string()],
+            ..FakeExecutor::default()
+        };
+        let log_archive = LogArchive::new(LogStore::Redis { max_bytes: None });
+        queue_batch(&mut conn, &keys, &batch("b1", 2));
+        queue_batch(&mut conn, &keys, &batch("b2", 1));
+        launch_queued_batches(&executor, &mut conn, &keys, 5)
+            .await
+            .unwrap();
+
+        for batch_id in &["b1", "b2"] {
+            executor
+                .statuses
+                .borrow_mut()
+                .insert(batch_id.to_string(), completed(0.0, Vec::new()));
+        }
+        reconcile_running_batches(&executor, &mut conn, &keys, &log_archive)
+            .await
+            .unwrap();
+
+        let running: Vec<String> = conn.smembers(keys.running_batches()).unwrap();
+        assert_eq!(running, vec!["b1"]);
+        assert_eq!(*executor.forgotten.borrow(), vec!["b2"]);
+        let outcome: Option<String> = conn.hget(keys.batch_outcomes(), "b2").unwrap();
+        assert_eq!(outcome.as_deref(), Some("completed"));
+        assert_eq!(usage(&mut conn, &keys), (0, 2));
+    }
+
+    #[tokio::test]
+    async fn succeeded_jobs_are_recorded_for_their_owner() {
+        let mut conn = FakeRedis::new().connection();
+        let keys = Keys::new("rft:");
+        let executor = FakeExecutor::default();
+        let log_archive = LogArchive::new(LogStore::Redis { max_bytes: None });
+        let mut first = batch("b1", 1);
+        first.commit = Some("abc123".to_string());
+        first.jobs.push(Job::with_content_id(
+            "abc123",
+            &first.source_file,
+            HashMap::new(),
+        ));
+        queue_batch(&mut conn, &keys, &first);
+        launch_queued_batches(&executor, &mut conn, &keys, 5)
+            .await
+            .unwrap();
+
+        let status = BatchStatus {
+            succeeded: vec![0, 1],
+            ..completed(0.0, Vec::new())
+        };
+        executor
+            .statuses
+            .borrow_mut()
+            .insert("b1".to_string(), status);
+        reconcile_running_batches(&executor, &mut conn, &keys, &log_archive)
+            .await
+            .unwrap();
+
+        let succeeded: Vec<String> = conn.smembers(keys.succeeded_jobs("matt")).unwrap();
+        assert_eq!(succeeded, vec![first.jobs[1].job_id.clone()]);
+        let others: Vec<String> = conn.smembers(keys.succeeded_jobs("tom")).unwrap();
+        assert!(others.is_empty());
+    }
+}
@@ -1,8 +1,12 @@
-use crate::{job::Job, ID_ALPHA, ID_LENGTH};
+use crate::{
+    job::{content_id, Job},
+    schema::ParamSchema,
+    ID_ALPHA, ID_LENGTH,
+};
 use nanoid::nanoid;
 use serde::{Deserialize, Serialize};
 use snafu::{ResultExt, Snafu};
-use std::fmt;
+use std::{fmt, str::FromStr};
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -15,29 +19,129 @@ pub enum Error {
 
 type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// How urgently a batch should run. Higher priority batches are dequeued first and their pods
+/// use a higher Kubernetes PriorityClass, so urgent batches can preempt background sweeps.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+    Urgent,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 4] = [
+        Priority::Low,
+        Priority::Normal,
+        Priority::High,
+        Priority::Urgent,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+            Priority::Urgent => "urgent",
+        }
+    }
+
+    /// Position of the priority from lowest (0) to highest
+    pub fn rank(&self) -> u64 {
+        *self as u64
+    }
+
+    /// Name of the Kubernetes PriorityClass the batch's pods run with
+    pub fn priority_class(&self) -> String {
+        format!("rft-{}", self.as_str())
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for Priority {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Priority::ALL
+            .iter()
+            .find(|priority| priority.as_str() == s)
+            .copied()
+            .ok_or_else(|| {
+                format!(
+                    "Unknown priority '{}', expected low, normal, high or urgent",
+                    s
+                )
+            })
+    }
+}
+
+/// Generates a random batch ID
+pub fn new_batch_id() -> String {
+    nanoid!(ID_LENGTH, &ID_ALPHA)
+}
+
 /// Batch structure:
 /// {  
-///     "batch_id": "fkIopp4D_K",  
+///     "batch_id": "fkIopp4D_K" - Assigned by the gateway, any value sent by clients is replaced
 ///     "author": "Matt",
+///     "principal": "matt" - Set by the gateway, the authenticated user who submitted the batch
+///     "team": "research" - Set by the gateway, the team the principal belongs to
+///     "submitted_at": 1633046400 - Set by the gateway, unix timestamp the batch was accepted at
+///     "priority": "normal" - Optional, one of low, normal, high or urgent
 ///     "source_file": "examples/basic/main.py",
 ///     "repository_url": "git@github.com/retwolf/rft",
 ///     "branch": "master",
+///     "commit": "3f1c2a9e..." - Optional, the commit the batch was submitted from
+///     "schema": {...} - Optional, see ParamSchema for this format
+///     "depends_on": ["aZ3kLp0qWe"] - Optional, batches that must complete before this one runs
 ///     "jobs": [
 ///         {...} - See job structure below for this format
 ///     ]
 /// }
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Batch {
-    /// a short nanoid representing the batch
+    /// a short nanoid representing the batch, assigned by the gateway
+    #[serde(default)]
     pub batch_id: String,
     /// the author of the batch
     pub author: String,
+    /// the authenticated user who submitted the batch, set by the gateway
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub principal: Option<String>,
+    /// the team of the user who submitted the batch, set by the gateway
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub team: Option<String>,
+    /// unix timestamp the gateway accepted the batch at
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub submitted_at: Option<u64>,
+    /// how urgently the batch should run, normal unless set
+    #[serde(default)]
+    pub priority: Priority,
     /// relative path from the root of a git repository to the file to be ran for this batch
     pub source_file: String,
     /// the repository url to download the source code from
     pub repository_url: String,
     /// the git branch to checkout and execute from
     pub branch: String,
+    /// the commit the batch was submitted from, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    /// the types of the params used by jobs in this batch, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<ParamSchema>,
+    /// IDs of the batches that must complete before this batch runs. It waits in the queue
+    /// until they do, and fails if any of them fails.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<String>,
     /// a list of jobs to be executed in this batch
     pub jobs: Vec<Job>,
 }
@@ -45,15 +149,52 @@ pub struct Batch {
 impl Batch {
     pub fn new(author: &str, source_file: &str, repository_url: &str, branch: &str) -> Batch {
         Batch {
-            batch_id: nanoid!(ID_LENGTH, &ID_ALPHA),
+            batch_id: new_batch_id(),
             author: author.to_string(),
+            principal: None,
+            team: None,
+            submitted_at: None,
+            priority: Priority::default(),
             source_file: source_file.to_string(),
             repository_url: repository_url.to_string(),
             branch: branch.to_string(),
+            commit: None,
+            schema: None,
+            depends_on: Vec::new(),
             jobs: Vec::<Job>::new(),
         }
     }
 
+    /// The user quotas and fair-share scheduling are applied to. This is the authenticated
+    /// principal when the gateway recorded one, otherwise the self-reported author.
+    pub fn owner(&self) -> &str {
+        self.principal.as_deref().unwrap_or(&self.author)
+    }
+
+    /// Replaces the batch ID, and the IDs of jobs not derived from their content, with newly
+    /// generated ones so clients can't choose IDs that collide with another user's batch
+    pub fn assign_ids(&mut self) {
+        self.batch_id = new_batch_id();
+        let jobs = std::mem::take(&mut self.jobs);
+        self.jobs = jobs
+            .into_iter()
+            .map(|mut job| {
+                if !self.is_content_addressed(&job) {
+                    job.job_id = nanoid!(ID_LENGTH, &ID_ALPHA);
+                }
+                job
+            })
+            .collect();
+    }
+
+    /// Whether a job's ID was derived from its content rather than randomly generated
+    pub fn is_content_addressed(&self, job: &Job) -> bool {
+        match &self.commit {
+            Some(commit) => content_id(commit, &self.source_file, &job.params) == job.job_id,
+            None => false,
+        }
+    }
+
     pub fn from_json(json: &str) -> Result<Batch> {
         serde_json::from_str(json).context(DeserializeFailed {
             batch: json.to_string(),
@@ -65,9 +206,19 @@ impl fmt::Display for Batch {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "batch_id: {}", &self.batch_id).unwrap_or(());
         writeln!(f, "author: {}", &self.author).unwrap_or(());
+        if let Some(principal) = &self.principal {
+            writeln!(f, "principal: {}", principal).unwrap_or(());
+        }
+        if let Some(team) = &self.team {
+            writeln!(f, "team: {}", team).unwrap_or(());
+        }
+        writeln!(f, "priority: {}", &self.priority).unwrap_or(());
         writeln!(f, "source_file: {}", &self.source_file).unwrap_or(());
         writeln!(f, "repository_url: {}", &self.repository_url).unwrap_or(());
         writeln!(f, "branch: {}", &self.branch).unwrap_or(());
+        if let Some(commit) = &self.commit {
+            writeln!(f, "commit: {}", commit).unwrap_or(());
+        }
         writeln!(f, "jobs: ").unwrap_or(());
         for job in &self.jobs.clone() {
             write!(f, "{}", &job).unwrap_or(());
@@ -79,7 +230,9 @@ impl fmt::Display for Batch {
 
 #[cfg(test)]
 mod tests {
-    use crate::batch::Batch;
+    use crate::batch::{Batch, Priority};
+    use crate::job::Job;
+    use std::collections::HashMap;
 
     #[test]
     fn deserialize_batch() {
@@ -105,6 +258,8 @@ mod tests {
             Batch::from_json(&valid_batch_json).expect("Should successfully deserialize JSON");
 
         assert!(test_batch.batch_id == "fkIopp4D_K");
+        assert!(test_batch.schema.is_none());
+        assert_eq!(test_batch.priority, Priority::Normal);
 
         let invalid_batch_json = r#"
         {
@@ -114,4 +269,30 @@ mod tests {
 
         Batch::from_json(&invalid_batch_json).expect_err("Should produce a deserialization error.");
     }
+
+    #[test]
+    fn assign_ids_keeps_content_ids() {
+        let mut batch = Batch::new(
+            "matt",
+            "main.py",
+            "git@github.com:retwolf/rft.git",
+            "master",
+        );
+        batch.commit = Some("3f1c2a9e".to_string());
+        batch
+            .jobs
+            .push(Job::with_content_id("3f1c2a9e", "main.py", HashMap::new()));
+        batch.jobs.push(Job::new(HashMap::new()));
+        let (batch_id, content_id, random_id) = (
+            batch.batch_id.clone(),
+            batch.jobs[0].job_id.clone(),
+            batch.jobs[1].job_id.clone(),
+        );
+
+        batch.assign_ids();
+
+        assert_ne!(batch.batch_id, batch_id);
+        assert_eq!(batch.jobs[0].job_id, content_id);
+        assert_ne!(batch.jobs[1].job_id, random_id);
+    }
 }
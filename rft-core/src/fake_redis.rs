@@ -0,0 +1,581 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "async-test-util")]
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+#[cfg(feature = "async-test-util")]
+use redis::{Cmd, Pipeline, RedisFuture};
+use redis::{ErrorKind, RedisError, RedisResult, Value};
+
+/// An in-memory stand-in for a Redis server, for testing the gateway and controller without
+/// one. Supports the commands they use, including WATCH and MULTI/EXEC. Expiry is ignored.
+#[derive(Clone, Default)]
+pub struct FakeRedis {
+    data: Arc<Mutex<Data>>,
+}
+
+impl FakeRedis {
+    pub fn new() -> FakeRedis {
+        FakeRedis::default()
+    }
+
+    /// Opens a connection. With `async-test-util` it is an async connection too, whose commands
+    /// yield to the runtime first so concurrent requests interleave like they would against a
+    /// real server.
+    pub fn connection(&self) -> FakeConnection {
+        FakeConnection {
+            data: self.data.clone(),
+            watched: Vec::new(),
+            transaction: None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Data {
+    entries: HashMap<String, Entry>,
+    /// bumped on every write to a key, WATCH compares them to detect changes
+    versions: HashMap<String, u64>,
+    clock: u64,
+}
+
+enum Entry {
+    String(String),
+    Hash(BTreeMap<String, String>),
+    Set(BTreeSet<String>),
+    List(Vec<String>),
+}
+
+pub struct FakeConnection {
+    data: Arc<Mutex<Data>>,
+    watched: Vec<(String, u64)>,
+    transaction: Option<Vec<Vec<String>>>,
+}
+
+impl FakeConnection {
+    /// Runs every command in a packed request, returning a response for each
+    fn run_packed(&mut self, packed: &[u8]) -> RedisResult<Vec<Value>> {
+        let mut responses = Vec::new();
+        let mut rest = packed;
+        while !rest.is_empty() {
+            let (args, remaining) = unpack(rest).ok_or_else(|| error("expected a command"))?;
+            rest = remaining;
+            responses.push(self.run(args)?);
+        }
+
+        Ok(responses)
+    }
+
+    fn run(&mut self, args: Vec<String>) -> RedisResult<Value> {
+        let name = args
+            .first()
+            .map(|name| name.to_uppercase())
+            .unwrap_or_default();
+        match (name.as_str(), self.transaction.as_mut()) {
+            ("MULTI", _) => {
+                self.transaction = Some(Vec::new());
+                Ok(Value::Okay)
+            }
+            ("EXEC", Some(_)) => {
+                let queued = self.transaction.take().unwrap_or_default();
+                let watched = std::mem::take(&mut self.watched);
+                let mut data = self.data.lock().unwrap();
+                if watched
+                    .iter()
+                    .any(|(key, version)| data.version(key) != *version)
+                {
+                    return Ok(Value::Nil);
+                }
+                let results = queued
+                    .into_iter()
+                    .map(|args| data.run(&args))
+                    .collect::<RedisResult<Vec<Value>>>()?;
+                Ok(Value::Bulk(results))
+            }
+            ("DISCARD", Some(_)) => {
+                self.transaction = None;
+                self.watched.clear();
+                Ok(Value::Okay)
+            }
+            (_, Some(queued)) => {
+                queued.push(args);
+                Ok(Value::Status("QUEUED".to_string()))
+            }
+            ("WATCH", None) => {
+                let data = self.data.lock().unwrap();
+                for key in &args[1..] {
+                    self.watched.push((key.clone(), data.version(key)));
+                }
+                Ok(Value::Okay)
+            }
+            ("UNWATCH", None) => {
+                self.watched.clear();
+                Ok(Value::Okay)
+            }
+            _ => self.data.lock().unwrap().run(&args),
+        }
+    }
+}
+
+impl Data {
+    fn version(&self, key: &str) -> u64 {
+        self.versions.get(key).copied().unwrap_or_default()
+    }
+
+    fn touch(&mut self, key: &str) {
+        self.clock += 1;
+        self.versions.insert(key.to_string(), self.clock);
+        // Redis deletes collections once they are empty
+        let empty = match self.entries.get(key) {
+            Some(Entry::Hash(hash)) => hash.is_empty(),
+            Some(Entry::Set(set)) => set.is_empty(),
+            Some(Entry::List(list)) => list.is_empty(),
+            _ => false,
+        };
+        if empty {
+            self.entries.remove(key);
+        }
+    }
+
+    fn hash(&mut self, key: &str) -> RedisResult<&mut BTreeMap<String, String>> {
+        match self
+            .entries
+            .entry(key.to_string())
+            .or_insert_with(|| Entry::Hash(BTreeMap::new()))
+        {
+            Entry::Hash(hash) => Ok(hash),
+            _ => Err(wrong_type()),
+        }
+    }
+
+    fn set(&mut self, key: &str) -> RedisResult<&mut BTreeSet<String>> {
+        match self
+            .entries
+            .entry(key.to_string())
+            .or_insert_with(|| Entry::Set(BTreeSet::new()))
+        {
+            Entry::Set(set) => Ok(set),
+            _ => Err(wrong_type()),
+        }
+    }
+
+    fn list(&mut self, key: &str) -> RedisResult<&mut Vec<String>> {
+        match self
+            .entries
+            .entry(key.to_string())
+            .or_insert_with(|| Entry::List(Vec::new()))
+        {
+            Entry::List(list) => Ok(list),
+            _ => Err(wrong_type()),
+        }
+    }
+
+    fn run(&mut self, args: &[String]) -> RedisResult<Value> {
+        let name = args[0].to_uppercase();
+        let arg = |index: usize| -> RedisResult<&str> {
+            args.get(index)
+                .map(|arg| arg.as_str())
+                .ok_or_else(|| error("wrong number of arguments"))
+        };
+        let int = |index: usize| -> RedisResult<i64> {
+            arg(index)?
+                .parse::<i64>()
+                .map_err(|_| error("value is not an integer"))
+        };
+        let key = arg(1).unwrap_or_default().to_string();
+
+        let response = match name.as_str() {
+            "PING" => Value::Status("PONG".to_string()),
+            "GET" => match self.entries.get(&key) {
+                Some(Entry::String(value)) => data(value),
+                Some(_) => return Err(wrong_type()),
+                None => Value::Nil,
+            },
+            "SET" => {
+                let options: Vec<String> = args[3..].iter().map(|o| o.to_uppercase()).collect();
+                let exists = self.entries.contains_key(&key);
+                if (options.contains(&"NX".to_string()) && exists)
+                    || (options.contains(&"XX".to_string()) && !exists)
+                {
+                    return Ok(Value::Nil);
+                }
+                self.entries
+                    .insert(key.clone(), Entry::String(arg(2)?.to_string()));
+                self.touch(&key);
+                Value::Okay
+            }
+            "SETEX" => {
+                self.entries
+                    .insert(key.clone(), Entry::String(arg(3)?.to_string()));
+                self.touch(&key);
+                Value::Okay
+            }
+            "DEL" => {
+                let mut deleted = 0;
+                for key in &args[1..] {
+                    if self.entries.remove(key).is_some() {
+                        self.touch(key);
+                        deleted += 1;
+                    }
+                }
+                Value::Int(deleted)
+            }
+            "EXISTS" => Value::Int(
+                args[1..]
+                    .iter()
+                    .filter(|key| self.entries.contains_key(*key))
+                    .count() as i64,
+            ),
+            "EXPIRE" => Value::Int(self.entries.contains_key(&key) as i64),
+            "HGET" => match self.entries.get(&key) {
+                Some(Entry::Hash(hash)) => hash.get(arg(2)?).map(|v| data(v)).unwrap_or(Value::Nil),
+                Some(_) => return Err(wrong_type()),
+                None => Value::Nil,
+            },
+            "HEXISTS" => match self.entries.get(&key) {
+                Some(Entry::Hash(hash)) => Value::Int(hash.contains_key(arg(2)?) as i64),
+                Some(_) => return Err(wrong_type()),
+                None => Value::Int(0),
+            },
+            "HGETALL" => match self.entries.get(&key) {
+                Some(Entry::Hash(hash)) => Value::Bulk(
+                    hash.iter()
+                        .flat_map(|(field, value)| vec![data(field), data(value)])
+                        .collect(),
+                ),
+                Some(_) => return Err(wrong_type()),
+                None => Value::Bulk(Vec::new()),
+            },
+            "HSET" | "HMSET" => {
+                let pairs = &args[2..];
+                if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+                    return Err(error("wrong number of arguments"));
+                }
+                let hash = self.hash(&key)?;
+                let mut added = 0;
+                for pair in pairs.chunks(2) {
+                    if hash.insert(pair[0].clone(), pair[1].clone()).is_none() {
+                        added += 1;
+                    }
+                }
+                self.touch(&key);
+                match name.as_str() {
+                    "HSET" => Value::Int(added),
+                    _ => Value::Okay,
+                }
+            }
+            "HDEL" => {
+                let hash = self.hash(&key)?;
+                let deleted = args[2..]
+                    .iter()
+                    .filter(|field| hash.remove(*field).is_some())
+                    .count();
+                self.touch(&key);
+                Value::Int(deleted as i64)
+            }
+            "HINCRBY" => {
+                let by = int(3)?;
+                let hash = self.hash(&key)?;
+                let current = match hash.get(arg(2)?) {
+                    Some(value) => value
+                        .parse::<i64>()
+                        .map_err(|_| error("hash value is not an integer"))?,
+                    None => 0,
+                };
+                hash.insert(arg(2)?.to_string(), (current + by).to_string());
+                self.touch(&key);
+                Value::Int(current + by)
+            }
+            "HINCRBYFLOAT" => {
+                let by = arg(3)?
+                    .parse::<f64>()
+                    .map_err(|_| error("value is not a valid float"))?;
+                let hash = self.hash(&key)?;
+                let current = match hash.get(arg(2)?) {
+                    Some(value) => value
+                        .parse::<f64>()
+                        .map_err(|_| error("hash value is not a float"))?,
+                    None => 0.0,
+                };
+                let value = (current + by).to_string();
+                hash.insert(arg(2)?.to_string(), value.clone());
+                self.touch(&key);
+                data(&value)
+            }
+            "SADD" => {
+                let set = self.set(&key)?;
+                let added = args[2..]
+                    .iter()
+                    .filter(|member| set.insert(member.to_string()))
+                    .count();
+                self.touch(&key);
+                Value::Int(added as i64)
+            }
+            "SREM" => {
+                let set = self.set(&key)?;
+                let removed = args[2..]
+                    .iter()
+                    .filter(|member| set.remove(*member))
+                    .count();
+                self.touch(&key);
+                Value::Int(removed as i64)
+            }
+            "SMEMBERS" => match self.entries.get(&key) {
+                Some(Entry::Set(set)) => Value::Bulk(set.iter().map(|m| data(m)).collect()),
+                Some(_) => return Err(wrong_type()),
+                None => Value::Bulk(Vec::new()),
+            },
+            "SISMEMBER" => match self.entries.get(&key) {
+                Some(Entry::Set(set)) => Value::Int(set.contains(arg(2)?) as i64),
+                Some(_) => return Err(wrong_type()),
+                None => Value::Int(0),
+            },
+            "SCARD" => match self.entries.get(&key) {
+                Some(Entry::Set(set)) => Value::Int(set.len() as i64),
+                Some(_) => return Err(wrong_type()),
+                None => Value::Int(0),
+            },
+            "RPUSH" | "LPUSH" => {
+                let list = self.list(&key)?;
+                for value in &args[2..] {
+                    match name.as_str() {
+                        "RPUSH" => list.push(value.clone()),
+                        _ => list.insert(0, value.clone()),
+                    }
+                }
+                let len = list.len();
+                self.touch(&key);
+                Value::Int(len as i64)
+            }
+            "LRANGE" => match self.entries.get(&key) {
+                Some(Entry::List(list)) => {
+                    let (start, stop) = list_range(list.len(), int(2)?, int(3)?);
+                    Value::Bulk(list[start..stop].iter().map(|v| data(v)).collect())
+                }
+                Some(_) => return Err(wrong_type()),
+                None => Value::Bulk(Vec::new()),
+            },
+            "LLEN" => match self.entries.get(&key) {
+                Some(Entry::List(list)) => Value::Int(list.len() as i64),
+                Some(_) => return Err(wrong_type()),
+                None => Value::Int(0),
+            },
+            "LREM" => {
+                let count = int(2)?;
+                let value = arg(3)?.to_string();
+                let list = self.list(&key)?;
+                let limit = match count {
+                    0 => usize::MAX,
+                    count => count.unsigned_abs() as usize,
+                };
+                let mut positions: Vec<usize> = (0..list.len())
+                    .filter(|index| list[*index] == value)
+                    .collect();
+                if count < 0 {
+                    positions.reverse();
+                }
+                positions.truncate(limit);
+                positions.sort_unstable();
+                for index in positions.iter().rev() {
+                    list.remove(*index);
+                }
+                self.touch(&key);
+                Value::Int(positions.len() as i64)
+            }
+            "LTRIM" => {
+                let (start, stop) = (int(2)?, int(3)?);
+                let list = self.list(&key)?;
+                let (start, stop) = list_range(list.len(), start, stop);
+                *list = list[start..stop].to_vec();
+                self.touch(&key);
+                Value::Okay
+            }
+            _ => return Err(error("unknown command")),
+        };
+
+        Ok(response)
+    }
+}
+
+/// Reads one packed command, i.e. `*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n`, returning its arguments
+/// and what follows it
+fn unpack(packed: &[u8]) -> Option<(Vec<String>, &[u8])> {
+    fn line(packed: &[u8], prefix: u8) -> Option<(usize, &[u8])> {
+        let end = packed.windows(2).position(|w| w == b"\r\n")?;
+        if packed.first() != Some(&prefix) {
+            return None;
+        }
+        let number = std::str::from_utf8(&packed[1..end]).ok()?.parse().ok()?;
+        Some((number, &packed[end + 2..]))
+    }
+
+    let (count, mut rest) = line(packed, b'*')?;
+    let mut args = Vec::with_capacity(count);
+    for _ in 0..count {
+        let (len, data) = line(rest, b'$')?;
+        args.push(String::from_utf8_lossy(data.get(..len)?).to_string());
+        rest = data.get(len + 2..)?;
+    }
+
+    Some((args, rest))
+}
+
+/// Converts an inclusive Redis range, which may count from the end, into a slice range
+fn list_range(len: usize, start: i64, stop: i64) -> (usize, usize) {
+    let resolve = |index: i64| {
+        if index < 0 {
+            (len as i64 + index).max(0)
+        } else {
+            index
+        }
+    };
+    let start = resolve(start).min(len as i64) as usize;
+    let stop = (resolve(stop) + 1).min(len as i64) as usize;
+
+    (start, stop.max(start))
+}
+
+fn data(value: &str) -> Value {
+    Value::Data(value.as_bytes().to_vec())
+}
+
+fn error(message: &'static str) -> RedisError {
+    RedisError::from((ErrorKind::ResponseError, message))
+}
+
+fn wrong_type() -> RedisError {
+    RedisError::from((
+        ErrorKind::TypeError,
+        "Operation against a key holding the wrong kind of value",
+    ))
+}
+
+impl redis::ConnectionLike for FakeConnection {
+    fn req_packed_command(&mut self, cmd: &[u8]) -> RedisResult<Value> {
+        self.run_packed(cmd)?
+            .pop()
+            .ok_or_else(|| error("expected a command"))
+    }
+
+    fn req_packed_commands(
+        &mut self,
+        cmd: &[u8],
+        offset: usize,
+        count: usize,
+    ) -> RedisResult<Vec<Value>> {
+        Ok(self
+            .run_packed(cmd)?
+            .into_iter()
+            .skip(offset)
+            .take(count)
+            .collect())
+    }
+
+    fn get_db(&self) -> i64 {
+        0
+    }
+
+    fn check_connection(&mut self) -> bool {
+        true
+    }
+
+    fn is_open(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(feature = "async-test-util")]
+impl redis::aio::ConnectionLike for FakeConnection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        Box::pin(async move {
+            YieldNow(false).await;
+            redis::ConnectionLike::req_packed_command(self, &cmd.get_packed_command())
+        })
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        Box::pin(async move {
+            YieldNow(false).await;
+            redis::ConnectionLike::req_packed_commands(
+                self,
+                &cmd.get_packed_pipeline(),
+                offset,
+                count,
+            )
+        })
+    }
+
+    fn get_db(&self) -> i64 {
+        0
+    }
+}
+
+/// Returns to the runtime once before completing, like waiting on a socket would
+#[cfg(feature = "async-test-util")]
+struct YieldNow(bool);
+
+#[cfg(feature = "async-test-util")]
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fake_redis::FakeRedis;
+    use redis::Commands;
+
+    #[test]
+    fn exec_fails_after_a_watched_key_changes() {
+        let redis = FakeRedis::new();
+        let (mut first, mut second) = (redis.connection(), redis.connection());
+
+        let _: () = redis::cmd("WATCH").arg("count").query(&mut first).unwrap();
+        let _: i64 = second.hincr("count", "matt", 1).unwrap();
+        let result: Option<(i64,)> = redis::pipe()
+            .atomic()
+            .hincr("count", "matt", 1)
+            .query(&mut first)
+            .unwrap();
+        assert_eq!(result, None);
+
+        let _: () = redis::cmd("WATCH").arg("count").query(&mut first).unwrap();
+        let result: Option<(i64,)> = redis::pipe()
+            .atomic()
+            .hincr("count", "matt", 1)
+            .query(&mut first)
+            .unwrap();
+        assert_eq!(result, Some((2,)));
+    }
+
+    #[test]
+    fn lists_and_sets() {
+        let mut conn = FakeRedis::new().connection();
+        let _: () = conn.rpush("queue", &["a", "b", "a", "c"]).unwrap();
+        let _: () = conn.lrem("queue", 1, "a").unwrap();
+        let queue: Vec<String> = conn.lrange("queue", 0, -1).unwrap();
+        assert_eq!(queue, vec!["b", "a", "c"]);
+
+        let _: () = conn.sadd("users", "matt").unwrap();
+        let _: () = conn.srem("users", "matt").unwrap();
+        let exists: bool = conn.exists("users").unwrap();
+        assert!(!exists);
+    }
+}
@@ -1,31 +1,73 @@
 use nanoid::nanoid;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fmt};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
 
 use crate::{ID_ALPHA, ID_LENGTH};
 
 // Job structure:
 // {
-//     "job_id": "EKKFKWaBJZ",
+//     "job_id": "EKKFKWaBJZ", - Assigned by the gateway unless derived from the job's content
 //     "params": {
-//         "start_date": "1980",
-//         "end_date": "2020"
+//         "start_date": 1980,
+//         "end_date": 2020,
+//         "region": "us-east-1"
 //     }
 // }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Job {
+    #[serde(default)]
     pub job_id: String,
-    pub params: HashMap<String, String>,
+    pub params: HashMap<String, Value>,
 }
 
+/// Length of content addressed job IDs. Longer than random IDs since collisions
+/// between unrelated jobs would cause one to be skipped as already succeeded.
+static CONTENT_ID_LENGTH: usize = 16;
+
 impl Job {
-    pub fn new(params: HashMap<String, String>) -> Job {
+    pub fn new(params: HashMap<String, Value>) -> Job {
         Job {
             job_id: nanoid!(ID_LENGTH, &ID_ALPHA),
             params,
         }
     }
+
+    /// Creates a job whose ID is derived from the commit, source file and params it runs with,
+    /// so resubmitting the same work always produces the same job ID
+    pub fn with_content_id(commit: &str, source_file: &str, params: HashMap<String, Value>) -> Job {
+        Job {
+            job_id: content_id(commit, source_file, &params),
+            params,
+        }
+    }
+}
+
+/// A stable representation of a set of params, independent of map ordering
+pub fn canonical_params(params: &HashMap<String, Value>) -> String {
+    let sorted: BTreeMap<&String, &Value> = params.iter().collect();
+    serde_json::to_string(&sorted).unwrap_or_default()
+}
+
+/// Hashes the commit, source file and params into a hex job ID
+pub fn content_id(commit: &str, source_file: &str, params: &HashMap<String, Value>) -> String {
+    let content = json!({
+        "commit": commit,
+        "source_file": source_file,
+        "params": canonical_params(params),
+    });
+    let digest = Sha256::digest(content.to_string().as_bytes());
+
+    digest
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>()[..CONTENT_ID_LENGTH]
+        .to_string()
 }
 
 impl fmt::Display for Job {
@@ -33,9 +75,34 @@ impl fmt::Display for Job {
         writeln!(f, "job_id: {}", &self.job_id).unwrap_or(());
         writeln!(f, "params: ").unwrap_or(());
         for (key, val) in &self.params.clone() {
-            writeln!(f, "  '{}': '{}'", key, val).unwrap_or(());
+            writeln!(f, "  '{}': {}", key, val).unwrap_or(());
         }
 
         write!(f, "")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::job::Job;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    #[test]
+    fn content_ids_are_deterministic() {
+        let mut params = HashMap::new();
+        params.insert("start_date".to_string(), json!(1980));
+        params.insert("end_date".to_string(), json!(2020));
+
+        let first = Job::with_content_id("abc123", "main.py", params.clone());
+        let second = Job::with_content_id("abc123", "main.py", params.clone());
+        assert_eq!(first.job_id, second.job_id);
+
+        let other_commit = Job::with_content_id("def456", "main.py", params.clone());
+        assert_ne!(first.job_id, other_commit.job_id);
+
+        params.insert("end_date".to_string(), json!("2020"));
+        let other_params = Job::with_content_id("abc123", "main.py", params);
+        assert_ne!(first.job_id, other_params.job_id);
+    }
+}
@@ -0,0 +1,80 @@
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+
+/// Prefix of every job token, which tells them apart from users' API tokens
+pub static JOB_TOKEN_PREFIX: &str = "rftjob_";
+
+/// Signs and checks per-job tokens, which let a running job report its progress, metrics and
+/// result to the gateway without holding an API token. A token is an HMAC of the batch and job
+/// ID, so it only grants access to that one job. The controller and gateway share the key,
+/// read from the JOB_TOKEN_SECRET environment variable.
+#[derive(Clone)]
+pub struct JobTokens {
+    secret: String,
+}
+
+impl JobTokens {
+    pub fn new(secret: impl Into<String>) -> JobTokens {
+        JobTokens {
+            secret: secret.into(),
+        }
+    }
+
+    /// Reads the key through `lookup`, returning None if job tokens aren't configured
+    pub fn from_lookup<F>(lookup: F) -> Option<JobTokens>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        lookup("JOB_TOKEN_SECRET")
+            .filter(|secret| !secret.is_empty())
+            .map(JobTokens::new)
+    }
+
+    /// The token for a single job, i.e. `rftjob_3f1c...`
+    pub fn token(&self, batch_id: &str, job_id: &str) -> String {
+        format!("{}{}", JOB_TOKEN_PREFIX, self.signature(batch_id, job_id))
+    }
+
+    /// Whether `token` was issued for the job, compared in constant time
+    pub fn verify(&self, batch_id: &str, job_id: &str, token: &str) -> bool {
+        let signature = match token.strip_prefix(JOB_TOKEN_PREFIX) {
+            Some(signature) => signature,
+            None => return false,
+        };
+        let expected = self.signature(batch_id, job_id);
+
+        expected.len() == signature.len()
+            && expected
+                .bytes()
+                .zip(signature.bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+
+    fn signature(&self, batch_id: &str, job_id: &str) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(self.secret.as_bytes()).expect("HMAC accepts any key");
+        mac.update(format!("{}/{}", batch_id, job_id).as_bytes());
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::job_token::JobTokens;
+
+    #[test]
+    fn tokens_only_grant_their_job() {
+        let tokens = JobTokens::new("secret");
+        let token = tokens.token("abc", "job1");
+        assert!(token.starts_with("rftjob_"));
+        assert!(tokens.verify("abc", "job1", &token));
+        assert!(!tokens.verify("abc", "job2", &token));
+        assert!(!tokens.verify("abc", "job1", &token[7..]));
+        assert!(!JobTokens::new("other").verify("abc", "job1", &token));
+    }
+}
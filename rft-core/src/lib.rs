@@ -1,5 +1,16 @@
+pub mod artifacts;
 pub mod batch;
+#[cfg(feature = "test-util")]
+pub mod fake_redis;
 pub mod job;
+pub mod job_token;
+pub mod local;
+pub mod log_store;
+pub mod manifest;
+pub mod queue;
+#[cfg(feature = "redis")]
+pub mod redis_config;
+pub mod schema;
 
 static ID_LENGTH: usize = 10;
 static ID_ALPHA: [char; 36] = [
@@ -0,0 +1,239 @@
+use serde_json::{json, Value};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::artifacts::OUTPUT_DIR;
+use crate::batch::Batch;
+use crate::job::Job;
+
+/// File in a job's directory the worker's stdout and stderr are written to
+pub static LOG_FILE: &str = "worker.log";
+/// File in a job's directory the worker SDKs append progress, metrics and results to
+pub static REPORT_FILE: &str = "reports.jsonl";
+
+/// The directory a job runs in on the local machine, `<run_dir>/<job_id>`, laid out like a
+/// pod's volumes: `input/data.json` and `input/job_id` for the worker to read, `output/` for
+/// its artifacts, plus the worker's log and the reports it made
+pub struct JobDir {
+    pub root: PathBuf,
+}
+
+impl JobDir {
+    pub fn new(run_dir: &Path, job_id: &str) -> JobDir {
+        JobDir {
+            root: run_dir.join(job_id),
+        }
+    }
+
+    pub fn data_path(&self) -> PathBuf {
+        self.root.join("input").join("data.json")
+    }
+
+    pub fn output_dir(&self) -> PathBuf {
+        self.root.join("output")
+    }
+
+    pub fn log_path(&self) -> PathBuf {
+        self.root.join(LOG_FILE)
+    }
+
+    pub fn report_path(&self) -> PathBuf {
+        self.root.join(REPORT_FILE)
+    }
+
+    /// Writes the job's input files and creates its output directory, clearing the log and
+    /// reports of any previous run
+    pub fn prepare(&self, job: &Job) -> io::Result<()> {
+        let input_dir = self.root.join("input");
+        fs::create_dir_all(&input_dir)?;
+        fs::create_dir_all(self.output_dir())?;
+        fs::write(self.data_path(), serde_json::to_string(job)?)?;
+        fs::write(input_dir.join("job_id"), &job.job_id)?;
+        for path in &[self.log_path(), self.report_path()] {
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Builds the command running `source_file` for the job at `index` from `workdir`, the
+    /// root of the repository. The worker gets the same variables as in a pod, plus `env`,
+    /// with paths into the job's directory. It reports to the report file unless `env` points
+    /// it at a gateway.
+    pub fn command(
+        &self,
+        batch: &Batch,
+        index: usize,
+        workdir: &Path,
+        source_file: &Path,
+        env: &[(String, String)],
+    ) -> io::Result<Command> {
+        let (program, args) = interpreter(&source_file.to_string_lossy());
+        let mut command = Command::new(program);
+        command
+            .args(args)
+            .current_dir(workdir)
+            .env_remove("RFT_GATEWAY_URL")
+            .env_remove("RFT_JOB_TOKEN")
+            .env("RFT_DATA_PATH", self.data_path())
+            .env("RFT_OUTPUT_DIR", self.output_dir())
+            .env("RFT_REPORT_FILE", self.report_path())
+            .envs(worker_env(batch, index))
+            .envs(env.iter().cloned());
+        self.log_output(&mut command)?;
+
+        Ok(command)
+    }
+
+    /// Like `command`, running the worker in a Docker container from `image` named `name`
+    /// instead. The job's input and output are mounted at /input and /output as in a pod, and
+    /// the repository at /src. Paths must be absolute for Docker to mount them.
+    #[allow(clippy::too_many_arguments)]
+    pub fn docker_command(
+        &self,
+        batch: &Batch,
+        index: usize,
+        workdir: &Path,
+        source_file: &str,
+        image: &str,
+        name: &str,
+        env: &[(String, String)],
+    ) -> io::Result<Command> {
+        let mut command = Command::new("docker");
+        command.args(["run", "--rm", "--name", name, "-w", "/src"]);
+        for (source, target) in &[
+            (self.root.join("input"), "/input".to_string()),
+            (self.output_dir(), OUTPUT_DIR.to_string()),
+            (self.root.clone(), "/rft".to_string()),
+            (workdir.to_path_buf(), "/src".to_string()),
+        ] {
+            command
+                .arg("-v")
+                .arg(format!("{}:{}", source.display(), target));
+        }
+        let paths = vec![
+            ("RFT_DATA_PATH".to_string(), "/input/data.json".to_string()),
+            ("RFT_OUTPUT_DIR".to_string(), OUTPUT_DIR.to_string()),
+            (
+                "RFT_REPORT_FILE".to_string(),
+                format!("/rft/{}", REPORT_FILE),
+            ),
+        ];
+        for (name, value) in paths
+            .into_iter()
+            .chain(worker_env(batch, index))
+            .chain(env.iter().cloned())
+        {
+            command.arg("-e").arg(format!("{}={}", name, value));
+        }
+        let (program, args) = interpreter(&format!("/src/{}", source_file));
+        command.arg(image).arg(program).args(args);
+        self.log_output(&mut command)?;
+
+        Ok(command)
+    }
+
+    /// Sends the command's stdout and stderr to the job's log file
+    fn log_output(&self, command: &mut Command) -> io::Result<()> {
+        let log = File::create(self.log_path())?;
+        command
+            .stdin(Stdio::null())
+            .stdout(log.try_clone()?)
+            .stderr(log);
+
+        Ok(())
+    }
+
+    /// Reads what the worker reported. Missing or malformed lines are skipped.
+    pub fn reports(&self) -> Reports {
+        let mut reports = Reports::default();
+        let file = match File::open(self.report_path()) {
+            Ok(file) => file,
+            Err(_) => return reports,
+        };
+
+        for line in BufReader::new(file).lines().map_while(Result::ok) {
+            let report: Value = match serde_json::from_str(&line) {
+                Ok(report) => report,
+                Err(_) => continue,
+            };
+            let body = report["report"].clone();
+            match report["kind"].as_str() {
+                Some("result") => reports.result = Some(body),
+                Some("progress") => reports.progress = Some(body),
+                Some("metrics") => reports.metrics.push(body),
+                _ => {}
+            }
+        }
+
+        reports
+    }
+}
+
+/// Progress, metrics and the result a worker reported, the latest progress and result win
+#[derive(Debug, Default, PartialEq)]
+pub struct Reports {
+    pub progress: Option<Value>,
+    pub metrics: Vec<Value>,
+    pub result: Option<Value>,
+}
+
+/// Appends a report to a report file, as the worker SDKs do
+pub fn append_report(path: &Path, kind: &str, report: &Value) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", json!({ "kind": kind, "report": report }))
+}
+
+/// Variables describing the job and its batch, set on every worker
+fn worker_env(batch: &Batch, index: usize) -> Vec<(String, String)> {
+    let mut env = vec![
+        ("JOB_COMPLETION_INDEX".to_string(), index.to_string()),
+        ("RFT_BATCH_ID".to_string(), batch.batch_id.clone()),
+        ("RFT_AUTHOR".to_string(), batch.author.clone()),
+    ];
+    if let Some(commit) = &batch.commit {
+        env.push(("RFT_COMMIT".to_string(), commit.clone()));
+    }
+
+    env
+}
+
+/// Runs Python files with `python3`, shell scripts with `bash` and anything else directly
+fn interpreter(source_file: &str) -> (String, Vec<String>) {
+    let source_file = source_file.to_string();
+    match Path::new(&source_file)
+        .extension()
+        .and_then(|ext| ext.to_str())
+    {
+        Some("py") => ("python3".to_string(), vec![source_file]),
+        Some("sh") => ("bash".to_string(), vec![source_file]),
+        _ => (source_file, Vec::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::local::{append_report, JobDir};
+    use serde_json::json;
+
+    #[test]
+    fn reads_reports() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = JobDir::new(tmp.path(), "job1");
+        std::fs::create_dir_all(&dir.root).unwrap();
+
+        append_report(&dir.report_path(), "progress", &json!({"fraction": 0.5})).unwrap();
+        append_report(&dir.report_path(), "metrics", &json!({"name": "loss"})).unwrap();
+        append_report(&dir.report_path(), "result", &json!({"loss": 1})).unwrap();
+        append_report(&dir.report_path(), "result", &json!({"loss": 2})).unwrap();
+
+        let reports = dir.reports();
+        assert_eq!(reports.progress, Some(json!({"fraction": 0.5})));
+        assert_eq!(reports.metrics.len(), 1);
+        assert_eq!(reports.result, Some(json!({"loss": 2})));
+    }
+}
@@ -0,0 +1,213 @@
+use snafu::Snafu;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::artifacts::{self, ArtifactStore};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid value '{}' for {}, expected {}", value, name, expected))]
+    InvalidSetting {
+        name: String,
+        value: String,
+        expected: String,
+    },
+    #[snafu(display("{} must be set when LOG_STORE is {}", name, store))]
+    MissingSetting { name: String, store: String },
+    #[snafu(display("Invalid artifact bucket for LOG_STORE=s3: {}", source))]
+    Bucket { source: artifacts::Error },
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Logs archived to Redis are capped at this size unless LOG_STORE_MAX_BYTES is set
+pub const DEFAULT_REDIS_MAX_BYTES: usize = 1024 * 1024;
+
+/// Where the controller archives each job's logs once its pod finishes, so they can still be
+/// read after Kubernetes removes the pod. Read from environment variables:
+/// LOG_STORE - `redis` (default), `filesystem` or `s3`
+/// LOG_STORE_MAX_BYTES - logs over this size keep only their end, default 1 MiB for Redis and
+///     unlimited otherwise
+/// LOG_STORE_DIR - directory for the filesystem store, shared by the controller and gateway
+///
+/// The s3 store uses the artifact bucket, see `ArtifactStore`, keeping logs under `_logs/`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LogStore {
+    Redis {
+        max_bytes: Option<usize>,
+    },
+    Filesystem {
+        dir: PathBuf,
+        max_bytes: Option<usize>,
+    },
+    ObjectStorage {
+        bucket: ArtifactStore,
+        max_bytes: Option<usize>,
+    },
+}
+
+impl LogStore {
+    /// Reads settings through `lookup`, which returns the value of a variable if it is set
+    pub fn from_lookup<F>(lookup: F) -> Result<LogStore>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let lookup = |name: &str| lookup(name).filter(|value| !value.is_empty());
+        let max_bytes = match lookup("LOG_STORE_MAX_BYTES") {
+            Some(value) => Some(value.parse().map_err(|_| Error::InvalidSetting {
+                name: "LOG_STORE_MAX_BYTES".to_string(),
+                value,
+                expected: "a number of bytes".to_string(),
+            })?),
+            None => None,
+        };
+        let missing = |name: &str, store: &str| Error::MissingSetting {
+            name: name.to_string(),
+            store: store.to_string(),
+        };
+
+        match lookup("LOG_STORE").as_deref() {
+            None | Some("redis") => Ok(LogStore::Redis {
+                max_bytes: max_bytes.or(Some(DEFAULT_REDIS_MAX_BYTES)),
+            }),
+            Some("filesystem") => Ok(LogStore::Filesystem {
+                dir: lookup("LOG_STORE_DIR")
+                    .map(PathBuf::from)
+                    .ok_or_else(|| missing("LOG_STORE_DIR", "filesystem"))?,
+                max_bytes,
+            }),
+            Some("s3") => {
+                let bucket = ArtifactStore::from_lookup(lookup)
+                    .map_err(|source| Error::Bucket { source })?
+                    .ok_or_else(|| missing("ARTIFACTS_BUCKET", "s3"))?;
+                if bucket.credentials.is_none() {
+                    return Err(Error::Bucket {
+                        source: artifacts::Error::MissingCredentials,
+                    });
+                }
+                Ok(LogStore::ObjectStorage { bucket, max_bytes })
+            }
+            Some(other) => Err(Error::InvalidSetting {
+                name: "LOG_STORE".to_string(),
+                value: other.to_string(),
+                expected: "redis, filesystem or s3".to_string(),
+            }),
+        }
+    }
+
+    pub fn max_bytes(&self) -> Option<usize> {
+        match self {
+            LogStore::Redis { max_bytes }
+            | LogStore::Filesystem { max_bytes, .. }
+            | LogStore::ObjectStorage { max_bytes, .. } => *max_bytes,
+        }
+    }
+
+    /// Cuts logs down to the store's size limit, see `truncate`
+    pub fn prepare(&self, logs: String) -> String {
+        match self.max_bytes() {
+            Some(max_bytes) => truncate(logs, max_bytes),
+            None => logs,
+        }
+    }
+
+    /// File a job's logs are written to by the filesystem store
+    pub fn file_path(dir: &Path, batch_id: &str, job_id: &str) -> PathBuf {
+        dir.join(batch_id).join(format!("{}.log", job_id))
+    }
+
+    /// Key of a job's logs in the s3 store
+    pub fn object_key(batch_id: &str, job_id: &str) -> String {
+        format!("_logs/{}/{}.log", batch_id, job_id)
+    }
+}
+
+/// Keeps the end of logs over `max_bytes`, where failures are usually reported, noting how
+/// much was cut from the start
+pub fn truncate(logs: String, max_bytes: usize) -> String {
+    if logs.len() <= max_bytes {
+        return logs;
+    }
+
+    let mut start = logs.len() - max_bytes;
+    while !logs.is_char_boundary(start) {
+        start += 1;
+    }
+    // Start at a whole line if there is one
+    if let Some(newline) = logs[start..].find('\n') {
+        if newline + 1 < logs.len() - start {
+            start += newline + 1;
+        }
+    }
+
+    format!(
+        "[rft: {} bytes of earlier output were truncated]\n{}",
+        start,
+        &logs[start..]
+    )
+}
+
+/// Keeps the last `lines` lines of logs
+pub fn tail(logs: &str, lines: usize) -> &str {
+    let trimmed = logs.strip_suffix('\n').unwrap_or(logs);
+    match trimmed.rmatch_indices('\n').nth(lines.saturating_sub(1)) {
+        Some((index, _)) if lines > 0 => &logs[index + 1..],
+        Some(_) => "",
+        None if lines == 0 => "",
+        None => logs,
+    }
+}
+
+impl fmt::Display for LogStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogStore::Redis { .. } => write!(f, "Redis"),
+            LogStore::Filesystem { dir, .. } => write!(f, "{}", dir.display()),
+            LogStore::ObjectStorage { bucket, .. } => write!(f, "{}/_logs", bucket),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::log_store::{tail, truncate, LogStore, DEFAULT_REDIS_MAX_BYTES};
+    use std::collections::HashMap;
+
+    #[test]
+    fn reads_settings() {
+        let from_vars = |vars: &[(&str, &str)]| {
+            let vars: HashMap<&str, &str> = vars.iter().copied().collect();
+            LogStore::from_lookup(|name| vars.get(name).map(|v| v.to_string()))
+        };
+
+        assert_eq!(
+            from_vars(&[]).unwrap(),
+            LogStore::Redis {
+                max_bytes: Some(DEFAULT_REDIS_MAX_BYTES)
+            }
+        );
+        assert_eq!(
+            from_vars(&[("LOG_STORE", "filesystem"), ("LOG_STORE_DIR", "/logs")])
+                .unwrap()
+                .max_bytes(),
+            None
+        );
+        from_vars(&[("LOG_STORE", "filesystem")]).expect_err("Should need a directory");
+        from_vars(&[("LOG_STORE", "s3"), ("ARTIFACTS_BUCKET", "rft")])
+            .expect_err("Should need credentials");
+    }
+
+    #[test]
+    fn keeps_the_end_of_logs() {
+        let logs = "first line\nsecond line\nthird line\n".to_string();
+        assert_eq!(truncate(logs.clone(), 100), logs);
+        assert_eq!(
+            truncate(logs.clone(), 15),
+            "[rft: 23 bytes of earlier output were truncated]\nthird line\n"
+        );
+
+        assert_eq!(tail(&logs, 2), "second line\nthird line\n");
+        assert_eq!(tail(&logs, 5), logs);
+        assert_eq!(tail(&logs, 0), "");
+    }
+}
@@ -0,0 +1,328 @@
+use serde_json::{json, Value};
+
+use crate::artifacts::{ArtifactStore, DONE_MARKER, OUTPUT_DIR};
+use crate::batch::Batch;
+use crate::job_token::JobTokens;
+
+/// Label applied to every Kubernetes resource created by rft
+pub static MANAGED_BY_LABEL: &str = "app.kubernetes.io/managed-by";
+/// Label holding the ID of the batch a Kubernetes Job runs
+pub static BATCH_ID_LABEL: &str = "rft/batch-id";
+
+/// Annotation Kubernetes sets on an Indexed Job's pods with the index of the job they run
+pub static COMPLETION_INDEX_ANNOTATION: &str = "batch.kubernetes.io/job-completion-index";
+/// Name of the container running the job in each pod
+pub static WORKER_CONTAINER: &str = "worker";
+
+/// CPUs requested by each worker container, also used to account CPU hours against quotas
+pub static WORKER_CPU_REQUEST: f64 = 1.0;
+
+/// Image of the sidecar uploading artifacts when ARTIFACTS_UPLOAD_IMAGE isn't set
+pub static DEFAULT_UPLOAD_IMAGE: &str = "amazon/aws-cli:2.15.0";
+
+/// How job pods upload their artifacts
+#[derive(Clone, Debug)]
+pub struct ArtifactUpload {
+    pub store: ArtifactStore,
+    /// Kubernetes Secret with the AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY the sidecar uses
+    pub credentials_secret: String,
+    /// image providing the `aws` CLI
+    pub image: String,
+}
+
+/// How jobs report progress, metrics and results back to the gateway
+#[derive(Clone)]
+pub struct JobReporting {
+    /// URL pods reach the gateway at, i.e. `http://rft-gateway:8000`
+    pub gateway_url: String,
+    pub tokens: JobTokens,
+}
+
+/// Name of the Kubernetes Job that runs a batch
+pub fn job_name(batch_id: &str) -> String {
+    format!("rft-indexed-job-{}", batch_id)
+}
+
+/// Name of the Kubernetes Secret holding the job tokens of a batch
+pub fn job_tokens_secret_name(batch_id: &str) -> String {
+    format!("rft-batch-tokens-{}", batch_id)
+}
+
+/// Renders the Secret holding each job's token under its completion index, owned by the
+/// batch's Kubernetes Job, with UID `job_uid`, so it is deleted along with it. Tokens are kept
+/// out of the Job itself so reading the Job or a pod doesn't reveal them.
+pub fn job_tokens_secret(batch: &Batch, tokens: &JobTokens, job_uid: &str) -> Value {
+    let tokens: serde_json::Map<String, Value> = batch
+        .jobs
+        .iter()
+        .enumerate()
+        .map(|(index, job)| {
+            (
+                index.to_string(),
+                json!(tokens.token(&batch.batch_id, &job.job_id)),
+            )
+        })
+        .collect();
+
+    json!({
+        "apiVersion": "v1",
+        "kind": "Secret",
+        "metadata": {
+            "name": job_tokens_secret_name(&batch.batch_id),
+            "labels": {
+                MANAGED_BY_LABEL: "rft",
+                BATCH_ID_LABEL: batch.batch_id
+            },
+            "ownerReferences": [
+                {
+                    "apiVersion": "batch/v1",
+                    "kind": "Job",
+                    "name": job_name(&batch.batch_id),
+                    "uid": job_uid
+                }
+            ]
+        },
+        "type": "Opaque",
+        "stringData": tokens
+    })
+}
+
+/// Renders the Kubernetes Indexed Job that runs every job in a batch. Each pod's init container
+/// reads the batch from its RFT_BATCH variable and writes the job at its completion index to
+/// /input/data.json for the worker to read, and its ID to /input/job_id. The worker can write
+/// files to /output, which an upload sidecar copies to the artifact bucket under
+/// `<batch_id>/<job_id>/` once the worker exits. With reporting configured the init container
+/// also copies the job's token from the batch's Secret, see `job_tokens_secret`, to
+/// /input/job_token, which the worker SDK sends to RFT_GATEWAY_URL.
+pub fn indexed_job(
+    batch: &Batch,
+    upload: Option<&ArtifactUpload>,
+    reporting: Option<&JobReporting>,
+) -> Value {
+    // The batch is passed through the environment rather than spliced into the script, params,
+    // author and branch are the submitter's and may hold quotes or shell syntax
+    let json_batch = serde_json::to_string(batch).unwrap_or_default();
+    let mut input_mapping = String::from(
+        "printenv RFT_BATCH | jq --argjson index \"$JOB_COMPLETION_INDEX\" '.jobs[$index]' > /input/data.json && jq -r .job_id /input/data.json > /input/job_id",
+    );
+    let mut env = vec![
+        json!({
+            "name": "RFT_OUTPUT_DIR",
+            "value": OUTPUT_DIR
+        }),
+        json!({
+            "name": "RFT_BATCH_ID",
+            "value": batch.batch_id
+        }),
+        json!({
+            "name": "RFT_AUTHOR",
+            "value": batch.author
+        }),
+    ];
+    if let Some(commit) = &batch.commit {
+        env.push(json!({
+            "name": "RFT_COMMIT",
+            "value": commit
+        }));
+    }
+    let mut volumes = vec![
+        json!({
+            "name": "input",
+            "emptyDir": {}
+        }),
+        json!({
+            "name": "output",
+            "emptyDir": {}
+        }),
+    ];
+    let mut input_mounts = vec![json!({
+        "name": "input",
+        "mountPath": "/input"
+    })];
+    if let Some(reporting) = reporting {
+        // Only the init container mounts the batch's tokens, copying its own job's token
+        input_mapping.push_str(" && cp \"/job-tokens/$JOB_COMPLETION_INDEX\" /input/job_token");
+        volumes.push(json!({
+            "name": "job-tokens",
+            "secret": {
+                "secretName": job_tokens_secret_name(&batch.batch_id)
+            }
+        }));
+        input_mounts.push(json!({
+            "name": "job-tokens",
+            "mountPath": "/job-tokens",
+            "readOnly": true
+        }));
+        env.push(json!({
+            "name": "RFT_GATEWAY_URL",
+            "value": reporting.gateway_url
+        }));
+    }
+
+    let mut containers = vec![json!({
+        "name": WORKER_CONTAINER,
+        "image": "docker.io/library/bash",
+        "command": [
+            "bash",
+            "-c",
+            format!("trap 'touch {}/{}' EXIT; cat /input/data.json", OUTPUT_DIR, DONE_MARKER)
+        ],
+        "env": env,
+        "resources": {
+            "requests": {
+                "cpu": WORKER_CPU_REQUEST.to_string()
+            }
+        },
+        "volumeMounts": [
+            {
+                "name": "input",
+                "mountPath": "/input"
+            },
+            {
+                "name": "output",
+                "mountPath": OUTPUT_DIR
+            }
+        ]
+    })];
+    if let Some(upload) = upload {
+        containers.push(upload_sidecar(batch, upload));
+    }
+
+    json!({
+        "apiVersion": "batch/v1",
+        "kind": "Job",
+        "metadata": {
+            "name": job_name(&batch.batch_id),
+            "labels": {
+                MANAGED_BY_LABEL: "rft",
+                BATCH_ID_LABEL: batch.batch_id
+            }
+        },
+        "spec": {
+            "completions": batch.jobs.len(),
+            "parallelism": 3,
+            "completionMode": "Indexed",
+            "template": {
+                "spec": {
+                    "restartPolicy": "Never",
+                    "priorityClassName": batch.priority.priority_class(),
+                    "volumes": volumes,
+                    "initContainers": [
+                        {
+                            "name": "input-mapping",
+                            "image": "public.ecr.aws/e1q1z8n5/alpine-jq",
+                            "command": [
+                                "/bin/sh",
+                                "-c",
+                                input_mapping
+                            ],
+                            "env": [
+                                {
+                                    "name": "RFT_BATCH",
+                                    "value": json_batch
+                                }
+                            ],
+                            "volumeMounts": input_mounts
+                        }
+                    ],
+                    "containers": containers,
+                }
+            }
+        },
+    })
+}
+
+/// Container which waits for the worker to exit, then uploads everything it wrote to /output.
+/// The pod only completes once the upload has finished, and fails if it doesn't succeed.
+fn upload_sidecar(batch: &Batch, upload: &ArtifactUpload) -> Value {
+    let script = format!(
+        "until [ -f {output}/{done} ]; do sleep 1; done; rm -f {output}/{done}; \
+         aws --endpoint-url \"$ARTIFACTS_ENDPOINT\" s3 cp --recursive --only-show-errors {output} \
+         \"s3://$ARTIFACTS_BUCKET/{prefix}$(cat /input/job_id)/\"",
+        output = OUTPUT_DIR,
+        done = DONE_MARKER,
+        prefix = ArtifactStore::prefix(&batch.batch_id, None),
+    );
+
+    json!({
+        "name": "upload-artifacts",
+        "image": upload.image,
+        "command": ["sh", "-c", script],
+        "env": [
+            {
+                "name": "ARTIFACTS_ENDPOINT",
+                "value": upload.store.endpoint
+            },
+            {
+                "name": "ARTIFACTS_BUCKET",
+                "value": upload.store.bucket
+            },
+            {
+                "name": "AWS_DEFAULT_REGION",
+                "value": upload.store.region
+            }
+        ],
+        "envFrom": [
+            {
+                "secretRef": {
+                    "name": upload.credentials_secret
+                }
+            }
+        ],
+        "volumeMounts": [
+            {
+                "name": "input",
+                "mountPath": "/input",
+                "readOnly": true
+            },
+            {
+                "name": "output",
+                "mountPath": OUTPUT_DIR
+            }
+        ]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::batch::Batch;
+    use crate::job::Job;
+    use crate::job_token::JobTokens;
+    use crate::manifest::{indexed_job, job_tokens_secret, JobReporting};
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    #[test]
+    fn batch_is_kept_out_of_the_script() {
+        let mut batch = Batch::new("o'brien", "main.py", "git@github.com:org/repo.git", "main");
+        let mut params = HashMap::new();
+        params.insert("name".to_string(), json!("'; rm -rf / #"));
+        batch.jobs.push(Job::new(params));
+
+        let job = indexed_job(&batch, None, None);
+        let init = &job["spec"]["template"]["spec"]["initContainers"][0];
+        let script = init["command"][2].as_str().unwrap();
+        assert!(!script.contains("rm -rf") && !script.contains("brien"));
+        let env = init["env"][0]["value"].as_str().unwrap();
+        assert_eq!(Batch::from_json(env).unwrap().author, "o'brien");
+    }
+
+    #[test]
+    fn tokens_stay_out_of_the_job() {
+        let mut batch = Batch::new("matt", "main.py", "git@github.com:org/repo.git", "main");
+        batch.jobs.push(Job::new(HashMap::new()));
+        batch.jobs.push(Job::new(HashMap::new()));
+        let reporting = JobReporting {
+            gateway_url: "http://rft-gateway:8000".to_string(),
+            tokens: JobTokens::new("secret"),
+        };
+        let token = reporting
+            .tokens
+            .token(&batch.batch_id, &batch.jobs[1].job_id);
+
+        let job = indexed_job(&batch, None, Some(&reporting)).to_string();
+        assert!(!job.contains(&token));
+        let secret = job_tokens_secret(&batch, &reporting.tokens, "uid");
+        assert_eq!(secret["stringData"]["1"], json!(token));
+    }
+}
@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::batch::{Batch, Priority};
+
+/// Names of the Redis keys shared by the gateway and controller. Every key starts with a
+/// configurable prefix so several deployments can share one Redis instance.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Keys {
+    prefix: String,
+}
+
+impl Keys {
+    pub fn new(prefix: &str) -> Keys {
+        Keys {
+            prefix: prefix.to_string(),
+        }
+    }
+
+    fn key(&self, name: &str) -> String {
+        format!("{}{}", self.prefix, name)
+    }
+
+    /// Set of content addressed job IDs which have completed successfully in a user's batches.
+    /// Kept per user, as only they can read the results of the jobs skipped because of it.
+    pub fn succeeded_jobs(&self, user: &str) -> String {
+        self.key(&format!("succeeded_jobs:{}", user))
+    }
+
+    /// Set of users with at least one queued batch
+    pub fn queued_users(&self) -> String {
+        self.key("queued_users")
+    }
+
+    /// Set of batch IDs currently running on the cluster
+    pub fn running_batches(&self) -> String {
+        self.key("running_batches")
+    }
+
+    /// Hash of batch ID to how the batch finished: completed, failed or deleted
+    pub fn batch_outcomes(&self) -> String {
+        self.key("batch_outcomes")
+    }
+
+    /// Hash of user (or `team:<name>`) to the number of batches they have queued
+    pub fn queued_batches_count(&self) -> String {
+        self.key("quota:queued_batches")
+    }
+
+    /// Hash of user (or `team:<name>`) to the number of jobs they have queued or running
+    pub fn active_jobs_count(&self) -> String {
+        self.key("quota:active_jobs")
+    }
+
+    /// Hash of batch ID to the quota a queued or running batch holds, see `HeldQuota`
+    pub fn held_quota(&self) -> String {
+        self.key("quota:held")
+    }
+
+    /// Key holding a batch's JSON
+    pub fn batch(&self, batch_id: &str) -> String {
+        self.key(&format!("batch:{}", batch_id))
+    }
+
+    /// List of batch IDs queued by a single user, oldest first
+    pub fn user_queue(&self, user: &str) -> String {
+        self.key(&format!("queue:{}", user))
+    }
+
+    /// Hash of job ID to a JSON array of the names of the pods that ran it, oldest first
+    pub fn job_pods(&self, batch_id: &str) -> String {
+        self.key(&format!("pods:{}", batch_id))
+    }
+
+    /// Hash of job ID to the archived logs of the job, when logs are archived to Redis
+    pub fn job_logs(&self, batch_id: &str) -> String {
+        self.key(&format!("logs:{}", batch_id))
+    }
+
+    /// Set of the names of a batch's pods whose logs have been archived
+    pub fn archived_pods(&self, batch_id: &str) -> String {
+        self.key(&format!("logs:archived_pods:{}", batch_id))
+    }
+
+    /// Hash of job ID to the JSON result the job posted
+    pub fn results(&self, batch_id: &str) -> String {
+        self.key(&format!("results:{}", batch_id))
+    }
+
+    /// Hash of job ID to the JSON progress the job last reported
+    pub fn progress(&self, batch_id: &str) -> String {
+        self.key(&format!("progress:{}", batch_id))
+    }
+
+    /// List of the JSON metrics a job has logged, oldest first
+    pub fn metrics(&self, batch_id: &str, job_id: &str) -> String {
+        self.key(&format!("metrics:{}:{}", batch_id, job_id))
+    }
+
+    /// Hash of user (or `team:<name>`) to the CPU seconds their jobs used on a given day
+    pub fn cpu_seconds(&self, epoch_day: u64) -> String {
+        self.key(&format!("quota:cpu_seconds:{}", epoch_day))
+    }
+
+    /// Response recorded for a request sent with an Idempotency-Key header
+    pub fn idempotency(&self, principal: &str, idempotency_key: &str) -> String {
+        self.key(&format!("idempotency:{}:{}", principal, idempotency_key))
+    }
+}
+
+/// Field used for a team in the quota hashes, kept apart from user names
+pub fn team_field(team: &str) -> String {
+    format!("team:{}", team)
+}
+
+/// The quota a queued or running batch counts against, recorded when it is queued so it can be
+/// released even if the batch is deleted or can't be read
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HeldQuota {
+    /// the quota hash fields: the batch's owner and, if set, its team
+    pub accounts: Vec<String>,
+    pub jobs: u64,
+}
+
+impl HeldQuota {
+    pub fn for_batch(batch: &Batch) -> HeldQuota {
+        let mut accounts = vec![batch.owner().to_string()];
+        if let Some(team) = &batch.team {
+            accounts.push(team_field(team));
+        }
+
+        HeldQuota {
+            accounts,
+            jobs: batch.jobs.len() as u64,
+        }
+    }
+}
+
+/// Days since the unix epoch (UTC), used to bucket daily quotas
+pub fn epoch_day(unix_seconds: u64) -> u64 {
+    unix_seconds / 86_400
+}
+
+/// Seconds a batch waits in the queue before it is treated as one priority level higher
+pub const AGING_INTERVAL_SECONDS: u64 = 30 * 60;
+
+/// A batch waiting in a user's queue
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueuedBatch {
+    pub batch_id: String,
+    pub user: String,
+    pub priority: Priority,
+    /// unix timestamp the gateway accepted the batch at
+    pub submitted_at: u64,
+}
+
+impl QueuedBatch {
+    /// The batch's priority rank raised by one level for every `AGING_INTERVAL_SECONDS` it has
+    /// waited, capped at urgent, so low priority batches can't be starved forever
+    pub fn effective_rank(&self, now: u64) -> u64 {
+        let waited = now.saturating_sub(self.submitted_at);
+        (self.priority.rank() + waited / AGING_INTERVAL_SECONDS).min(Priority::Urgent.rank())
+    }
+}
+
+/// Picks which queued batch to run next. The highest effective priority goes first, then the
+/// user with the fewest running jobs so concurrent users share the cluster fairly, and finally
+/// the oldest batch.
+pub fn next_batch<'a>(
+    queued: &'a [QueuedBatch],
+    running_jobs: &HashMap<String, usize>,
+    now: u64,
+) -> Option<&'a QueuedBatch> {
+    queued.iter().min_by_key(|queued| {
+        (
+            std::cmp::Reverse(queued.effective_rank(now)),
+            running_jobs.get(&queued.user).copied().unwrap_or(0),
+            queued.submitted_at,
+        )
+    })
+}
+
+/// Where a batch stands with the batches it depends on
+#[derive(Clone, Debug, PartialEq)]
+pub enum Dependencies {
+    /// every predecessor completed, the batch can run
+    Ready,
+    /// some predecessors are still queued or running
+    Waiting,
+    /// the predecessor with this ID failed or was deleted, so the batch never will run
+    Failed(String),
+}
+
+/// Checks a batch's predecessors given each one's ID and outcome, None while it is queued or
+/// running. Anything but "completed" fails the batch, including a predecessor that was itself
+/// failed by its own dependencies.
+pub fn dependencies(outcomes: &[(String, Option<String>)]) -> Dependencies {
+    let mut waiting = false;
+    for (batch_id, outcome) in outcomes {
+        match outcome.as_deref() {
+            Some("completed") => {}
+            Some(_) => return Dependencies::Failed(batch_id.clone()),
+            None => waiting = true,
+        }
+    }
+
+    if waiting {
+        Dependencies::Waiting
+    } else {
+        Dependencies::Ready
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::batch::Priority;
+    use crate::queue::{
+        dependencies, next_batch, Dependencies, Keys, QueuedBatch, AGING_INTERVAL_SECONDS,
+    };
+    use std::collections::HashMap;
+
+    fn queued(batch_id: &str, user: &str, priority: Priority, submitted_at: u64) -> QueuedBatch {
+        QueuedBatch {
+            batch_id: batch_id.to_string(),
+            user: user.to_string(),
+            priority,
+            submitted_at,
+        }
+    }
+
+    #[test]
+    fn users_with_fewer_running_jobs_go_first() {
+        let queued = vec![
+            queued("a", "matt", Priority::Normal, 1),
+            queued("b", "sam", Priority::Normal, 2),
+        ];
+        let mut running_jobs = HashMap::new();
+
+        assert_eq!(next_batch(&queued, &running_jobs, 2).unwrap().batch_id, "a");
+
+        running_jobs.insert("matt".to_string(), 10_000);
+        assert_eq!(next_batch(&queued, &running_jobs, 2).unwrap().batch_id, "b");
+
+        assert!(next_batch(&[], &running_jobs, 2).is_none());
+    }
+
+    #[test]
+    fn priority_wins_until_low_priority_batches_age() {
+        let queued = vec![
+            queued("sweep", "matt", Priority::Low, 0),
+            queued("rerun", "sam", Priority::Urgent, 10),
+            queued("check", "sam", Priority::High, 5),
+        ];
+        let running_jobs = HashMap::new();
+
+        assert_eq!(
+            next_batch(&queued, &running_jobs, 10).unwrap().batch_id,
+            "rerun"
+        );
+
+        // After waiting three intervals the low priority sweep is treated as urgent and,
+        // being the oldest, runs first
+        let now = 3 * AGING_INTERVAL_SECONDS;
+        assert_eq!(queued[0].effective_rank(now), Priority::Urgent.rank());
+        assert_eq!(
+            next_batch(&queued, &running_jobs, now).unwrap().batch_id,
+            "sweep"
+        );
+    }
+
+    #[test]
+    fn keys_are_prefixed() {
+        assert_eq!(Keys::default().batch("abc"), "batch:abc");
+        assert_eq!(
+            Keys::new("staging:").user_queue("matt"),
+            "staging:queue:matt"
+        );
+    }
+
+    #[test]
+    fn batches_wait_for_their_dependencies() {
+        let outcome = |batch_id: &str, outcome: Option<&str>| {
+            (batch_id.to_string(), outcome.map(|o| o.to_string()))
+        };
+        assert_eq!(dependencies(&[]), Dependencies::Ready);
+        assert_eq!(
+            dependencies(&[outcome("a", Some("completed")), outcome("b", None)]),
+            Dependencies::Waiting
+        );
+        assert_eq!(
+            dependencies(&[outcome("a", None), outcome("b", Some("deleted"))]),
+            Dependencies::Failed("b".to_string())
+        );
+        assert_eq!(
+            dependencies(&[outcome("a", Some("completed"))]),
+            Dependencies::Ready
+        );
+    }
+}
@@ -0,0 +1,453 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use snafu::Snafu;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+use crate::job::Job;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "Parameter '{}' should be of type {} but found value: {}",
+        param,
+        expected,
+        value
+    ))]
+    TypeMismatch {
+        param: String,
+        expected: ParamType,
+        value: Value,
+    },
+    #[snafu(display(
+        "Unknown parameter '{}'{}",
+        param,
+        suggestion
+            .as_ref()
+            .map(|s| format!(", did you mean '{}'?", s))
+            .unwrap_or_default()
+    ))]
+    UnknownParam {
+        param: String,
+        suggestion: Option<String>,
+    },
+    #[snafu(display("Missing required parameter '{}'", param))]
+    MissingParam { param: String },
+    #[snafu(display(
+        "Value {} is not allowed for parameter '{}'. Allowed values: {}",
+        value,
+        param,
+        Value::from(allowed.clone())
+    ))]
+    NotAllowed {
+        param: String,
+        value: Value,
+        allowed: Vec<Value>,
+    },
+    #[snafu(display(
+        "Default value {} of parameter '{}' is not of type {}",
+        value,
+        param,
+        expected
+    ))]
+    DefaultMismatch {
+        param: String,
+        expected: ParamType,
+        value: Value,
+    },
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The type of value a parameter carries
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParamType {
+    String,
+    Integer,
+    Float,
+    Boolean,
+    List,
+    Object,
+}
+
+impl ParamType {
+    /// Returns the type of a JSON value, or `None` for null
+    pub fn of(value: &Value) -> Option<ParamType> {
+        match value {
+            Value::String(_) => Some(ParamType::String),
+            Value::Number(n) if n.is_f64() => Some(ParamType::Float),
+            Value::Number(_) => Some(ParamType::Integer),
+            Value::Bool(_) => Some(ParamType::Boolean),
+            Value::Array(_) => Some(ParamType::List),
+            Value::Object(_) => Some(ParamType::Object),
+            Value::Null => None,
+        }
+    }
+
+    /// Checks whether a value is acceptable for this type. Integers are accepted as floats.
+    pub fn accepts(&self, value: &Value) -> bool {
+        match (self, ParamType::of(value)) {
+            (ParamType::Float, Some(ParamType::Integer)) => true,
+            (expected, Some(found)) => *expected == found,
+            (_, None) => false,
+        }
+    }
+
+    /// Reads a raw string, i.e. a CLI value, as this type. A string is taken as-is unless it is
+    /// quoted. Returns `None` if the string isn't a valid value of this type.
+    pub fn coerce(&self, raw: &str) -> Option<Value> {
+        match self {
+            ParamType::String => match serde_json::from_str::<Value>(raw) {
+                Ok(Value::String(unquoted)) => Some(Value::String(unquoted)),
+                _ => Some(Value::String(raw.to_string())),
+            },
+            ParamType::Integer => raw.parse::<i64>().ok().map(Value::from),
+            ParamType::Float => raw
+                .parse::<f64>()
+                .ok()
+                .and_then(serde_json::Number::from_f64)
+                .map(Value::Number),
+            ParamType::Boolean => raw.parse::<bool>().ok().map(Value::Bool),
+            ParamType::List | ParamType::Object => serde_json::from_str::<Value>(raw)
+                .ok()
+                .filter(|value| self.accepts(value)),
+        }
+    }
+}
+
+impl fmt::Display for ParamType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ParamType::String => "string",
+            ParamType::Integer => "integer",
+            ParamType::Float => "float",
+            ParamType::Boolean => "boolean",
+            ParamType::List => "list",
+            ParamType::Object => "object",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// A single parameter declaration:
+/// {
+///     "name": "region",
+///     "type": "string",
+///     "default": "us-east-1",
+///     "allowed": ["us-east-1", "eu-west-1"],
+///     "required": false,
+///     "description": "AWS region to pull data from"
+/// }
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ParamSpec {
+    /// the parameter name as used in a job's params
+    pub name: String,
+    /// the type every value of this parameter must have
+    #[serde(rename = "type")]
+    pub param_type: ParamType,
+    /// the value used when a job doesn't set this parameter
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<Value>,
+    /// the only values this parameter may take, if restricted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed: Option<Vec<Value>>,
+    /// whether every job must set this parameter
+    #[serde(default)]
+    pub required: bool,
+    /// a human readable explanation of the parameter
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+impl ParamSpec {
+    pub fn new(name: &str, param_type: ParamType) -> ParamSpec {
+        ParamSpec {
+            name: name.to_string(),
+            param_type,
+            default: None,
+            allowed: None,
+            required: false,
+            description: None,
+        }
+    }
+}
+
+/// Describes the parameters accepted by a batch's source file
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ParamSchema {
+    pub params: Vec<ParamSpec>,
+    /// whether params that aren't declared in the schema are rejected
+    #[serde(default)]
+    pub strict: bool,
+}
+
+impl ParamSchema {
+    pub fn get(&self, name: &str) -> Option<&ParamSpec> {
+        self.params.iter().find(|spec| spec.name == name)
+    }
+
+    /// Infers a schema from the values used across a set of jobs. Parameters whose values
+    /// don't share a single type are left out, integers and floats are widened to float.
+    pub fn infer(jobs: &[Job]) -> ParamSchema {
+        let mut types: BTreeMap<String, Option<ParamType>> = BTreeMap::new();
+        for job in jobs {
+            for (name, value) in &job.params {
+                let found = ParamType::of(value);
+                let inferred = match types.get(name) {
+                    None => found,
+                    Some(current) => match (*current, found) {
+                        (Some(a), Some(b)) if a == b => Some(a),
+                        (Some(ParamType::Integer), Some(ParamType::Float))
+                        | (Some(ParamType::Float), Some(ParamType::Integer)) => {
+                            Some(ParamType::Float)
+                        }
+                        _ => None,
+                    },
+                };
+                types.insert(name.clone(), inferred);
+            }
+        }
+
+        ParamSchema {
+            params: types
+                .into_iter()
+                .filter_map(|(name, param_type)| {
+                    param_type.map(|param_type| ParamSpec::new(&name, param_type))
+                })
+                .collect(),
+            strict: false,
+        }
+    }
+
+    /// Fills in declared defaults for any params a job doesn't set
+    pub fn apply_defaults(&self, params: &mut HashMap<String, Value>) {
+        for spec in &self.params {
+            if let Some(default) = &spec.default {
+                params
+                    .entry(spec.name.clone())
+                    .or_insert_with(|| default.clone());
+            }
+        }
+    }
+
+    /// Checks that every declared default is of its param's type
+    pub fn check_defaults(&self) -> Result<()> {
+        for spec in &self.params {
+            if let Some(default) = &spec.default {
+                if !spec.param_type.accepts(default) {
+                    return DefaultMismatch {
+                        param: spec.name.clone(),
+                        expected: spec.param_type,
+                        value: default.clone(),
+                    }
+                    .fail();
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Validates a single job's params against the schema
+    pub fn validate(&self, params: &HashMap<String, Value>) -> Result<()> {
+        for (name, value) in params {
+            match self.get(name) {
+                Some(spec) => {
+                    if !spec.param_type.accepts(value) {
+                        return TypeMismatch {
+                            param: name.clone(),
+                            expected: spec.param_type,
+                            value: value.clone(),
+                        }
+                        .fail();
+                    }
+
+                    if let Some(allowed) = &spec.allowed {
+                        if !allowed.contains(value) {
+                            return NotAllowed {
+                                param: name.clone(),
+                                value: value.clone(),
+                                allowed: allowed.clone(),
+                            }
+                            .fail();
+                        }
+                    }
+                }
+                None if self.strict => {
+                    return UnknownParam {
+                        param: name.clone(),
+                        suggestion: self.suggest(name),
+                    }
+                    .fail();
+                }
+                None => {}
+            }
+        }
+
+        for spec in &self.params {
+            if spec.required && !params.contains_key(&spec.name) {
+                return MissingParam {
+                    param: spec.name.clone(),
+                }
+                .fail();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Finds the declared param closest to a misspelled name, if any is close enough
+    fn suggest(&self, name: &str) -> Option<String> {
+        let max_distance = std::cmp::max(2, name.len() / 3);
+        self.params
+            .iter()
+            .map(|spec| (edit_distance(name, &spec.name), &spec.name))
+            .filter(|(distance, _)| *distance <= max_distance)
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, name)| name.clone())
+    }
+}
+
+/// Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == *cb { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::job::Job;
+    use crate::schema::{Error, ParamSchema, ParamSpec, ParamType};
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    #[test]
+    fn validate_param_types() {
+        let schema = ParamSchema {
+            params: vec![
+                ParamSpec::new("start_date", ParamType::Integer),
+                ParamSpec::new("learning_rate", ParamType::Float),
+            ],
+            strict: false,
+        };
+
+        let mut params = HashMap::new();
+        params.insert("start_date".to_string(), json!(1980));
+        params.insert("learning_rate".to_string(), json!(1));
+        schema
+            .validate(&params)
+            .expect("Integers should be accepted for integer and float params");
+
+        params.insert("start_date".to_string(), json!("1980"));
+        schema
+            .validate(&params)
+            .expect_err("A string should not be accepted for an integer param");
+    }
+
+    #[test]
+    fn validate_declared_schema() {
+        let mut region = ParamSpec::new("region", ParamType::String);
+        region.default = Some(json!("us-east-1"));
+        region.allowed = Some(vec![json!("us-east-1"), json!("eu-west-1")]);
+        let mut start_date = ParamSpec::new("start_date", ParamType::Integer);
+        start_date.required = true;
+        let schema = ParamSchema {
+            params: vec![start_date, region],
+            strict: true,
+        };
+
+        let mut params = HashMap::new();
+        params.insert("start_dat".to_string(), json!(1980));
+        match schema.validate(&params) {
+            Err(Error::UnknownParam { suggestion, .. }) => {
+                assert_eq!(suggestion.as_deref(), Some("start_date"))
+            }
+            other => panic!("Expected an unknown param error, got {:?}", other),
+        }
+
+        let mut params = HashMap::new();
+        schema
+            .validate(&params)
+            .expect_err("start_date is required");
+
+        params.insert("start_date".to_string(), json!(1980));
+        schema.apply_defaults(&mut params);
+        assert_eq!(params["region"], json!("us-east-1"));
+        schema
+            .validate(&params)
+            .expect("Defaults should satisfy the schema");
+
+        params.insert("region".to_string(), json!("ap-south-1"));
+        schema
+            .validate(&params)
+            .expect_err("ap-south-1 is not an allowed region");
+    }
+
+    #[test]
+    fn coerce_raw_values() {
+        assert_eq!(ParamType::String.coerce("1.0"), Some(json!("1.0")));
+        assert_eq!(ParamType::String.coerce("123456"), Some(json!("123456")));
+        assert_eq!(ParamType::String.coerce(r#""x,y""#), Some(json!("x,y")));
+        assert_eq!(ParamType::Integer.coerce("1980"), Some(json!(1980)));
+        assert_eq!(ParamType::Integer.coerce("1.5"), None);
+        assert_eq!(ParamType::Float.coerce("1"), Some(json!(1.0)));
+        assert_eq!(ParamType::Boolean.coerce("yes"), None);
+        assert_eq!(ParamType::List.coerce("[1,2]"), Some(json!([1, 2])));
+        assert_eq!(ParamType::Object.coerce("[1,2]"), None);
+    }
+
+    #[test]
+    fn check_declared_defaults() {
+        let mut region = ParamSpec::new("region", ParamType::String);
+        region.default = Some(json!("us-east-1"));
+        let mut schema = ParamSchema {
+            params: vec![region],
+            strict: true,
+        };
+        schema.check_defaults().expect("The default is a string");
+
+        schema.params[0].default = Some(json!(1));
+        match schema.check_defaults() {
+            Err(Error::DefaultMismatch { param, .. }) => assert_eq!(param, "region"),
+            other => panic!("Expected a default mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn infer_schema_from_jobs() {
+        let mut first = HashMap::new();
+        first.insert("start_date".to_string(), json!(1980));
+        first.insert("learning_rate".to_string(), json!(1));
+        first.insert("region".to_string(), json!("us-east-1"));
+        let mut second = HashMap::new();
+        second.insert("start_date".to_string(), json!(1990));
+        second.insert("learning_rate".to_string(), json!(0.5));
+        second.insert("region".to_string(), json!(true));
+
+        let schema = ParamSchema::infer(&[Job::new(first), Job::new(second)]);
+
+        assert_eq!(
+            schema.get("start_date").map(|s| s.param_type),
+            Some(ParamType::Integer)
+        );
+        assert_eq!(
+            schema.get("learning_rate").map(|s| s.param_type),
+            Some(ParamType::Float)
+        );
+        assert!(schema.get("region").is_none());
+    }
+}
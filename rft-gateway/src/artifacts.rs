@@ -0,0 +1,122 @@
+use std::time::Duration;
+
+use hyper::client::HttpConnector;
+use hyper::{Body, Client};
+use hyper_tls::HttpsConnector;
+use rft_core::artifacts::{self, ArtifactStore};
+use rocket::serde::json::{serde_json::json, Value};
+
+use crate::error::{ApiError, ErrorCode};
+
+/// How long download URLs handed to clients stay valid
+const DOWNLOAD_URL_SECONDS: u64 = 60 * 60;
+/// How long a listing request to the bucket can take
+const LIST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Objects listed per request, after which the listing is cut short
+const MAX_LISTED_OBJECTS: usize = 10_000;
+
+/// The bucket job artifacts are uploaded to, managed as Rocket state. Artifacts are disabled
+/// when no bucket is configured.
+pub struct Artifacts {
+    store: Option<ArtifactStore>,
+    client: Client<HttpsConnector<HttpConnector>, Body>,
+}
+
+impl Artifacts {
+    pub fn new(store: Option<ArtifactStore>) -> Artifacts {
+        Artifacts {
+            store,
+            client: Client::builder().build(HttpsConnector::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.store.is_some()
+    }
+
+    /// Lists the artifacts uploaded by a job, or by every job in the batch, each with a
+    /// presigned URL the client can download it from without bucket credentials
+    pub async fn list(
+        &self,
+        batch_id: &str,
+        job_id: Option<&str>,
+        unix_now: u64,
+    ) -> Result<Vec<Value>, ApiError> {
+        let store = self.store.as_ref().ok_or_else(|| {
+            ApiError::new(
+                ErrorCode::ArtifactsUnavailable,
+                "Artifact storage isn't configured on this gateway",
+            )
+        })?;
+        let batch_prefix = ArtifactStore::prefix(batch_id, None);
+        let prefix = ArtifactStore::prefix(batch_id, job_id);
+
+        let mut listed = Vec::new();
+        let mut continuation_token = None;
+        loop {
+            let url = store
+                .list_url(&prefix, continuation_token.as_deref(), unix_now)
+                .map_err(|err| unavailable(&err.to_string()))?;
+            let listing = artifacts::parse_listing(&self.get(&url).await?);
+            listed.extend(listing.objects);
+
+            continuation_token = listing.next_continuation_token;
+            if continuation_token.is_none() || listed.len() >= MAX_LISTED_OBJECTS {
+                break;
+            }
+        }
+
+        listed
+            .into_iter()
+            .filter_map(|object| {
+                // Keys are <batch_id>/<job_id>/<name>, where the name can include directories
+                let (job_id, name) = object.key.strip_prefix(&batch_prefix)?.split_once('/')?;
+                Some((job_id.to_string(), name.to_string(), object))
+            })
+            .map(|(job_id, name, object)| {
+                let url = store
+                    .download_url(&object.key, DOWNLOAD_URL_SECONDS, unix_now)
+                    .map_err(|err| unavailable(&err.to_string()))?;
+                Ok(json!({
+                    "job_id": job_id,
+                    "name": name,
+                    "size": object.size,
+                    "last_modified": object.last_modified,
+                    "url": url,
+                }))
+            })
+            .collect()
+    }
+
+    async fn get(&self, url: &str) -> Result<String, ApiError> {
+        let uri = url
+            .parse()
+            .map_err(|_| unavailable("invalid listing URL"))?;
+        let response = rocket::tokio::time::timeout(LIST_TIMEOUT, self.client.get(uri))
+            .await
+            .map_err(|_| unavailable("timed out listing the bucket"))?
+            .map_err(|err| unavailable(&err.to_string()))?;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(|err| unavailable(&err.to_string()))?;
+        let body = String::from_utf8_lossy(&body).to_string();
+        if !status.is_success() {
+            return Err(unavailable(&format!(
+                "bucket responded {}: {}",
+                status, body
+            )));
+        }
+
+        Ok(body)
+    }
+}
+
+/// Logs why listing artifacts failed and returns the error reported to clients
+fn unavailable(reason: &str) -> ApiError {
+    eprintln!("Unable to list artifacts: {}", reason);
+    ApiError::new(
+        ErrorCode::ArtifactsUnavailable,
+        "Artifact storage is unavailable, try again later",
+    )
+}
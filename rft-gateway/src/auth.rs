@@ -0,0 +1,152 @@
+use rft_core::batch::Batch;
+use rft_core::job_token::{JobTokens, JOB_TOKEN_PREFIX};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+/// An API token accepted by the gateway. Only the SHA-256 digest of the token is configured,
+/// generate one with i.e. `openssl rand -hex 32 | tee token | tr -d '\n' | sha256sum`
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ApiToken {
+    /// the user or service the token belongs to
+    pub principal: String,
+    /// the team the principal belongs to, used for team quotas
+    #[serde(default)]
+    pub team: Option<String>,
+    /// hex encoded SHA-256 digest of the token
+    pub token_sha256: String,
+}
+
+/// Authentication settings read from Rocket's configuration:
+/// [default]
+/// api_tokens = [{ principal = "matt", team = "research", token_sha256 = "9f86d0..." }]
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct AuthConfig {
+    #[serde(default)]
+    pub api_tokens: Vec<ApiToken>,
+    /// accept unauthenticated requests, only intended for local development
+    #[serde(default)]
+    pub disable_auth: bool,
+}
+
+/// The authenticated caller of a request, taken from an `Authorization: Bearer <token>` header
+pub struct Principal {
+    pub name: String,
+    pub team: Option<String>,
+}
+
+impl Principal {
+    /// Whether the principal may see a batch: they own it or are on the same team
+    pub fn can_access(&self, batch: &Batch) -> bool {
+        let same_team = self.team.is_some() && self.team == batch.team;
+        batch.owner() == self.name || same_team
+    }
+}
+
+/// Principal used for every request when authentication is disabled
+pub static ANONYMOUS: &str = "anonymous";
+
+#[derive(Debug)]
+pub enum AuthError {
+    NotConfigured,
+    MissingToken,
+    InvalidToken,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Principal {
+    type Error = AuthError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let config = match req.rocket().state::<AuthConfig>() {
+            Some(config) => config,
+            None => {
+                return Outcome::Failure((Status::InternalServerError, AuthError::NotConfigured))
+            }
+        };
+
+        if config.disable_auth {
+            return Outcome::Success(Principal {
+                name: ANONYMOUS.to_string(),
+                team: None,
+            });
+        }
+
+        let token = match req
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "))
+        {
+            Some(token) => token.trim(),
+            None => return Outcome::Failure((Status::Unauthorized, AuthError::MissingToken)),
+        };
+
+        let digest = token_digest(token);
+        match config
+            .api_tokens
+            .iter()
+            .find(|api_token| api_token.token_sha256.eq_ignore_ascii_case(&digest))
+        {
+            Some(api_token) => Outcome::Success(Principal {
+                name: api_token.principal.clone(),
+                team: api_token.team.clone(),
+            }),
+            None => Outcome::Failure((Status::Unauthorized, AuthError::InvalidToken)),
+        }
+    }
+}
+
+/// Key used to check job tokens, managed as Rocket state. None when job tokens aren't
+/// configured, so only API tokens are accepted.
+pub struct JobAuth(pub Option<JobTokens>);
+
+/// The caller of a job reporting request: a running job authenticated by its job token, or a
+/// user with an API token
+pub enum Reporter {
+    /// a job token, which is only checked against the job the request is for
+    Job(String),
+    User(Principal),
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Reporter {
+    type Error = AuthError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let job_token = req
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "))
+            .map(str::trim)
+            .filter(|token| token.starts_with(JOB_TOKEN_PREFIX));
+
+        match job_token {
+            Some(token) => Outcome::Success(Reporter::Job(token.to_string())),
+            None => Principal::from_request(req).await.map(Reporter::User),
+        }
+    }
+}
+
+/// Hex encoded SHA-256 digest of a token
+pub fn token_digest(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::token_digest;
+
+    #[test]
+    fn digest_matches_sha256sum() {
+        assert_eq!(
+            token_digest("test"),
+            "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+        );
+    }
+}
@@ -0,0 +1,119 @@
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, Responder};
+use rocket::serde::json::{serde_json::json, Value};
+
+/// Machine readable reason a request failed, sent as `error.code` so clients can react to
+/// specific failures without matching on messages
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorCode {
+    MalformedJson,
+    InvalidIdempotencyKey,
+    PayloadTooLarge,
+    InvalidBatch,
+    IdempotencyKeyReused,
+    InvalidResult,
+    InvalidReport,
+    Unauthorized,
+    NotFound,
+    RequestInProgress,
+    QuotaExceeded,
+    QueueUnavailable,
+    ArtifactsUnavailable,
+    LogsUnavailable,
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::MalformedJson => "malformed_json",
+            ErrorCode::InvalidIdempotencyKey => "invalid_idempotency_key",
+            ErrorCode::PayloadTooLarge => "payload_too_large",
+            ErrorCode::InvalidBatch => "invalid_batch",
+            ErrorCode::IdempotencyKeyReused => "idempotency_key_reused",
+            ErrorCode::InvalidResult => "invalid_result",
+            ErrorCode::InvalidReport => "invalid_report",
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::NotFound => "not_found",
+            ErrorCode::RequestInProgress => "request_in_progress",
+            ErrorCode::QuotaExceeded => "quota_exceeded",
+            ErrorCode::QueueUnavailable => "queue_unavailable",
+            ErrorCode::ArtifactsUnavailable => "artifacts_unavailable",
+            ErrorCode::LogsUnavailable => "logs_unavailable",
+            ErrorCode::Internal => "internal_error",
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        match self {
+            ErrorCode::MalformedJson => Status::BadRequest,
+            ErrorCode::InvalidIdempotencyKey => Status::BadRequest,
+            ErrorCode::PayloadTooLarge => Status::PayloadTooLarge,
+            ErrorCode::InvalidBatch => Status::UnprocessableEntity,
+            ErrorCode::IdempotencyKeyReused => Status::UnprocessableEntity,
+            ErrorCode::InvalidResult => Status::UnprocessableEntity,
+            ErrorCode::InvalidReport => Status::UnprocessableEntity,
+            ErrorCode::Unauthorized => Status::Unauthorized,
+            ErrorCode::NotFound => Status::NotFound,
+            ErrorCode::RequestInProgress => Status::Conflict,
+            ErrorCode::QuotaExceeded => Status::TooManyRequests,
+            ErrorCode::QueueUnavailable => Status::ServiceUnavailable,
+            ErrorCode::ArtifactsUnavailable => Status::ServiceUnavailable,
+            ErrorCode::LogsUnavailable => Status::ServiceUnavailable,
+            ErrorCode::Internal => Status::InternalServerError,
+        }
+    }
+}
+
+/// A failed request. Responds with the code's HTTP status and a JSON body:
+/// {
+///     "status": "failed",
+///     "error": {
+///         "code": "invalid_batch",
+///         "message": "Batch failed validation",
+///         "details": ["jobs[2].job_id: duplicate job ID 'EKKFKWaBJZ'"] - Optional
+///     }
+/// }
+#[derive(Debug)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub message: String,
+    pub details: Vec<String>,
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> ApiError {
+        ApiError {
+            code,
+            message: message.into(),
+            details: Vec::new(),
+        }
+    }
+
+    pub fn with_details(mut self, details: Vec<String>) -> ApiError {
+        self.details = details;
+        self
+    }
+
+    pub fn body(&self) -> Value {
+        let mut error = json!({
+            "code": self.code.as_str(),
+            "message": self.message,
+        });
+        if !self.details.is_empty() {
+            error["details"] = json!(self.details);
+        }
+
+        json!({
+            "status": "failed",
+            "error": error,
+        })
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        (self.code.status(), self.body()).respond_to(req)
+    }
+}
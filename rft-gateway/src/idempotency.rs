@@ -0,0 +1,213 @@
+use deadpool_redis::Connection;
+use redis::{AsyncCommands, RedisResult};
+use rft_core::batch::Batch;
+use rft_core::job::canonical_params;
+use rft_core::queue::Keys;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::json::{serde_json::json, Value};
+use sha2::{Digest, Sha256};
+
+/// Longest idempotency key accepted
+pub const MAX_KEY_LENGTH: usize = 128;
+
+/// How long a key stays reserved while its first request is handled. A few times longer than
+/// queueing a batch can take with Redis' connection timeouts, so a gateway that dies mid request
+/// doesn't block retries with the key for the whole window.
+const PENDING_SECONDS: u64 = 60;
+
+/// The optional `Idempotency-Key` header. Requests repeated with the same key within the
+/// configured window get the original response instead of queueing the batch again.
+pub struct IdempotencyKey(pub Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IdempotencyKey {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(IdempotencyKey(
+            req.headers()
+                .get_one("Idempotency-Key")
+                .map(|key| key.to_string()),
+        ))
+    }
+}
+
+/// What the gateway has recorded for an idempotency key
+pub enum Recorded {
+    /// the key hasn't been seen, it is now reserved for this request
+    New,
+    /// another request with the key is still being handled
+    Pending,
+    /// a request with the key already completed with this response
+    Completed(Status, Value),
+    /// the key was already used for a different batch
+    Reused,
+}
+
+/// Whether a key is safe to store, i.e. a UUID or random hex string
+pub fn is_valid_key(key: &str) -> bool {
+    !key.is_empty() && key.len() <= MAX_KEY_LENGTH && key.chars().all(|c| c.is_ascii_graphic())
+}
+
+/// Hex encoded SHA-256 digest of a submitted batch, used to tell a retry from a different batch
+/// sent with the same key. Job params are sorted as their order isn't kept when deserializing.
+pub fn fingerprint(batch: &Batch) -> String {
+    let mut hasher = Sha256::new();
+    let mut without_params = batch.clone();
+    for job in without_params.jobs.iter_mut() {
+        hasher.update(canonical_params(&job.params).as_bytes());
+        job.params.clear();
+    }
+    hasher.update(
+        serde_json::to_string(&without_params)
+            .unwrap_or_default()
+            .as_bytes(),
+    );
+
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Reserves a key for a new request, or returns what was recorded for it
+pub async fn reserve(
+    conn: &mut Connection,
+    keys: &Keys,
+    principal: &str,
+    key: &str,
+    fingerprint: &str,
+) -> RedisResult<Recorded> {
+    // Keys are scoped to the principal so users can't read each other's responses
+    let redis_key = keys.idempotency(principal, key);
+    let pending = json!({ "fingerprint": fingerprint });
+    let reserved: Option<String> = redis::cmd("SET")
+        .arg(&redis_key)
+        .arg(pending.to_string())
+        .arg("NX")
+        .arg("EX")
+        .arg(PENDING_SECONDS)
+        .query_async(conn)
+        .await?;
+    if reserved.is_some() {
+        return Ok(Recorded::New);
+    }
+
+    let recorded: Option<String> = conn.get(&redis_key).await?;
+    Ok(recorded_state(recorded.as_deref(), fingerprint))
+}
+
+/// Reads a recorded key, which may have expired between reserving and reading it
+fn recorded_state(recorded: Option<&str>, fingerprint: &str) -> Recorded {
+    let recorded = match recorded.and_then(|recorded| serde_json::from_str::<Value>(recorded).ok())
+    {
+        Some(recorded) => recorded,
+        None => return Recorded::Pending,
+    };
+    if recorded["fingerprint"].as_str() != Some(fingerprint) {
+        return Recorded::Reused;
+    }
+
+    match recorded["status"].as_u64() {
+        Some(status) => Recorded::Completed(
+            Status::from_code(status as u16).unwrap_or(Status::Ok),
+            recorded["body"].clone(),
+        ),
+        None => Recorded::Pending,
+    }
+}
+
+/// Records the response to a request so retries with the same key can be answered with it
+#[allow(clippy::too_many_arguments)]
+pub async fn complete(
+    conn: &mut Connection,
+    keys: &Keys,
+    principal: &str,
+    key: &str,
+    fingerprint: &str,
+    window_seconds: u64,
+    status: Status,
+    body: &Value,
+) -> RedisResult<()> {
+    let response = json!({
+        "fingerprint": fingerprint,
+        "status": status.code,
+        "body": body,
+    });
+    conn.set_ex(
+        keys.idempotency(principal, key),
+        response.to_string(),
+        window_seconds as usize,
+    )
+    .await
+}
+
+/// Forgets a key after a request failed, so it can be retried with the same key
+pub async fn release(
+    conn: &mut Connection,
+    keys: &Keys,
+    principal: &str,
+    key: &str,
+) -> RedisResult<()> {
+    conn.del(keys.idempotency(principal, key)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::idempotency::{fingerprint, recorded_state, Recorded};
+    use rft_core::batch::Batch;
+    use rft_core::job::Job;
+    use rocket::http::Status;
+    use rocket::serde::json::serde_json::json;
+    use std::collections::HashMap;
+
+    fn batch(params: &[(&str, i64)]) -> Batch {
+        let mut batch = Batch::new("matt", "main.py", "git@github.com:x/y.git", "main");
+        let params: HashMap<_, _> = params
+            .iter()
+            .map(|(name, value)| (name.to_string(), json!(value)))
+            .collect();
+        batch.jobs.push(Job::new(params));
+        batch
+    }
+
+    #[test]
+    fn fingerprints_ignore_param_order() {
+        let first = batch(&[("start_date", 1980), ("end_date", 2020)]);
+        let mut second = batch(&[("end_date", 2020), ("start_date", 1980)]);
+        second.batch_id = first.batch_id.clone();
+        second.jobs[0].job_id = first.jobs[0].job_id.clone();
+        assert_eq!(fingerprint(&first), fingerprint(&second));
+
+        second.jobs[0]
+            .params
+            .insert("start_date".to_string(), json!(1990));
+        assert_ne!(fingerprint(&first), fingerprint(&second));
+    }
+
+    #[test]
+    fn replays_only_the_same_batch() {
+        let completed =
+            json!({ "fingerprint": "abc", "status": 201, "body": { "batch_id": "b1" } });
+        match recorded_state(Some(&completed.to_string()), "abc") {
+            Recorded::Completed(status, body) => {
+                assert_eq!(status, Status::Created);
+                assert_eq!(body["batch_id"], "b1");
+            }
+            _ => panic!("Expected the recorded response"),
+        }
+        assert!(matches!(
+            recorded_state(Some(&completed.to_string()), "def"),
+            Recorded::Reused
+        ));
+
+        let pending = json!({ "fingerprint": "abc" });
+        assert!(matches!(
+            recorded_state(Some(&pending.to_string()), "abc"),
+            Recorded::Pending
+        ));
+        assert!(matches!(recorded_state(None, "abc"), Recorded::Pending));
+    }
+}
@@ -0,0 +1,245 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use deadpool_redis::Connection;
+use hyper::client::HttpConnector;
+use hyper::{Body, Client as HttpClient, StatusCode};
+use hyper_tls::HttpsConnector;
+use k8s_openapi::api::core::v1::Pod;
+use kube::api::{Api, LogParams};
+use kube::Client;
+use redis::AsyncCommands;
+use rft_core::log_store::{self, LogStore};
+use rft_core::manifest;
+use rft_core::queue::Keys;
+use rocket::futures::stream::{self, BoxStream, StreamExt};
+use rocket::tokio::sync::mpsc;
+
+use crate::error::{ApiError, ErrorCode};
+use crate::store::queue_unavailable;
+
+/// Chunks of a followed log buffered before the client has read them
+const FOLLOW_BUFFER: usize = 64;
+
+/// Reads job logs from the worker containers of their pods through the Kubernetes API, or
+/// from the log store once the controller has archived them, managed as Rocket state. Only
+/// archived logs are available when the gateway runs outside a cluster.
+pub struct PodLogs {
+    pods: Option<Api<Pod>>,
+    archive: LogStore,
+    http: HttpClient<HttpsConnector<HttpConnector>, Body>,
+}
+
+impl PodLogs {
+    pub fn new(client: Option<Client>, archive: LogStore) -> PodLogs {
+        PodLogs {
+            pods: client.map(|client| Api::namespaced(client, "default")),
+            archive,
+            http: HttpClient::builder().build(HttpsConnector::new()),
+        }
+    }
+
+    /// A job's logs, read from its most recent pod while the pod exists and from the archive
+    /// after Kubernetes removes it. Following archived logs returns them without waiting.
+    pub async fn job_logs(
+        &self,
+        conn: &mut Connection,
+        keys: &Keys,
+        batch_id: &str,
+        job_id: &str,
+        follow: bool,
+        tail_lines: Option<usize>,
+    ) -> Result<BoxStream<'static, String>, ApiError> {
+        let pod = PodLogs::latest_pod(conn, keys, batch_id, job_id).await?;
+        let live_error = match &pod {
+            Some(pod) if self.pods.is_some() => {
+                match self.stream(pod, follow, tail_lines.map(|n| n as i64)).await {
+                    Ok(logs) => return Ok(logs),
+                    Err(err) if err.code == ErrorCode::NotFound => err,
+                    Err(err) => return Err(err),
+                }
+            }
+            Some(_) => ApiError::new(
+                ErrorCode::LogsUnavailable,
+                "Logs are unavailable, the gateway isn't connected to Kubernetes",
+            ),
+            None => ApiError::new(
+                ErrorCode::NotFound,
+                format!("Job {} hasn't started a pod yet", job_id),
+            ),
+        };
+
+        match self.archived(conn, keys, batch_id, job_id).await? {
+            Some(logs) => {
+                let logs = match tail_lines {
+                    Some(lines) => log_store::tail(&logs, lines).to_string(),
+                    None => logs,
+                };
+                Ok(stream::once(async move { logs }).boxed())
+            }
+            None => Err(live_error),
+        }
+    }
+
+    /// Name of the most recent pod that ran a job, as recorded by the controller
+    async fn latest_pod(
+        conn: &mut Connection,
+        keys: &Keys,
+        batch_id: &str,
+        job_id: &str,
+    ) -> Result<Option<String>, ApiError> {
+        let pods: Option<String> = conn
+            .hget(keys.job_pods(batch_id), job_id)
+            .await
+            .map_err(queue_unavailable)?;
+
+        Ok(pods
+            .and_then(|pods| serde_json::from_str::<Vec<String>>(&pods).ok())
+            .and_then(|mut pods| pods.pop()))
+    }
+
+    /// Logs the controller archived for a job, if any
+    async fn archived(
+        &self,
+        conn: &mut Connection,
+        keys: &Keys,
+        batch_id: &str,
+        job_id: &str,
+    ) -> Result<Option<String>, ApiError> {
+        match &self.archive {
+            LogStore::Redis { .. } => conn
+                .hget(keys.job_logs(batch_id), job_id)
+                .await
+                .map_err(queue_unavailable),
+            LogStore::Filesystem { dir, .. } => {
+                let path = LogStore::file_path(dir, batch_id, job_id);
+                match rocket::tokio::fs::read_to_string(&path).await {
+                    Ok(logs) => Ok(Some(logs)),
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                    Err(err) => Err(archive_unavailable(&format!(
+                        "unable to read {}: {}",
+                        path.display(),
+                        err
+                    ))),
+                }
+            }
+            LogStore::ObjectStorage { bucket, .. } => {
+                let unix_now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or_default();
+                let url = bucket
+                    .download_url(&LogStore::object_key(batch_id, job_id), 60, unix_now)
+                    .map_err(|err| archive_unavailable(&err.to_string()))?;
+                let uri = url
+                    .parse()
+                    .map_err(|_| archive_unavailable("invalid log URL"))?;
+                let response = self
+                    .http
+                    .get(uri)
+                    .await
+                    .map_err(|err| archive_unavailable(&err.to_string()))?;
+                let status = response.status();
+                if status == StatusCode::NOT_FOUND {
+                    return Ok(None);
+                }
+                let body = hyper::body::to_bytes(response.into_body())
+                    .await
+                    .map_err(|err| archive_unavailable(&err.to_string()))?;
+                if !status.is_success() {
+                    return Err(archive_unavailable(&format!("bucket responded {}", status)));
+                }
+                Ok(Some(String::from_utf8_lossy(&body).to_string()))
+            }
+        }
+    }
+
+    /// Streams a pod's logs. Without `follow` the stream ends with the logs written so far,
+    /// otherwise it continues until the container exits or the client disconnects.
+    async fn stream(
+        &self,
+        pod: &str,
+        follow: bool,
+        tail_lines: Option<i64>,
+    ) -> Result<BoxStream<'static, String>, ApiError> {
+        let pods = self.pods.clone().ok_or_else(|| {
+            ApiError::new(
+                ErrorCode::LogsUnavailable,
+                "Logs are unavailable, the gateway isn't connected to Kubernetes",
+            )
+        })?;
+        let params = LogParams {
+            container: Some(manifest::WORKER_CONTAINER.to_string()),
+            follow,
+            tail_lines,
+            ..LogParams::default()
+        };
+
+        if !follow {
+            let logs = pods
+                .logs(pod, &params)
+                .await
+                .map_err(|err| logs_error(pod, err))?;
+            return Ok(stream::once(async move { logs }).boxed());
+        }
+
+        // The log stream borrows the API client, so forward it from a task which owns both.
+        // The task ends when the container exits or the client disconnects.
+        let mut logs = pods
+            .log_stream(pod, &params)
+            .await
+            .map_err(|err| logs_error(pod, err))?
+            .boxed();
+        let (sender, receiver) = mpsc::channel(FOLLOW_BUFFER);
+        let pod = pod.to_string();
+        rocket::tokio::spawn(async move {
+            while let Some(chunk) = logs.next().await {
+                let chunk = match chunk {
+                    Ok(chunk) => String::from_utf8_lossy(&chunk).to_string(),
+                    Err(err) => {
+                        eprintln!("Error following logs of pod {}: {}", pod, err);
+                        break;
+                    }
+                };
+                if sender.send(chunk).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|chunk| (chunk, receiver))
+        })
+        .boxed())
+    }
+}
+
+fn logs_error(pod: &str, err: kube::Error) -> ApiError {
+    match err {
+        kube::Error::Api(e) if e.code == 404 => ApiError::new(
+            ErrorCode::NotFound,
+            format!(
+                "Pod {} no longer exists, its logs were removed with it",
+                pod
+            ),
+        ),
+        // i.e. the container is still waiting to start
+        kube::Error::Api(e) if e.code == 400 => {
+            ApiError::new(ErrorCode::LogsUnavailable, e.message)
+        }
+        err => {
+            eprintln!("Unable to read logs of pod {}: {}", pod, err);
+            ApiError::new(
+                ErrorCode::LogsUnavailable,
+                "Logs are unavailable, try again later",
+            )
+        }
+    }
+}
+
+fn archive_unavailable(reason: &str) -> ApiError {
+    eprintln!("Unable to read archived logs: {}", reason);
+    ApiError::new(
+        ErrorCode::LogsUnavailable,
+        "Archived logs are unavailable, try again later",
+    )
+}
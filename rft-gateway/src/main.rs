@@ -1,76 +1,864 @@
 #[macro_use]
 extern crate rocket;
 
-use std::env;
+mod artifacts;
+mod auth;
+mod error;
+mod idempotency;
+mod logs;
+mod progress;
+mod quota;
+mod results;
+mod store;
+mod validation;
 
-use redis::{Commands, ConnectionAddr, ConnectionInfo, RedisConnectionInfo, RedisResult};
-use rft_core::batch::Batch;
-use rocket::serde::json::{serde_json::json, Json, Value};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use artifacts::Artifacts;
+use auth::{AuthConfig, JobAuth, Principal, Reporter};
+use deadpool_redis::Connection;
+use error::{ApiError, ErrorCode};
+use idempotency::{IdempotencyKey, Recorded};
+use logs::PodLogs;
+use quota::QuotaConfig;
+use redis::AsyncCommands;
+use rft_core::artifacts::ArtifactStore;
+use rft_core::batch::{new_batch_id, Batch};
+use rft_core::job_token::JobTokens;
+use rft_core::log_store::LogStore;
+use rft_core::queue::{self, Dependencies, Keys};
+use rft_core::redis_config::RedisConfig;
+use rocket::fairing::AdHoc;
+use rocket::futures::stream::BoxStream;
+use rocket::http::Status;
+use rocket::response::stream::TextStream;
+use rocket::serde::json::{self, serde_json::json, Json, Value};
+use rocket::serde::Deserialize;
+use rocket::{Build, Request, Rocket, State};
+use store::{queue_unavailable, Store};
+use validation::{validate_batch, BatchLimits};
+
+/// Attempts at finding an unused batch ID before giving up
+const MAX_ID_ATTEMPTS: usize = 5;
+
+/// Gateway settings read from Rocket's configuration, i.e. Rocket.toml or ROCKET_* env vars
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct GatewayConfig {
+    /// drop content addressed jobs which have already succeeded before queueing a batch
+    #[serde(default)]
+    skip_succeeded_jobs: bool,
+    #[serde(default)]
+    batch_limits: BatchLimits,
+    /// how long responses to requests with an Idempotency-Key header are kept for retries
+    #[serde(default = "default_idempotency_window_seconds")]
+    idempotency_window_seconds: u64,
+    /// principals, i.e. the workers' service token, allowed to post results for any batch
+    #[serde(default)]
+    result_writers: Vec<String>,
+}
+
+fn default_idempotency_window_seconds() -> u64 {
+    24 * 60 * 60
+}
 
 #[get("/health")]
 fn health_check() -> &'static str {
     "Healthy!"
 }
 
+#[get("/whoami")]
+fn whoami(principal: Principal) -> Value {
+    json!({
+        "principal": principal.name,
+        "team": principal.team,
+    })
+}
+
 #[post("/batch", format = "json", data = "<batch>")]
-fn create_batch(batch: Json<Batch>) -> Value {
+async fn create_batch(
+    batch: Result<Json<Batch>, json::Error<'_>>,
+    principal: Principal,
+    idempotency_key: IdempotencyKey,
+    store: &State<Store>,
+    config: &State<GatewayConfig>,
+    quota_config: &State<QuotaConfig>,
+) -> Result<(Status, Value), ApiError> {
+    let batch = match batch {
+        Ok(batch) => batch.into_inner(),
+        Err(json::Error::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+            return Err(ApiError::new(
+                ErrorCode::PayloadTooLarge,
+                "Batch is larger than the gateway accepts, split it into smaller batches",
+            ))
+        }
+        Err(json::Error::Io(e)) => {
+            return Err(ApiError::new(
+                ErrorCode::MalformedJson,
+                format!("Unable to read request body: {}", e),
+            ))
+        }
+        Err(json::Error::Parse(_, e)) if e.is_data() => {
+            return Err(ApiError::new(
+                ErrorCode::InvalidBatch,
+                format!("Batch does not match the expected format: {}", e),
+            ))
+        }
+        Err(json::Error::Parse(_, e)) => {
+            return Err(ApiError::new(
+                ErrorCode::MalformedJson,
+                format!("Request body is not valid JSON: {}", e),
+            ))
+        }
+    };
+
+    let key = match idempotency_key.0 {
+        Some(key) => key,
+        None => return accept_batch(batch, principal, store, config, quota_config).await,
+    };
+    if !idempotency::is_valid_key(&key) {
+        return Err(ApiError::new(
+            ErrorCode::InvalidIdempotencyKey,
+            format!(
+                "Idempotency-Key must be 1 to {} printable ASCII characters",
+                idempotency::MAX_KEY_LENGTH
+            ),
+        ));
+    }
+
+    // Reserve the key before queueing so a retry racing the original request can't queue the
+    // batch twice. Only successful responses are kept, failed requests can be retried.
+    let window = config.idempotency_window_seconds;
+    let principal_name = principal.name.clone();
+    let fingerprint = idempotency::fingerprint(&batch);
+    let mut conn = store.connection().await?;
+    match idempotency::reserve(&mut conn, &store.keys, &principal_name, &key, &fingerprint)
+        .await
+        .map_err(queue_unavailable)?
+    {
+        Recorded::Completed(status, body) => return Ok((status, body)),
+        Recorded::Pending => {
+            return Err(ApiError::new(
+                ErrorCode::RequestInProgress,
+                "A request with this Idempotency-Key is still being handled, retry shortly",
+            ))
+        }
+        Recorded::Reused => {
+            return Err(ApiError::new(
+                ErrorCode::IdempotencyKeyReused,
+                "This Idempotency-Key was already used for a different batch",
+            ))
+        }
+        Recorded::New => {}
+    }
+
+    let response = accept_batch(batch, principal, store, config, quota_config).await;
+    let recorded = match &response {
+        Ok((status, body)) => {
+            idempotency::complete(
+                &mut conn,
+                &store.keys,
+                &principal_name,
+                &key,
+                &fingerprint,
+                window,
+                *status,
+                body,
+            )
+            .await
+        }
+        Err(_) => idempotency::release(&mut conn, &store.keys, &principal_name, &key).await,
+    };
+    if let Err(err) = recorded {
+        eprintln!("Failed to record Idempotency-Key {}: {}", &key, err);
+    }
+
+    response
+}
+
+/// Validates and queues a submitted batch
+async fn accept_batch(
+    mut batch: Batch,
+    principal: Principal,
+    store: &Store,
+    config: &GatewayConfig,
+    quota_config: &QuotaConfig,
+) -> Result<(Status, Value), ApiError> {
+    batch.assign_ids();
+    let mut problems = validate_batch(&batch, &config.batch_limits);
+    let mut conn = store.connection().await?;
+    if problems.is_empty() {
+        // Batches can only wait on batches their owner can see, which also rules out cycles
+        for (index, predecessor) in batch.depends_on.iter().enumerate() {
+            let visible = store
+                .any_batch(&mut conn, predecessor)
+                .await?
+                .is_some_and(|predecessor| principal.can_access(&predecessor));
+            if !visible {
+                problems.push(format!("depends_on[{}]: no batch {}", index, predecessor));
+            }
+        }
+    }
+    if !problems.is_empty() {
+        return Err(
+            ApiError::new(ErrorCode::InvalidBatch, "Batch failed validation")
+                .with_details(problems),
+        );
+    }
+
+    batch.principal = Some(principal.name);
+    batch.team = principal.team;
+    batch.submitted_at = Some(unix_now());
+
     println!(
-        "Recieved batch with ID: {} from author: {} with {} jobs to process using file: {}",
+        "Recieved batch with ID: {} from author: {} (authenticated as {}) with {} jobs to process using file: {}",
         &batch.batch_id,
         &batch.author,
+        batch.owner(),
         &batch.jobs.len(),
         &batch.source_file
     );
 
-    match push_batch_to_redis(batch.into_inner()) {
-        Ok(_) => {
-            return json!({
-                "status": "ok",
+    let (batch, skipped_jobs) = queue_batch(
+        &mut conn,
+        &store.keys,
+        batch,
+        config.skip_succeeded_jobs,
+        quota_config,
+    )
+    .await
+    .unwrap_or_else(|err| Err(queue_unavailable(err)))?;
+
+    Ok((
+        Status::Created,
+        json!({
+            "status": "ok",
+            "batch_id": batch.batch_id,
+            "job_ids": batch.jobs.iter().map(|job| &job.job_id).collect::<Vec<&String>>(),
+            "skipped_jobs": skipped_jobs,
+            "status_url": format!("/batch/{}", batch.batch_id),
+        }),
+    ))
+}
+
+/// Reports where a batch is in its lifecycle: queued, running, completed, failed or deleted.
+/// Only the batch's owner, or members of the same team, can see it.
+#[get("/batch/<batch_id>")]
+async fn batch_status(
+    batch_id: &str,
+    principal: Principal,
+    store: &State<Store>,
+) -> Result<Value, ApiError> {
+    let mut conn = store.connection().await?;
+    let batch = store.batch(&mut conn, batch_id, &principal).await?;
+    let (running, outcome): (bool, Option<String>) = redis::pipe()
+        .sismember(store.keys.running_batches(), batch_id)
+        .hget(store.keys.batch_outcomes(), batch_id)
+        .query_async(&mut conn)
+        .await
+        .map_err(queue_unavailable)?;
+
+    let state = match outcome {
+        Some(outcome) => outcome,
+        None if running => "running".to_string(),
+        None if waiting(&mut conn, &store.keys, &batch).await? => "waiting".to_string(),
+        None => "queued".to_string(),
+    };
+
+    Ok(json!({
+        "batch_id": batch.batch_id,
+        "state": state,
+        "owner": batch.owner(),
+        "team": batch.team,
+        "priority": batch.priority,
+        "submitted_at": batch.submitted_at,
+        "source_file": batch.source_file,
+        "depends_on": batch.depends_on,
+        "jobs": batch.jobs.len(),
+    }))
+}
+
+/// Whether a queued batch is waiting for the batches it depends on. Batches whose
+/// predecessors failed stay waiting until the controller fails them.
+async fn waiting(conn: &mut Connection, keys: &Keys, batch: &Batch) -> Result<bool, ApiError> {
+    if batch.depends_on.is_empty() {
+        return Ok(false);
+    }
+    let mut pipe = redis::pipe();
+    for predecessor in &batch.depends_on {
+        pipe.hget(keys.batch_outcomes(), predecessor);
+    }
+    let outcomes: Vec<Option<String>> = pipe.query_async(conn).await.map_err(queue_unavailable)?;
+    let outcomes: Vec<(String, Option<String>)> =
+        batch.depends_on.iter().cloned().zip(outcomes).collect();
+
+    Ok(queue::dependencies(&outcomes) != Dependencies::Ready)
+}
+
+/// Records the result of a job, a JSON object of metrics or summary values. Results can be
+/// posted by the job itself with its job token, the batch's owner, their team, or one of the
+/// configured `result_writers`.
+#[post(
+    "/batch/<batch_id>/jobs/<job_id>/result",
+    format = "json",
+    data = "<result>"
+)]
+async fn post_result(
+    batch_id: &str,
+    job_id: &str,
+    result: Result<Json<Value>, json::Error<'_>>,
+    reporter: Reporter,
+    store: &State<Store>,
+    config: &State<GatewayConfig>,
+    job_auth: &State<JobAuth>,
+) -> Result<(Status, Value), ApiError> {
+    let result = read_report(result, "Result")?;
+    let result_json = results::check_result(&result, config.batch_limits.max_result_bytes)?;
+
+    let mut conn = store.connection().await?;
+    reported_batch(
+        &mut conn, store, config, job_auth, reporter, batch_id, job_id,
+    )
+    .await?;
+    results::record(&mut conn, &store.keys, batch_id, job_id, result_json)
+        .await
+        .map_err(queue_unavailable)?;
+
+    Ok((Status::Created, json!({ "status": "ok" })))
+}
+
+/// Records how far through a job is, `{"fraction": 0.4, "message": "epoch 2/5"}`, replacing
+/// its previous progress. Posted by the same callers as results.
+#[post(
+    "/batch/<batch_id>/jobs/<job_id>/progress",
+    format = "json",
+    data = "<progress>"
+)]
+async fn post_progress(
+    batch_id: &str,
+    job_id: &str,
+    progress: Result<Json<Value>, json::Error<'_>>,
+    reporter: Reporter,
+    store: &State<Store>,
+    config: &State<GatewayConfig>,
+    job_auth: &State<JobAuth>,
+) -> Result<(Status, Value), ApiError> {
+    let progress = read_report(progress, "Progress")?;
+    let progress_json = progress::check_progress(&progress, unix_now())?;
+
+    let mut conn = store.connection().await?;
+    reported_batch(
+        &mut conn, store, config, job_auth, reporter, batch_id, job_id,
+    )
+    .await?;
+    progress::record_progress(&mut conn, &store.keys, batch_id, job_id, progress_json)
+        .await
+        .map_err(queue_unavailable)?;
+
+    Ok((Status::Created, json!({ "status": "ok" })))
+}
+
+/// Appends a value to one of a job's metric series, `{"name": "loss", "value": 0.12,
+/// "step": 10}`. Posted by the same callers as results.
+#[post(
+    "/batch/<batch_id>/jobs/<job_id>/metrics",
+    format = "json",
+    data = "<metric>"
+)]
+async fn post_metric(
+    batch_id: &str,
+    job_id: &str,
+    metric: Result<Json<Value>, json::Error<'_>>,
+    reporter: Reporter,
+    store: &State<Store>,
+    config: &State<GatewayConfig>,
+    job_auth: &State<JobAuth>,
+) -> Result<(Status, Value), ApiError> {
+    let metric = read_report(metric, "Metric")?;
+    let metric_json = progress::check_metric(&metric, unix_now())?;
+
+    let mut conn = store.connection().await?;
+    reported_batch(
+        &mut conn, store, config, job_auth, reporter, batch_id, job_id,
+    )
+    .await?;
+    progress::record_metric(&mut conn, &store.keys, batch_id, job_id, metric_json)
+        .await
+        .map_err(queue_unavailable)?;
+
+    Ok((Status::Created, json!({ "status": "ok" })))
+}
+
+/// Lists every metric a job has logged, oldest first
+#[get("/batch/<batch_id>/jobs/<job_id>/metrics")]
+async fn job_metrics(
+    batch_id: &str,
+    job_id: &str,
+    principal: Principal,
+    store: &State<Store>,
+) -> Result<Value, ApiError> {
+    let mut conn = store.connection().await?;
+    let batch = store.batch(&mut conn, batch_id, &principal).await?;
+    if !batch.jobs.iter().any(|job| job.job_id == job_id) {
+        return Err(ApiError::new(
+            ErrorCode::NotFound,
+            format!("Batch {} has no job {}", batch_id, job_id),
+        ));
+    }
+    let metrics = progress::metrics(&mut conn, &store.keys, batch_id, job_id)
+        .await
+        .map_err(queue_unavailable)?;
+
+    Ok(json!({
+        "batch_id": batch.batch_id,
+        "job_id": job_id,
+        "metrics": metrics,
+    }))
+}
+
+/// Reads the JSON body of a result, progress or metric report
+fn read_report(body: Result<Json<Value>, json::Error<'_>>, kind: &str) -> Result<Value, ApiError> {
+    match body {
+        Ok(body) => Ok(body.into_inner()),
+        Err(json::Error::Io(e)) => Err(ApiError::new(
+            ErrorCode::MalformedJson,
+            format!("Unable to read request body: {}", e),
+        )),
+        Err(json::Error::Parse(_, e)) => Err(ApiError::new(
+            ErrorCode::MalformedJson,
+            format!("{} is not valid JSON: {}", kind, e),
+        )),
+    }
+}
+
+/// Loads the batch a job report is posted for. A job token is only accepted for the job it was
+/// issued to, `result_writers` may report for any batch, and other users must be able to
+/// access the batch.
+async fn reported_batch(
+    conn: &mut Connection,
+    store: &Store,
+    config: &GatewayConfig,
+    job_auth: &JobAuth,
+    reporter: Reporter,
+    batch_id: &str,
+    job_id: &str,
+) -> Result<Batch, ApiError> {
+    let batch = match reporter {
+        Reporter::Job(token) => {
+            let valid = job_auth
+                .0
+                .as_ref()
+                .is_some_and(|tokens| tokens.verify(batch_id, job_id, &token));
+            if !valid {
+                return Err(ApiError::new(
+                    ErrorCode::Unauthorized,
+                    format!(
+                        "Job token is not valid for job {} of batch {}",
+                        job_id, batch_id
+                    ),
+                ));
+            }
+            store.any_batch(conn, batch_id).await?
+        }
+        Reporter::User(principal) if config.result_writers.contains(&principal.name) => {
+            store.any_batch(conn, batch_id).await?
+        }
+        Reporter::User(principal) => Some(store.batch(conn, batch_id, &principal).await?),
+    }
+    .ok_or_else(|| ApiError::new(ErrorCode::NotFound, format!("No batch {}", batch_id)))?;
+
+    if !batch.jobs.iter().any(|job| job.job_id == job_id) {
+        return Err(ApiError::new(
+            ErrorCode::NotFound,
+            format!("Batch {} has no job {}", batch_id, job_id),
+        ));
+    }
+
+    Ok(batch)
+}
+
+/// Lists every job in a batch with its params and result
+#[get("/batch/<batch_id>/results")]
+async fn batch_results(
+    batch_id: &str,
+    principal: Principal,
+    store: &State<Store>,
+) -> Result<Value, ApiError> {
+    let mut conn = store.connection().await?;
+    let batch = store.batch(&mut conn, batch_id, &principal).await?;
+
+    results::list(&mut conn, &store.keys, &batch)
+        .await
+        .map_err(queue_unavailable)
+}
+
+/// Lists the artifacts uploaded by every job in a batch, or a single job when `job` is given,
+/// each with a download URL valid for an hour
+#[get("/batch/<batch_id>/artifacts?<job>")]
+async fn batch_artifacts(
+    batch_id: &str,
+    job: Option<&str>,
+    principal: Principal,
+    store: &State<Store>,
+    artifacts: &State<Artifacts>,
+) -> Result<Value, ApiError> {
+    let mut conn = store.connection().await?;
+    let batch = store.batch(&mut conn, batch_id, &principal).await?;
+
+    Ok(json!({
+        "batch_id": batch.batch_id,
+        "artifacts": artifacts.list(batch_id, job, unix_now()).await?,
+    }))
+}
+
+/// Lists a batch's jobs with their completion index, the index of the pod running them, and
+/// the progress they last reported or null
+#[get("/batch/<batch_id>/jobs")]
+async fn batch_jobs(
+    batch_id: &str,
+    principal: Principal,
+    store: &State<Store>,
+) -> Result<Value, ApiError> {
+    let mut conn = store.connection().await?;
+    let batch = store.batch(&mut conn, batch_id, &principal).await?;
+    let mut progress = progress::batch_progress(&mut conn, &store.keys, batch_id)
+        .await
+        .map_err(queue_unavailable)?;
+    let jobs: Vec<Value> = batch
+        .jobs
+        .iter()
+        .enumerate()
+        .map(|(index, job)| {
+            json!({
+                "index": index,
+                "job_id": job.job_id,
+                "params": job.params,
+                "progress": progress.remove(&job.job_id),
             })
+        })
+        .collect();
+
+    Ok(json!({
+        "batch_id": batch.batch_id,
+        "jobs": jobs,
+    }))
+}
+
+/// Returns a job's logs as plain text, from its most recent pod or from the log archive once
+/// the pod is gone. With `follow=true` the response streams new output until the job's
+/// container exits.
+#[get("/batch/<batch_id>/jobs/<job_id>/logs?<follow>&<tail>")]
+async fn job_logs(
+    batch_id: &str,
+    job_id: &str,
+    follow: Option<bool>,
+    tail: Option<usize>,
+    principal: Principal,
+    store: &State<Store>,
+    logs: &State<PodLogs>,
+) -> Result<TextStream<BoxStream<'static, String>>, ApiError> {
+    let mut conn = store.connection().await?;
+    let batch = store.batch(&mut conn, batch_id, &principal).await?;
+    if !batch.jobs.iter().any(|job| job.job_id == job_id) {
+        return Err(ApiError::new(
+            ErrorCode::NotFound,
+            format!("Batch {} has no job {}", batch_id, job_id),
+        ));
+    }
+    let logs = logs
+        .job_logs(
+            &mut conn,
+            &store.keys,
+            batch_id,
+            job_id,
+            follow.unwrap_or(false),
+            tail,
+        )
+        .await?;
+
+    Ok(TextStream(logs))
+}
+
+/// Reports a single job: its params, progress, result and artifacts, or null artifacts if
+/// artifact storage isn't configured
+#[get("/batch/<batch_id>/jobs/<job_id>")]
+async fn job_status(
+    batch_id: &str,
+    job_id: &str,
+    principal: Principal,
+    store: &State<Store>,
+    artifacts: &State<Artifacts>,
+) -> Result<Value, ApiError> {
+    let mut conn = store.connection().await?;
+    let batch = store.batch(&mut conn, batch_id, &principal).await?;
+    let job = batch
+        .jobs
+        .iter()
+        .find(|job| job.job_id == job_id)
+        .ok_or_else(|| {
+            ApiError::new(
+                ErrorCode::NotFound,
+                format!("Batch {} has no job {}", batch_id, job_id),
+            )
+        })?;
+    let (result, progress): (Option<String>, Option<String>) = redis::pipe()
+        .hget(store.keys.results(batch_id), job_id)
+        .hget(store.keys.progress(batch_id), job_id)
+        .query_async(&mut conn)
+        .await
+        .map_err(queue_unavailable)?;
+    let artifacts = match artifacts.is_enabled() {
+        true => Some(artifacts.list(batch_id, Some(job_id), unix_now()).await?),
+        false => None,
+    };
+
+    Ok(json!({
+        "batch_id": batch.batch_id,
+        "job_id": job.job_id,
+        "params": job.params,
+        "progress": progress.and_then(|progress| serde_json::from_str::<Value>(&progress).ok()),
+        "result": result.and_then(|result| serde_json::from_str::<Value>(&result).ok()),
+        "artifacts": artifacts,
+    }))
+}
+
+#[catch(401)]
+fn unauthorized() -> ApiError {
+    ApiError::new(
+        ErrorCode::Unauthorized,
+        "A valid API token is required. Log in with 'rft-client login'",
+    )
+}
+
+#[catch(404)]
+fn not_found(req: &Request) -> ApiError {
+    ApiError::new(ErrorCode::NotFound, format!("No route for {}", req.uri()))
+}
+
+#[catch(default)]
+fn default_catcher(status: Status, _req: &Request) -> (Status, Value) {
+    (
+        status,
+        json!({
+            "status": "failed",
+            "error": {
+                "code": "request_failed",
+                "message": status.reason().unwrap_or("Request failed"),
+            },
+        }),
+    )
+}
+
+#[launch]
+fn rocket() -> _ {
+    rocket::build()
+        .mount(
+            "/",
+            routes![
+                create_batch,
+                batch_status,
+                post_result,
+                post_progress,
+                post_metric,
+                job_metrics,
+                batch_results,
+                batch_artifacts,
+                batch_jobs,
+                job_status,
+                job_logs,
+                health_check,
+                whoami
+            ],
+        )
+        .register("/", catchers![unauthorized, not_found, default_catcher])
+        .attach(AdHoc::config::<GatewayConfig>())
+        .attach(AdHoc::config::<AuthConfig>())
+        .attach(AdHoc::config::<QuotaConfig>())
+        .attach(AdHoc::try_on_ignite("Redis", connect_redis))
+        .attach(AdHoc::try_on_ignite("Artifacts", configure_artifacts))
+        .attach(AdHoc::try_on_ignite("Logs", configure_logs))
+        .attach(AdHoc::on_ignite("Job tokens", configure_job_tokens))
+}
+
+/// Reads the shared Redis configuration and manages a connection pool as Rocket state
+async fn connect_redis(rocket: Rocket<Build>) -> Result<Rocket<Build>, Rocket<Build>> {
+    let redis_config = match RedisConfig::from_env() {
+        Ok(redis_config) => redis_config,
+        Err(err) => {
+            eprintln!("FATAL - {}!", err);
+            return Err(rocket);
+        }
+    };
+
+    match Store::new(&redis_config) {
+        Ok(store) => {
+            println!("Using Redis at {}", redis_config);
+            Ok(rocket.manage(store))
         }
         Err(err) => {
-            if err.is_connection_refusal() {
-                println!("Error connecting to Redis");
-            } else if err.is_io_error() {
-                println!("IO Error");
-            } else {
-                eprintln!("{}", err);
-            }
+            eprintln!("FATAL - Unable to configure Redis connections: {}!", err);
+            Err(rocket)
+        }
+    }
+}
 
-            return json!({
-                "status": "failed"
-            });
+/// Reads the artifact bucket settings, see `ArtifactStore`. The gateway needs credentials for
+/// the bucket to list artifacts and sign download URLs.
+async fn configure_artifacts(rocket: Rocket<Build>) -> Result<Rocket<Build>, Rocket<Build>> {
+    match ArtifactStore::from_lookup(|name| std::env::var(name).ok()) {
+        Ok(Some(store)) if store.credentials.is_none() => {
+            eprintln!(
+                "FATAL - {}!",
+                rft_core::artifacts::Error::MissingCredentials
+            );
+            Err(rocket)
+        }
+        Ok(store) => {
+            if let Some(store) = &store {
+                println!("Listing artifacts from {}", store);
+            }
+            Ok(rocket.manage(Artifacts::new(store)))
+        }
+        Err(err) => {
+            eprintln!("FATAL - {}!", err);
+            Err(rocket)
         }
     }
 }
 
-#[launch]
-fn rocket() -> _ {
-    rocket::build().mount("/", routes![create_batch, health_check])
-}
-
-fn push_batch_to_redis(batch: Batch) -> redis::RedisResult<()> {
-    let redis_host = env::var("REDIS_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
-    let redis_password = env::var("REDIS_PASSWORD").unwrap_or_else(|_| "Mxu168c6OL".to_string());
-    let connection_details = ConnectionInfo {
-        addr: ConnectionAddr::Tcp(redis_host, 6379),
-        redis: RedisConnectionInfo {
-            db: 0,
-            username: None,
-            password: Some(redis_password),
-        },
+/// Reads the log store settings and connects to the Kubernetes API to read logs of running
+/// jobs. The gateway still starts without Kubernetes, i.e. when run locally, serving only
+/// archived logs.
+async fn configure_logs(rocket: Rocket<Build>) -> Result<Rocket<Build>, Rocket<Build>> {
+    let archive = match LogStore::from_lookup(|name| std::env::var(name).ok()) {
+        Ok(archive) => archive,
+        Err(err) => {
+            eprintln!("FATAL - {}!", err);
+            return Err(rocket);
+        }
+    };
+    let client = match kube::Client::try_default().await {
+        Ok(client) => Some(client),
+        Err(err) => {
+            eprintln!(
+                "Unable to connect to Kubernetes, only archived logs are available: {}",
+                err
+            );
+            None
+        }
     };
-    let client = redis::Client::open(connection_details)?;
-    let mut conn = client.get_connection()?;
 
-    match serde_json::to_string(&batch) {
-        Ok(batch_json) => {
-            if let Err(err) = conn.rpush::<&str, String, i32>("queued_batches", batch_json) {
-                return RedisResult::Err(err);
+    println!("Reading archived logs from {}", archive);
+    Ok(rocket.manage(PodLogs::new(client, archive)))
+}
+
+/// Reads the key job tokens are signed with, shared with the controller. Without it jobs can't
+/// report to the gateway themselves.
+async fn configure_job_tokens(rocket: Rocket<Build>) -> Rocket<Build> {
+    let tokens = JobTokens::from_lookup(|name| std::env::var(name).ok());
+    if tokens.is_some() {
+        println!("Accepting job tokens");
+    }
+    rocket.manage(JobAuth(tokens))
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Queues a batch on its owner's queue, returning the queued batch and the number of jobs
+/// skipped because they already succeeded. A batch left with no jobs is stored as completed
+/// rather than queued.
+async fn queue_batch(
+    conn: &mut Connection,
+    keys: &Keys,
+    mut batch: Batch,
+    skip_succeeded_jobs: bool,
+    quota_config: &QuotaConfig,
+) -> redis::RedisResult<Result<(Batch, usize), ApiError>> {
+    let mut skipped_jobs = 0;
+    if skip_succeeded_jobs {
+        let mut pipe = redis::pipe();
+        for job in &batch.jobs {
+            pipe.sismember(keys.succeeded_jobs(batch.owner()), &job.job_id);
+        }
+        let succeeded: Vec<bool> = pipe.query_async(conn).await?;
+
+        let total = batch.jobs.len();
+        let mut succeeded = succeeded.into_iter();
+        let jobs = std::mem::take(&mut batch.jobs);
+        batch.jobs = jobs
+            .into_iter()
+            .filter(|job| !(succeeded.next().unwrap_or(false) && batch.is_content_addressed(job)))
+            .collect();
+        skipped_jobs = total - batch.jobs.len();
+    }
+
+    // Reserve the batch ID with SET NX so a colliding ID can never overwrite an existing batch
+    let mut reserved = false;
+    for _ in 0..MAX_ID_ATTEMPTS {
+        let batch_json = match serde_json::to_string(&batch) {
+            Ok(batch_json) => batch_json,
+            Err(err) => {
+                eprintln!("Failed to serialize batch {}: {}", &batch.batch_id, err);
+                return Ok(Err(ApiError::new(
+                    ErrorCode::Internal,
+                    "Unable to store the batch",
+                )));
             }
+        };
+
+        let set: Option<String> = redis::cmd("SET")
+            .arg(keys.batch(&batch.batch_id))
+            .arg(batch_json)
+            .arg("NX")
+            .query_async(conn)
+            .await?;
+        if set.is_some() {
+            reserved = true;
+            break;
+        }
+        batch.batch_id = new_batch_id();
+    }
+    if !reserved {
+        return Ok(Err(ApiError::new(
+            ErrorCode::Internal,
+            "Unable to assign a unique batch ID",
+        )));
+    }
+
+    // The batch's ID still resolves, and batches can depend on it, when there's nothing to run
+    if batch.jobs.is_empty() {
+        println!(
+            "All jobs in batch {} have already succeeded, nothing to queue",
+            &batch.batch_id
+        );
+        let completed: redis::RedisResult<()> = conn
+            .hset(keys.batch_outcomes(), &batch.batch_id, "completed")
+            .await;
+        if completed.is_err() {
+            let _: redis::RedisResult<()> = conn.del(keys.batch(&batch.batch_id)).await;
         }
-        Err(_) => todo!(),
+        completed?;
+        return Ok(Ok((batch, skipped_jobs)));
+    }
+
+    // Only queued if the user and their team are within quota, don't leave a batch behind
+    // that will never be queued otherwise
+    let queued = quota::queue_within_quota(conn, keys, &quota_config.quotas, &batch).await;
+    if !matches!(queued, Ok(Ok(()))) {
+        let _: redis::RedisResult<()> = conn.del(keys.batch(&batch.batch_id)).await;
+    }
+    if let Err(exceeded) = queued? {
+        println!("Rejected batch: {}", &exceeded);
+        return Ok(Err(ApiError::new(
+            ErrorCode::QuotaExceeded,
+            format!("Quota exceeded: {}", exceeded),
+        )));
     }
 
-    Ok(())
+    Ok(Ok((batch, skipped_jobs)))
 }
@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+
+use deadpool_redis::Connection;
+use redis::{AsyncCommands, RedisResult};
+use rft_core::queue::Keys;
+use rocket::serde::json::{serde_json::json, Value};
+
+use crate::error::{ApiError, ErrorCode};
+
+/// Metrics kept per job, older values are dropped as new ones are logged
+pub const MAX_METRICS_PER_JOB: isize = 10_000;
+/// Longest progress message or metric name accepted
+const MAX_TEXT_LENGTH: usize = 256;
+
+/// Checks a progress report, `{"fraction": 0.4, "message": "epoch 2/5"}` with an optional
+/// message, returning the JSON stored for the job
+pub fn check_progress(report: &Value, now: u64) -> Result<String, ApiError> {
+    let fraction = report["fraction"]
+        .as_f64()
+        .filter(|fraction| (0.0..=1.0).contains(fraction))
+        .ok_or_else(|| invalid("fraction must be a number from 0 to 1"))?;
+    let message = match &report["message"] {
+        Value::Null => None,
+        Value::String(message) if message.len() <= MAX_TEXT_LENGTH => Some(message),
+        _ => {
+            return Err(invalid(format!(
+                "message must be a string of at most {} characters",
+                MAX_TEXT_LENGTH
+            )))
+        }
+    };
+
+    Ok(json!({
+        "fraction": fraction,
+        "message": message,
+        "updated_at": now,
+    })
+    .to_string())
+}
+
+/// Checks a metric, `{"name": "loss", "value": 0.12, "step": 10}` with an optional step,
+/// returning the JSON stored for the job
+pub fn check_metric(metric: &Value, now: u64) -> Result<String, ApiError> {
+    let name = metric["name"]
+        .as_str()
+        .filter(|name| !name.is_empty() && name.len() <= MAX_TEXT_LENGTH)
+        .ok_or_else(|| {
+            invalid(format!(
+                "name must be a string of 1 to {} characters",
+                MAX_TEXT_LENGTH
+            ))
+        })?;
+    let value = metric["value"]
+        .as_f64()
+        .ok_or_else(|| invalid("value must be a number"))?;
+    let step = match &metric["step"] {
+        Value::Null => None,
+        step => Some(
+            step.as_u64()
+                .ok_or_else(|| invalid("step must be a non-negative integer"))?,
+        ),
+    };
+
+    Ok(json!({
+        "name": name,
+        "value": value,
+        "step": step,
+        "logged_at": now,
+    })
+    .to_string())
+}
+
+fn invalid(message: impl Into<String>) -> ApiError {
+    ApiError::new(ErrorCode::InvalidReport, message)
+}
+
+/// Stores a job's progress, replacing what it reported before
+pub async fn record_progress(
+    conn: &mut Connection,
+    keys: &Keys,
+    batch_id: &str,
+    job_id: &str,
+    progress_json: String,
+) -> RedisResult<()> {
+    conn.hset(keys.progress(batch_id), job_id, progress_json)
+        .await
+}
+
+/// Appends a metric to the job's series, keeping the most recent `MAX_METRICS_PER_JOB`
+pub async fn record_metric(
+    conn: &mut Connection,
+    keys: &Keys,
+    batch_id: &str,
+    job_id: &str,
+    metric_json: String,
+) -> RedisResult<()> {
+    let key = keys.metrics(batch_id, job_id);
+    redis::pipe()
+        .rpush(&key, metric_json)
+        .ignore()
+        .ltrim(&key, -MAX_METRICS_PER_JOB, -1)
+        .ignore()
+        .query_async(conn)
+        .await
+}
+
+/// The progress every job in the batch has reported, by job ID
+pub async fn batch_progress(
+    conn: &mut Connection,
+    keys: &Keys,
+    batch_id: &str,
+) -> RedisResult<HashMap<String, Value>> {
+    let progress: HashMap<String, String> = conn.hgetall(keys.progress(batch_id)).await?;
+
+    Ok(progress
+        .into_iter()
+        .filter_map(|(job_id, progress)| {
+            serde_json::from_str::<Value>(&progress)
+                .ok()
+                .map(|progress| (job_id, progress))
+        })
+        .collect())
+}
+
+/// A job's metrics, oldest first
+pub async fn metrics(
+    conn: &mut Connection,
+    keys: &Keys,
+    batch_id: &str,
+    job_id: &str,
+) -> RedisResult<Vec<Value>> {
+    let metrics: Vec<String> = conn.lrange(keys.metrics(batch_id, job_id), 0, -1).await?;
+
+    Ok(metrics
+        .iter()
+        .filter_map(|metric| serde_json::from_str::<Value>(metric).ok())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::progress::{check_metric, check_progress};
+    use serde_json::json;
+
+    #[test]
+    fn reports_are_checked() {
+        assert_eq!(
+            check_progress(&json!({"fraction": 0.5, "message": "epoch 2"}), 10).unwrap(),
+            r#"{"fraction":0.5,"message":"epoch 2","updated_at":10}"#
+        );
+        assert!(check_progress(&json!({"fraction": 1.5}), 10).is_err());
+        assert!(check_progress(&json!({"fraction": 0.5, "message": 3}), 10).is_err());
+
+        assert_eq!(
+            check_metric(&json!({"name": "loss", "value": 1}), 10).unwrap(),
+            r#"{"name":"loss","value":1.0,"step":null,"logged_at":10}"#
+        );
+        assert!(check_metric(&json!({"name": "loss", "value": "low"}), 10).is_err());
+        assert!(check_metric(&json!({"name": "loss", "value": 1, "step": -1}), 10).is_err());
+    }
+}
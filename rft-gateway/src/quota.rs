@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+
+use redis::aio::ConnectionLike;
+use redis::{ErrorKind, RedisResult};
+use rft_core::batch::Batch;
+use rft_core::queue::{self, HeldQuota, Keys};
+use rocket::serde::Deserialize;
+
+/// Attempts at queueing a batch while other submissions keep changing the usage it was checked
+/// against, before giving up
+const MAX_QUEUE_ATTEMPTS: usize = 10;
+
+/// Limits applied to a user or team. Unset limits are unlimited.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Quota {
+    /// jobs that may be queued or running at once
+    pub max_concurrent_jobs: Option<u64>,
+    /// batches that may be waiting in the queue at once
+    pub max_queued_batches: Option<u64>,
+    /// CPU hours jobs may use per UTC day
+    pub max_cpu_hours_per_day: Option<f64>,
+}
+
+/// Quotas read from Rocket's configuration:
+/// [default.quotas.default]
+/// max_queued_batches = 10
+///
+/// [default.quotas.users.matt]
+/// max_concurrent_jobs = 2000
+///
+/// [default.quotas.teams.research]
+/// max_cpu_hours_per_day = 500
+#[derive(Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Quotas {
+    /// applies to every user without an entry in `users`
+    #[serde(default)]
+    pub default: Quota,
+    #[serde(default)]
+    pub users: HashMap<String, Quota>,
+    #[serde(default)]
+    pub teams: HashMap<String, Quota>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct QuotaConfig {
+    #[serde(default)]
+    pub quotas: Quotas,
+}
+
+impl Quotas {
+    pub fn for_user(&self, user: &str) -> &Quota {
+        self.users.get(user).unwrap_or(&self.default)
+    }
+
+    pub fn for_team(&self, team: &str) -> Option<&Quota> {
+        self.teams.get(team)
+    }
+}
+
+/// What a user or team currently has queued, running or has used today
+#[derive(Debug, Default)]
+pub struct Usage {
+    pub queued_batches: u64,
+    pub active_jobs: u64,
+    pub cpu_seconds_today: f64,
+}
+
+/// Checks whether a new batch with `new_jobs` jobs fits within a quota, returning
+/// a description of the exceeded limit if it doesn't
+pub fn check(quota: &Quota, usage: &Usage, new_jobs: u64, subject: &str) -> Result<(), String> {
+    if let Some(max) = quota.max_queued_batches {
+        if usage.queued_batches >= max {
+            return Err(format!(
+                "{} already has {} batches queued, the limit is {}",
+                subject, usage.queued_batches, max
+            ));
+        }
+    }
+
+    if let Some(max) = quota.max_concurrent_jobs {
+        if usage.active_jobs + new_jobs > max {
+            return Err(format!(
+                "{} has {} jobs queued or running and this batch adds {}, the limit is {}",
+                subject, usage.active_jobs, new_jobs, max
+            ));
+        }
+    }
+
+    if let Some(max) = quota.max_cpu_hours_per_day {
+        let used = usage.cpu_seconds_today / 3600.0;
+        if used >= max {
+            return Err(format!(
+                "{} has used {:.1} of {} CPU hours today",
+                subject, used, max
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Queues a stored batch, unless it would take its owner or their team over quota, returning
+/// a description of the exceeded limit if it would. The usage is watched while it is checked
+/// and the batch is only queued if it hasn't changed, so concurrent submissions can't both take
+/// the last of a quota.
+pub async fn queue_within_quota<C>(
+    conn: &mut C,
+    keys: &Keys,
+    quotas: &Quotas,
+    batch: &Batch,
+) -> RedisResult<Result<(), String>>
+where
+    C: ConnectionLike + Send,
+{
+    let owner = batch.owner().to_string();
+    let new_jobs = batch.jobs.len() as u64;
+    let mut subjects = vec![(owner.clone(), quotas.for_user(&owner))];
+    if let Some(team) = &batch.team {
+        if let Some(team_quota) = quotas.for_team(team) {
+            subjects.push((queue::team_field(team), team_quota));
+        }
+    }
+    let held = HeldQuota::for_batch(batch);
+    let cpu_seconds = keys.cpu_seconds(queue::epoch_day(batch.submitted_at.unwrap_or_default()));
+
+    for _ in 0..MAX_QUEUE_ATTEMPTS {
+        redis::cmd("WATCH")
+            .arg(keys.queued_batches_count())
+            .arg(keys.active_jobs_count())
+            .arg(&cpu_seconds)
+            .query_async::<_, ()>(conn)
+            .await?;
+
+        for (subject, quota) in &subjects {
+            let usage = get_usage(conn, keys, subject, &cpu_seconds).await?;
+            if let Err(exceeded) = check(quota, &usage, new_jobs, subject) {
+                redis::cmd("UNWATCH").query_async::<_, ()>(conn).await?;
+                return Ok(Err(exceeded));
+            }
+        }
+
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .rpush(keys.user_queue(&owner), &batch.batch_id)
+            .ignore()
+            .sadd(keys.queued_users(), &owner)
+            .ignore()
+            .hset(
+                keys.held_quota(),
+                &batch.batch_id,
+                serde_json::to_string(&held).unwrap_or_default(),
+            )
+            .ignore();
+        for account in &held.accounts {
+            pipe.hincr(keys.queued_batches_count(), account, 1)
+                .ignore()
+                .hincr(keys.active_jobs_count(), account, new_jobs)
+                .ignore();
+        }
+        // Nothing is returned when the usage changed since it was watched
+        let queued: Option<()> = pipe.query_async(conn).await?;
+        if queued.is_some() {
+            return Ok(Ok(()));
+        }
+    }
+
+    Err((
+        ErrorKind::TryAgain,
+        "Quota usage kept changing while queueing the batch",
+    )
+        .into())
+}
+
+/// Reads a user's (or `team:<name>`) current queue and resource usage
+async fn get_usage<C>(
+    conn: &mut C,
+    keys: &Keys,
+    subject: &str,
+    cpu_seconds: &str,
+) -> RedisResult<Usage>
+where
+    C: ConnectionLike + Send,
+{
+    let (queued_batches, active_jobs, cpu_seconds_today): (Option<u64>, Option<u64>, Option<f64>) =
+        redis::pipe()
+            .hget(keys.queued_batches_count(), subject)
+            .hget(keys.active_jobs_count(), subject)
+            .hget(cpu_seconds, subject)
+            .query_async(conn)
+            .await?;
+
+    Ok(Usage {
+        queued_batches: queued_batches.unwrap_or_default(),
+        active_jobs: active_jobs.unwrap_or_default(),
+        cpu_seconds_today: cpu_seconds_today.unwrap_or_default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::quota::{check, queue_within_quota, Quota, Quotas, Usage};
+    use redis::AsyncCommands;
+    use rft_core::batch::Batch;
+    use rft_core::fake_redis::FakeRedis;
+    use rft_core::job::Job;
+    use rft_core::queue::Keys;
+    use std::collections::HashMap;
+
+    #[test]
+    fn check_limits() {
+        let quota = Quota {
+            max_concurrent_jobs: Some(100),
+            max_queued_batches: Some(2),
+            max_cpu_hours_per_day: Some(10.0),
+        };
+        let mut usage = Usage {
+            queued_batches: 1,
+            active_jobs: 50,
+            cpu_seconds_today: 3600.0,
+        };
+
+        check(&quota, &usage, 50, "matt").expect("Batch fits within the quota");
+        check(&quota, &usage, 51, "matt").expect_err("Too many concurrent jobs");
+
+        usage.queued_batches = 2;
+        check(&quota, &usage, 1, "matt").expect_err("Too many queued batches");
+
+        usage.queued_batches = 0;
+        usage.cpu_seconds_today = 36_000.0;
+        check(&quota, &usage, 1, "matt").expect_err("Out of CPU hours");
+
+        check(&Quota::default(), &usage, 10_000, "matt").expect("No limits configured");
+    }
+
+    #[rocket::async_test]
+    async fn racing_batches_share_a_quota() {
+        let redis = FakeRedis::new();
+        let keys = Keys::new("rft");
+        let quotas = Quotas {
+            default: Quota {
+                max_queued_batches: Some(1),
+                ..Quota::default()
+            },
+            ..Quotas::default()
+        };
+        let batch = |batch_id: &str| {
+            let mut batch = Batch::new("matt", "main.py", "git@github.com:x/y.git", "main");
+            batch.batch_id = batch_id.to_string();
+            batch.jobs.push(Job::new(HashMap::new()));
+            batch
+        };
+        let (first, second) = (batch("b1"), batch("b2"));
+        let (mut first_conn, mut second_conn) = (redis.connection(), redis.connection());
+
+        let (first, second) = rocket::futures::join!(
+            queue_within_quota(&mut first_conn, &keys, &quotas, &first),
+            queue_within_quota(&mut second_conn, &keys, &quotas, &second),
+        );
+        let queued: Vec<bool> = vec![first.unwrap().is_ok(), second.unwrap().is_ok()];
+        assert_eq!(queued.iter().filter(|queued| **queued).count(), 1);
+
+        let mut conn = redis.connection();
+        let queue: Vec<String> = conn.lrange(keys.user_queue("matt"), 0, -1).await.unwrap();
+        assert_eq!(queue.len(), 1);
+        let queued_batches: u64 = conn
+            .hget(keys.queued_batches_count(), "matt")
+            .await
+            .unwrap();
+        assert_eq!(queued_batches, 1);
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+
+use deadpool_redis::Connection;
+use redis::{AsyncCommands, RedisResult};
+use rft_core::batch::Batch;
+use rft_core::queue::Keys;
+use rocket::serde::json::{serde_json::json, Value};
+
+use crate::error::{ApiError, ErrorCode};
+
+/// Checks a posted result is a JSON object within the size limit, returning it serialized
+pub fn check_result(result: &Value, max_bytes: usize) -> Result<String, ApiError> {
+    if !result.is_object() {
+        return Err(ApiError::new(
+            ErrorCode::InvalidResult,
+            "Result must be a JSON object, i.e. {\"loss\": 0.12}",
+        ));
+    }
+    let result_json = result.to_string();
+    if result_json.len() > max_bytes {
+        return Err(ApiError::new(
+            ErrorCode::InvalidResult,
+            format!(
+                "Result is {} bytes, the limit is {}. Store large outputs as artifacts instead",
+                result_json.len(),
+                max_bytes
+            ),
+        ));
+    }
+
+    Ok(result_json)
+}
+
+/// Stores a job's result, replacing any it posted before
+pub async fn record(
+    conn: &mut Connection,
+    keys: &Keys,
+    batch_id: &str,
+    job_id: &str,
+    result_json: String,
+) -> RedisResult<()> {
+    conn.hset(keys.results(batch_id), job_id, result_json).await
+}
+
+/// Every job in the batch, in submission order, with its params and result or null if it
+/// hasn't posted one
+pub async fn list(conn: &mut Connection, keys: &Keys, batch: &Batch) -> RedisResult<Value> {
+    let results: HashMap<String, String> = conn.hgetall(keys.results(&batch.batch_id)).await?;
+
+    let jobs: Vec<Value> = batch
+        .jobs
+        .iter()
+        .map(|job| {
+            let result = results
+                .get(&job.job_id)
+                .and_then(|result| serde_json::from_str::<Value>(result).ok())
+                .unwrap_or(Value::Null);
+            json!({
+                "job_id": job.job_id,
+                "params": job.params,
+                "result": result,
+            })
+        })
+        .collect();
+
+    Ok(json!({
+        "batch_id": batch.batch_id,
+        "completed": results.len(),
+        "jobs": jobs,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::results::check_result;
+    use serde_json::json;
+
+    #[test]
+    fn results_must_be_small_objects() {
+        assert_eq!(
+            check_result(&json!({"loss": 0.12}), 1024).unwrap(),
+            r#"{"loss":0.12}"#
+        );
+        assert!(check_result(&json!([0.12]), 1024).is_err());
+        assert!(check_result(&json!({"loss": 0.12}), 8).is_err());
+    }
+}
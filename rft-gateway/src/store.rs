@@ -0,0 +1,93 @@
+use std::time::Duration;
+
+use deadpool_redis::{Connection, Manager, Pool, Runtime};
+use redis::AsyncCommands;
+use rft_core::batch::Batch;
+use rft_core::queue::Keys;
+use rft_core::redis_config::RedisConfig;
+
+use crate::auth::Principal;
+use crate::error::{ApiError, ErrorCode};
+
+/// How long a request waits for a pooled connection, or for a new one to be opened
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Pooled connections to the Redis instance holding the queue, managed as Rocket state
+pub struct Store {
+    pool: Pool,
+    pub keys: Keys,
+}
+
+impl Store {
+    pub fn new(config: &RedisConfig) -> Result<Store, String> {
+        let manager = Manager::new(config.connection_info()).map_err(|e| e.to_string())?;
+        let pool = Pool::builder(manager)
+            .max_size(config.pool_size)
+            .runtime(Runtime::Tokio1)
+            .wait_timeout(Some(CONNECTION_TIMEOUT))
+            .create_timeout(Some(CONNECTION_TIMEOUT))
+            .build()
+            .map_err(|e| e.to_string())?;
+
+        Ok(Store {
+            pool,
+            keys: config.keys(),
+        })
+    }
+
+    /// Takes a connection from the pool, opening a new one if none are idle
+    pub async fn connection(&self) -> Result<Connection, ApiError> {
+        self.pool.get().await.map_err(|err| {
+            eprintln!("Unable to get a Redis connection: {}", err);
+            unavailable()
+        })
+    }
+
+    /// Loads a batch the principal can access. Batches belonging to other users are reported
+    /// as missing so their IDs can't be probed.
+    pub async fn batch(
+        &self,
+        conn: &mut Connection,
+        batch_id: &str,
+        principal: &Principal,
+    ) -> Result<Batch, ApiError> {
+        self.any_batch(conn, batch_id)
+            .await?
+            .filter(|batch| principal.can_access(batch))
+            .ok_or_else(|| ApiError::new(ErrorCode::NotFound, format!("No batch {}", batch_id)))
+    }
+
+    /// Loads a batch without checking who it belongs to
+    pub async fn any_batch(
+        &self,
+        conn: &mut Connection,
+        batch_id: &str,
+    ) -> Result<Option<Batch>, ApiError> {
+        let json_batch: Option<String> = conn
+            .get(self.keys.batch(batch_id))
+            .await
+            .map_err(queue_unavailable)?;
+
+        Ok(json_batch.and_then(|json_batch| Batch::from_json(&json_batch).ok()))
+    }
+}
+
+/// Logs a failed Redis command and returns the error reported to clients
+pub fn queue_unavailable(err: redis::RedisError) -> ApiError {
+    if err.is_connection_refusal() {
+        println!("Error connecting to Redis");
+    } else if err.is_io_error() {
+        println!("IO Error");
+    } else {
+        eprintln!("{}", err);
+    }
+
+    unavailable()
+}
+
+fn unavailable() -> ApiError {
+    ApiError::new(
+        ErrorCode::QueueUnavailable,
+        "The batch queue is unavailable, try again later",
+    )
+}
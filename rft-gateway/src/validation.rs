@@ -0,0 +1,283 @@
+use std::collections::HashSet;
+
+use rft_core::batch::Batch;
+use rocket::serde::Deserialize;
+
+/// Size limits applied to submitted batches, read from Rocket's configuration:
+/// [default.batch_limits]
+/// max_jobs = 10000
+/// max_params_per_job = 100
+/// max_result_bytes = 65536
+///
+/// The size of the request body itself is limited by Rocket's `limits.json`.
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct BatchLimits {
+    #[serde(default = "default_max_jobs")]
+    pub max_jobs: usize,
+    #[serde(default = "default_max_params_per_job")]
+    pub max_params_per_job: usize,
+    /// size of the JSON result a single job may post
+    #[serde(default = "default_max_result_bytes")]
+    pub max_result_bytes: usize,
+}
+
+impl Default for BatchLimits {
+    fn default() -> Self {
+        BatchLimits {
+            max_jobs: default_max_jobs(),
+            max_params_per_job: default_max_params_per_job(),
+            max_result_bytes: default_max_result_bytes(),
+        }
+    }
+}
+
+fn default_max_jobs() -> usize {
+    10_000
+}
+
+fn default_max_params_per_job() -> usize {
+    100
+}
+
+fn default_max_result_bytes() -> usize {
+    64 * 1024
+}
+
+/// Longest batch ID, job ID or param name accepted
+const MAX_NAME_LENGTH: usize = 64;
+/// Problems reported back to the client, the rest are summarised
+const MAX_REPORTED_PROBLEMS: usize = 20;
+/// Most batches a single batch can depend on
+const MAX_DEPENDENCIES: usize = 100;
+
+/// Checks a batch before it is queued, returning every problem found
+pub fn validate_batch(batch: &Batch, limits: &BatchLimits) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if let Err(problem) = check_id(&batch.batch_id) {
+        problems.push(format!("batch_id: {}", problem));
+    }
+    if batch.author.trim().is_empty() {
+        problems.push("author: must not be empty".to_string());
+    }
+    if let Err(problem) = check_source_file(&batch.source_file) {
+        problems.push(format!("source_file: {}", problem));
+    }
+    if let Err(problem) = check_repository_url(&batch.repository_url) {
+        problems.push(format!("repository_url: {}", problem));
+    }
+    if batch.branch.trim().is_empty()
+        || batch.branch.contains(char::is_whitespace)
+        || batch.branch.starts_with('-')
+    {
+        problems.push(
+            "branch: must be a non-empty name without whitespace, not starting with '-'"
+                .to_string(),
+        );
+    }
+
+    if batch.depends_on.len() > MAX_DEPENDENCIES {
+        problems.push(format!(
+            "depends_on: batch depends on {} batches, the limit is {}",
+            batch.depends_on.len(),
+            MAX_DEPENDENCIES
+        ));
+    }
+    let mut predecessors = HashSet::new();
+    for (index, predecessor) in batch.depends_on.iter().enumerate() {
+        if let Err(problem) = check_id(predecessor) {
+            problems.push(format!("depends_on[{}]: {}", index, problem));
+        } else if !predecessors.insert(predecessor) {
+            problems.push(format!(
+                "depends_on[{}]: duplicate batch ID '{}'",
+                index, predecessor
+            ));
+        }
+    }
+
+    if batch.jobs.is_empty() {
+        problems.push("jobs: batch has no jobs".to_string());
+    } else if batch.jobs.len() > limits.max_jobs {
+        problems.push(format!(
+            "jobs: batch has {} jobs, the limit is {}",
+            batch.jobs.len(),
+            limits.max_jobs
+        ));
+    }
+
+    let mut job_ids = HashSet::new();
+    for (index, job) in batch.jobs.iter().enumerate() {
+        if let Err(problem) = check_id(&job.job_id) {
+            problems.push(format!("jobs[{}].job_id: {}", index, problem));
+        } else if !job_ids.insert(&job.job_id) {
+            problems.push(format!(
+                "jobs[{}].job_id: duplicate job ID '{}'",
+                index, job.job_id
+            ));
+        }
+
+        if job.params.len() > limits.max_params_per_job {
+            problems.push(format!(
+                "jobs[{}].params: job has {} params, the limit is {}",
+                index,
+                job.params.len(),
+                limits.max_params_per_job
+            ));
+        }
+        let mut names: Vec<&String> = job.params.keys().collect();
+        names.sort();
+        for name in names {
+            if let Err(problem) = check_param_name(name) {
+                problems.push(format!("jobs[{}].params.{}: {}", index, name, problem));
+            }
+        }
+    }
+
+    if problems.len() > MAX_REPORTED_PROBLEMS {
+        let remaining = problems.len() - MAX_REPORTED_PROBLEMS;
+        problems.truncate(MAX_REPORTED_PROBLEMS);
+        problems.push(format!("... and {} more", remaining));
+    }
+
+    problems
+}
+
+fn check_id(id: &str) -> Result<(), String> {
+    if id.is_empty() || id.len() > MAX_NAME_LENGTH {
+        return Err(format!("must be 1 to {} characters", MAX_NAME_LENGTH));
+    }
+    if !id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "'{}' may only contain letters, digits, '-' and '_'",
+            id
+        ));
+    }
+
+    Ok(())
+}
+
+/// Param names become keys in the worker's data file and environment, so keep them to
+/// identifiers, i.e. `start_date`
+fn check_param_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.len() > MAX_NAME_LENGTH {
+        return Err(format!("must be 1 to {} characters", MAX_NAME_LENGTH));
+    }
+    let mut chars = name.chars();
+    let starts_ok = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_');
+    if !starts_ok || !chars.all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(
+            "must start with a letter or '_' and contain only letters, digits and '_'".to_string(),
+        );
+    }
+
+    Ok(())
+}
+
+fn check_source_file(source_file: &str) -> Result<(), String> {
+    if source_file.trim().is_empty() {
+        return Err("must not be empty".to_string());
+    }
+    if source_file.starts_with('/') || source_file.split('/').any(|part| part == "..") {
+        return Err(format!(
+            "'{}' must be a path relative to the repository root",
+            source_file
+        ));
+    }
+
+    Ok(())
+}
+
+/// Accepts URLs git can clone from: https://, http://, ssh:// and git:// URLs with a host, or
+/// scp-like `user@host:path` addresses
+fn check_repository_url(url: &str) -> Result<(), String> {
+    let invalid = || {
+        format!(
+            "'{}' is not a git URL, expected i.e. https://github.com/org/repo.git or git@github.com:org/repo.git",
+            url
+        )
+    };
+    // A leading '-' would be read by git as an option, i.e. --upload-pack=<command>
+    if url.is_empty() || url.contains(char::is_whitespace) || url.starts_with('-') {
+        return Err(invalid());
+    }
+
+    match url.split_once("://") {
+        Some((scheme, rest)) => {
+            let host = rest.split('/').next().unwrap_or_default();
+            let has_path = rest.len() > host.len() + 1;
+            if ["https", "http", "ssh", "git"].contains(&scheme) && !host.is_empty() && has_path {
+                Ok(())
+            } else {
+                Err(invalid())
+            }
+        }
+        None => match url.split_once(':') {
+            Some((user_host, path))
+                if user_host.contains('@') && !user_host.ends_with('@') && !path.is_empty() =>
+            {
+                Ok(())
+            }
+            _ => Err(invalid()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::validation::{validate_batch, BatchLimits};
+    use rft_core::batch::Batch;
+    use rft_core::job::Job;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    #[test]
+    fn reports_every_problem() {
+        let mut batch = Batch::new(
+            "matt",
+            "examples/basic/main.py",
+            "git@github.com:retwolf/rft.git",
+            "master",
+        );
+        let mut params = HashMap::new();
+        params.insert("start_date".to_string(), json!(2000));
+        batch.jobs.push(Job::new(params.clone()));
+        assert!(validate_batch(&batch, &BatchLimits::default()).is_empty());
+
+        batch.repository_url = "github.com/retwolf/rft".to_string();
+        batch.source_file = "../main.py".to_string();
+        params.insert("end date".to_string(), json!(2020));
+        let mut duplicate = Job::new(params);
+        duplicate.job_id = batch.jobs[0].job_id.clone();
+        batch.jobs.push(duplicate);
+        batch.depends_on = vec!["aZ3kLp0qWe".to_string(), "aZ3kLp0qWe".to_string()];
+
+        let problems = validate_batch(&batch, &BatchLimits::default());
+        assert_eq!(problems.len(), 5, "{:?}", problems);
+        assert!(problems[0].starts_with("source_file:"));
+        assert!(problems[1].starts_with("repository_url:"));
+        assert!(problems[2].starts_with("depends_on[1]: duplicate batch ID"));
+        assert!(problems[3].contains("duplicate job ID"));
+        assert!(problems[4].starts_with("jobs[1].params.end date:"));
+
+        batch.jobs.clear();
+        assert!(validate_batch(&batch, &BatchLimits::default())
+            .contains(&"jobs: batch has no jobs".to_string()));
+    }
+
+    #[test]
+    fn rejects_git_options() {
+        let mut batch = Batch::new("matt", "main.py", "--upload-pack=evil@h:p", "-b");
+        batch.jobs.push(Job::new(HashMap::new()));
+
+        let problems = validate_batch(&batch, &BatchLimits::default());
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems[0].starts_with("repository_url:"));
+        assert!(problems[1].starts_with("branch:"));
+    }
+}
@@ -11,3 +11,9 @@ import rft_worker_sdk as rft
 
 print(rft.job_id("../example-data.json"))
 print(rft.get_param("start_date", 2000, "../example-data.json"))
+print(rft.get_int("end_date", datapath="../example-data.json"))
+print(rft.get_params("../example-data.json"))
+
+rft.report_progress(0.5, "halfway", "../example-data.json")
+rft.log_metric("loss", 0.12, step=1, datapath="../example-data.json")
+rft.set_result({"loss": 0.12}, "../example-data.json")
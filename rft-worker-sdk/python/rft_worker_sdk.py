@@ -0,0 +1,240 @@
+"""A Python SDK for using rft to process work in parallel"""
+import datetime
+import json
+import os
+import sys
+import urllib.error
+import urllib.request
+
+
+__version__ = "0.2.0"
+
+DATA_PATH = "/input/data.json"
+"""Where the init container writes the job a pod runs, overridden by RFT_DATA_PATH"""
+LOCAL_PARAMS_PATH = "params.json"
+"""Params file read in local mode when RFT_PARAMS_FILE isn't set"""
+LOCAL_JOB_ID = "local"
+"""Job ID used in local mode when the params file doesn't set one"""
+
+_REQUIRED = object()
+_jobs = {}
+
+
+class MissingParamError(KeyError):
+    """A required param isn't set for the job"""
+
+    def __str__(self):
+        return self.args[0]
+
+
+class InvalidParamError(ValueError):
+    """A param can't be converted to the requested type"""
+
+
+def _load_job(datapath=None):
+    """Reads the job from the datafile, once per path. Without a datafile, i.e. during
+    development, the job's params are read from RFT_PARAMS_FILE or ./params.json instead,
+    either a job with `job_id` and `params` or a plain object of params. Returns None if
+    neither exists.
+    """
+    path = datapath or os.environ.get("RFT_DATA_PATH") or DATA_PATH
+    if path not in _jobs:
+        local_path = os.environ.get("RFT_PARAMS_FILE") or LOCAL_PARAMS_PATH
+        if os.path.exists(path):
+            with open(path) as read_file:
+                job = json.load(read_file)
+        elif os.path.exists(local_path):
+            with open(local_path) as read_file:
+                job = json.load(read_file)
+            if not isinstance(job.get("params"), dict):
+                job = {"params": job}
+        else:
+            return None
+        job.setdefault("job_id", LOCAL_JOB_ID)
+        _jobs[path] = job
+
+    return _jobs[path]
+
+
+def job_id(datapath=None):
+    """Returns the ID of the job, `local` in local mode without one."""
+    job = _load_job(datapath)
+    if job is None:
+        raise FileNotFoundError(
+            "No rft datafile at {}".format(
+                datapath or os.environ.get("RFT_DATA_PATH") or DATA_PATH
+            )
+        )
+
+    return job["job_id"]
+
+
+def get_params(datapath=None):
+    """Returns every param of the job as a dict, empty if there is no datafile."""
+    job = _load_job(datapath)
+    if job is None:
+        return {}
+
+    return dict(job["params"])
+
+
+def get_param(name, fallback=_REQUIRED, datapath=None):
+    """Returns the value of a job parameter.
+
+    Parameters are stored as typed JSON, so numbers, booleans, lists and objects
+    are returned as int, float, bool, list and dict rather than strings.
+
+    Without a fallback the param is required, and MissingParamError is raised if
+    it isn't set. With one, the fallback is returned instead.
+    """
+    params = get_params(datapath)
+    if name in params:
+        return params[name]
+    if fallback is _REQUIRED:
+        raise MissingParamError("The job has no param '{}'".format(name))
+
+    return fallback
+
+
+def get_int(name, fallback=_REQUIRED, datapath=None):
+    """Returns a param as an int, parsing strings such as "42"."""
+    return _convert(name, fallback, datapath, "an integer", _to_int)
+
+
+def get_float(name, fallback=_REQUIRED, datapath=None):
+    """Returns a param as a float, parsing strings such as "0.5"."""
+    return _convert(name, fallback, datapath, "a number", _to_float)
+
+
+def get_date(name, fallback=_REQUIRED, datapath=None, format=None):
+    """Returns a param as a datetime.date, parsed from an ISO date such as
+    "2020-01-31", or with `format` as in datetime.strptime.
+    """
+
+    def to_date(value):
+        if not isinstance(value, str):
+            raise ValueError
+        if format is None:
+            return datetime.date.fromisoformat(value)
+        return datetime.datetime.strptime(value, format).date()
+
+    return _convert(name, fallback, datapath, "a date", to_date)
+
+
+def _convert(name, fallback, datapath, expected, convert):
+    params = get_params(datapath)
+    if name not in params:
+        return get_param(name, fallback, datapath)
+
+    value = params[name]
+    try:
+        return convert(value)
+    except (TypeError, ValueError):
+        raise InvalidParamError(
+            "Param '{}' should be {} but found {}".format(
+                name, expected, json.dumps(value)
+            )
+        ) from None
+
+
+def _to_int(value):
+    if isinstance(value, bool) or (isinstance(value, float) and not value.is_integer()):
+        raise ValueError
+    return int(value)
+
+
+def _to_float(value):
+    if isinstance(value, bool):
+        raise ValueError
+    return float(value)
+
+
+def batch_id():
+    """Returns the ID of the batch the job belongs to, None outside of rft."""
+    return os.environ.get("RFT_BATCH_ID") or None
+
+
+def author():
+    """Returns the author of the batch, None outside of rft."""
+    return os.environ.get("RFT_AUTHOR") or None
+
+
+def commit():
+    """Returns the commit the batch was submitted from, None if it isn't known."""
+    return os.environ.get("RFT_COMMIT") or None
+
+
+def report_progress(fraction, message=None, datapath=None):
+    """Reports how far through the job is, from 0.0 to 1.0, with an optional message.
+
+    Shown per job by the gateway, replacing the previously reported progress.
+    """
+    fraction = float(fraction)
+    if not 0.0 <= fraction <= 1.0:
+        raise ValueError("fraction must be between 0 and 1")
+    _report("progress", {"fraction": fraction, "message": message}, datapath)
+
+
+def log_metric(name, value, step=None, datapath=None):
+    """Appends a value to one of the job's metric series, i.e. the loss at each epoch."""
+    _report(
+        "metrics",
+        {"name": str(name), "value": float(value), "step": step},
+        datapath,
+    )
+
+
+def set_result(result, datapath=None):
+    """Records the job's result, a dict of JSON values such as final metrics.
+
+    Results of every job in a batch can be exported with `rft-client results`.
+    """
+    if not isinstance(result, dict):
+        raise TypeError("result must be a dict")
+    _report("result", result, datapath)
+
+
+def _report(kind, body, datapath):
+    """Posts a report to RFT_GATEWAY_URL, which may be the gateway or a local sidecar
+    forwarding to it. Jobs authenticate with their job token, read from RFT_JOB_TOKEN or
+    /input/job_token. Jobs run by `rft-client run --local` append reports to RFT_REPORT_FILE
+    instead, and outside of rft they are printed to stderr.
+    """
+    gateway_url = os.environ.get("RFT_GATEWAY_URL")
+    batch = batch_id()
+    report_file = os.environ.get("RFT_REPORT_FILE")
+    if (not gateway_url or not batch) and report_file:
+        with open(report_file, "a") as write_file:
+            write_file.write(json.dumps({"kind": kind, "report": body}) + "\n")
+        return
+    if not gateway_url or not batch:
+        print("rft {}: {}".format(kind, json.dumps(body)), file=sys.stderr)
+        return
+
+    url = "{}/batch/{}/jobs/{}/{}".format(
+        gateway_url.rstrip("/"), batch, job_id(datapath), kind
+    )
+    request = urllib.request.Request(
+        url,
+        data=json.dumps(body).encode("utf-8"),
+        headers={
+            "Content-Type": "application/json",
+            "Authorization": "Bearer {}".format(_job_token()),
+        },
+        method="POST",
+    )
+    try:
+        with urllib.request.urlopen(request, timeout=10):
+            pass
+    except urllib.error.HTTPError as err:
+        raise RuntimeError(
+            "rft gateway rejected {}: {}".format(kind, err.read().decode("utf-8"))
+        ) from err
+
+
+def _job_token(tokenpath="/input/job_token"):
+    token = os.environ.get("RFT_JOB_TOKEN")
+    if token:
+        return token
+    with open(tokenpath) as read_file:
+        return read_file.read().strip()
@@ -0,0 +1,23 @@
+use std::process::exit;
+
+fn main() {
+    let worker = match rft_worker::Worker::from_env() {
+        Ok(worker) => worker,
+        Err(err) => {
+            println!("Error! - {}", err);
+            exit(1)
+        }
+    };
+
+    println!("{}", worker.job_id());
+    println!("{}", worker.param_or("start_date", 2000).unwrap_or(2000));
+
+    let report = worker
+        .report_progress(0.5, Some("halfway"))
+        .and_then(|_| worker.log_metric("loss", 0.12, Some(1)))
+        .and_then(|_| worker.set_result(&serde_json::json!({ "loss": 0.12 })));
+    if let Err(err) = report {
+        println!("Error! - {}", err);
+        exit(1)
+    }
+}
@@ -0,0 +1,379 @@
+//! A Rust SDK for rft workers: read the job's params, write artifacts and report progress,
+//! metrics and results back to the gateway.
+//!
+//! ```no_run
+//! let worker = rft_worker::Worker::from_env()?;
+//! let start_date: u32 = worker.param("start_date")?;
+//! let regions: Vec<String> = worker.param_or("regions", Vec::new())?;
+//!
+//! worker.report_progress(0.5, Some("halfway"))?;
+//! worker.log_metric("loss", 0.12, Some(1))?;
+//! worker.set_result(&serde_json::json!({ "loss": 0.12 }))?;
+//! # Ok::<(), rft_worker::Error>(())
+//! ```
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use isahc::{prelude::*, Request};
+use rft_core::artifacts::OUTPUT_DIR;
+use rft_core::job::Job;
+use rft_core::local::append_report;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+use snafu::{ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Unable to read {}: {}", path.display(), source))]
+    ReadData {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("{} is not a valid rft datafile: {}", path.display(), source))]
+    ParseData {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    #[snafu(display(
+        "No rft datafile at {} and no params file at {} for local development",
+        data_path.display(),
+        params_path.display()
+    ))]
+    NoData {
+        data_path: PathBuf,
+        params_path: PathBuf,
+    },
+    #[snafu(display("The job has no param '{}'", name))]
+    MissingParam { name: String },
+    #[snafu(display("Param '{}' is invalid: {}", name, message))]
+    InvalidParam { name: String, message: String },
+    #[snafu(display("Unable to read the job token: {}", source))]
+    ReadToken { source: std::io::Error },
+    #[snafu(display("Unable to report {} to the gateway: {}", kind, message))]
+    Report { kind: String, message: String },
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Where the init container writes the job a pod runs
+pub static DATA_PATH: &str = "/input/data.json";
+/// Where the init container writes the job's token when reporting is enabled
+pub static TOKEN_PATH: &str = "/input/job_token";
+/// Params file read in local mode when RFT_PARAMS_FILE isn't set
+pub static LOCAL_PARAMS_PATH: &str = "params.json";
+/// Job ID used in local mode when the params file doesn't set one
+pub static LOCAL_JOB_ID: &str = "local";
+
+/// The job this worker is running and where it reports to
+pub struct Worker {
+    job: Job,
+    batch: BatchInfo,
+    output_dir: PathBuf,
+    reporter: Reporter,
+}
+
+/// Metadata of the batch the job belongs to, from the RFT_BATCH_ID, RFT_AUTHOR and RFT_COMMIT
+/// environment variables. Unset outside of rft.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BatchInfo {
+    pub batch_id: Option<String>,
+    pub author: Option<String>,
+    /// the commit the batch was submitted from, if known
+    pub commit: Option<String>,
+}
+
+/// Where progress, metrics and results are sent
+enum Reporter {
+    Gateway {
+        url: String,
+        batch_id: String,
+        token: String,
+    },
+    /// appended to a file, when run by `rft-client run --local`
+    File(PathBuf),
+    /// printed to stderr, outside of rft or when reporting isn't enabled
+    Stderr,
+}
+
+impl Worker {
+    /// Loads the job from /input/data.json, or the file at RFT_DATA_PATH. When there is no
+    /// datafile, i.e. during development, the worker runs in local mode and reads its params
+    /// from RFT_PARAMS_FILE or ./params.json instead. Reports go to the gateway at
+    /// RFT_GATEWAY_URL when it is set, are appended to RFT_REPORT_FILE when run by
+    /// `rft-client run --local`, and are printed to stderr otherwise.
+    pub fn from_env() -> Result<Worker> {
+        Worker::from_lookup(|name| std::env::var(name).ok())
+    }
+
+    /// Like `from_env`, reading variables through `lookup`
+    pub fn from_lookup<F>(lookup: F) -> Result<Worker>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let lookup = |name: &str| lookup(name).filter(|value| !value.is_empty());
+        let data_path = PathBuf::from(lookup("RFT_DATA_PATH").unwrap_or_else(|| DATA_PATH.into()));
+        let params_path =
+            PathBuf::from(lookup("RFT_PARAMS_FILE").unwrap_or_else(|| LOCAL_PARAMS_PATH.into()));
+
+        let (job, default_output_dir) = if data_path.exists() {
+            (read_job(&data_path)?, OUTPUT_DIR)
+        } else if params_path.exists() {
+            (read_job(&params_path)?, "output")
+        } else {
+            return Err(Error::NoData {
+                data_path,
+                params_path,
+            });
+        };
+
+        let batch = BatchInfo {
+            batch_id: lookup("RFT_BATCH_ID"),
+            author: lookup("RFT_AUTHOR"),
+            commit: lookup("RFT_COMMIT"),
+        };
+        let reporter = match (lookup("RFT_GATEWAY_URL"), batch.batch_id.clone()) {
+            (Some(url), Some(batch_id)) => {
+                let token = match lookup("RFT_JOB_TOKEN") {
+                    Some(token) => token,
+                    None => fs::read_to_string(TOKEN_PATH).context(ReadToken)?,
+                };
+                Reporter::Gateway {
+                    url: url.trim_end_matches('/').to_string(),
+                    batch_id,
+                    token: token.trim().to_string(),
+                }
+            }
+            _ => match lookup("RFT_REPORT_FILE") {
+                Some(path) => Reporter::File(PathBuf::from(path)),
+                None => Reporter::Stderr,
+            },
+        };
+
+        Ok(Worker {
+            job,
+            batch,
+            output_dir: PathBuf::from(
+                lookup("RFT_OUTPUT_DIR").unwrap_or_else(|| default_output_dir.into()),
+            ),
+            reporter,
+        })
+    }
+
+    pub fn job(&self) -> &Job {
+        &self.job
+    }
+
+    pub fn job_id(&self) -> &str {
+        &self.job.job_id
+    }
+
+    pub fn batch(&self) -> &BatchInfo {
+        &self.batch
+    }
+
+    /// ID of the batch the job belongs to, None outside of rft
+    pub fn batch_id(&self) -> Option<&str> {
+        self.batch.batch_id.as_deref()
+    }
+
+    /// Converts a param to any type it deserializes into, i.e. numbers, strings, `Vec`s or
+    /// your own structs
+    pub fn param<T: DeserializeOwned>(&self, name: &str) -> Result<T> {
+        let value = self
+            .job
+            .params
+            .get(name)
+            .ok_or_else(|| Error::MissingParam {
+                name: name.to_string(),
+            })?;
+
+        serde_json::from_value(value.clone()).map_err(|err| Error::InvalidParam {
+            name: name.to_string(),
+            message: err.to_string(),
+        })
+    }
+
+    /// Like `param`, returning `default` when the job doesn't have the param
+    pub fn param_or<T: DeserializeOwned>(&self, name: &str, default: T) -> Result<T> {
+        match self.job.params.contains_key(name) {
+            true => self.param(name),
+            false => Ok(default),
+        }
+    }
+
+    /// Parses a param with `FromStr`. Strings are parsed as is, other values from their JSON,
+    /// so `"2020-01-01"` and `2020` can both be parsed into types with a `FromStr` impl.
+    pub fn parse_param<T>(&self, name: &str) -> Result<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let text = match self.job.params.get(name) {
+            Some(Value::String(value)) => value.clone(),
+            Some(value) => value.to_string(),
+            None => {
+                return Err(Error::MissingParam {
+                    name: name.to_string(),
+                })
+            }
+        };
+
+        text.parse().map_err(|err: T::Err| Error::InvalidParam {
+            name: name.to_string(),
+            message: err.to_string(),
+        })
+    }
+
+    /// Directory whose files are uploaded as the job's artifacts, ./output in local mode
+    pub fn output_dir(&self) -> &Path {
+        &self.output_dir
+    }
+
+    /// Path to write an artifact to, creating its parent directories
+    pub fn artifact_path(&self, name: impl AsRef<Path>) -> std::io::Result<PathBuf> {
+        let path = self.output_dir.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        Ok(path)
+    }
+
+    /// Reports how far through the job is, from 0.0 to 1.0, replacing its previous progress
+    pub fn report_progress(&self, fraction: f64, message: Option<&str>) -> Result<()> {
+        if !(0.0..=1.0).contains(&fraction) {
+            return Err(Error::Report {
+                kind: "progress".to_string(),
+                message: "fraction must be between 0 and 1".to_string(),
+            });
+        }
+        self.report(
+            "progress",
+            json!({ "fraction": fraction, "message": message }),
+        )
+    }
+
+    /// Appends a value to one of the job's metric series, i.e. the loss at each epoch
+    pub fn log_metric(&self, name: &str, value: f64, step: Option<u64>) -> Result<()> {
+        self.report(
+            "metrics",
+            json!({ "name": name, "value": value, "step": step }),
+        )
+    }
+
+    /// Records the job's result, which must serialize to a JSON object
+    pub fn set_result<T: Serialize>(&self, result: &T) -> Result<()> {
+        let result = serde_json::to_value(result).map_err(|err| Error::Report {
+            kind: "result".to_string(),
+            message: err.to_string(),
+        })?;
+        if !result.is_object() {
+            return Err(Error::Report {
+                kind: "result".to_string(),
+                message: "result must be a JSON object".to_string(),
+            });
+        }
+        self.report("result", result)
+    }
+
+    fn report(&self, kind: &str, body: Value) -> Result<()> {
+        let failed = |message: String| Error::Report {
+            kind: kind.to_string(),
+            message,
+        };
+        let (url, batch_id, token) = match &self.reporter {
+            Reporter::Gateway {
+                url,
+                batch_id,
+                token,
+            } => (url, batch_id, token),
+            Reporter::File(path) => {
+                return append_report(path, kind, &body).map_err(|err| failed(err.to_string()));
+            }
+            Reporter::Stderr => {
+                eprintln!("rft {}: {}", kind, body);
+                return Ok(());
+            }
+        };
+
+        let uri = format!(
+            "{}/batch/{}/jobs/{}/{}",
+            url, batch_id, self.job.job_id, kind
+        );
+        let request = Request::post(uri)
+            .header("Content-Type", "application/json")
+            .header("Authorization", format!("Bearer {}", token))
+            .body(body.to_string())
+            .map_err(|err| failed(err.to_string()))?;
+        let mut response = isahc::send(request).map_err(|err| failed(err.to_string()))?;
+        if !response.status().is_success() {
+            return Err(failed(response.text().unwrap_or_default()));
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads a datafile, a job with `job_id` and `params`, or in local mode a bare object of params
+fn read_job(path: &Path) -> Result<Job> {
+    let data = fs::read_to_string(path).context(ReadData { path })?;
+    let value: Value = serde_json::from_str(&data).context(ParseData { path })?;
+
+    let mut job = match value.get("params") {
+        Some(Value::Object(_)) => serde_json::from_value::<Job>(value),
+        _ => serde_json::from_value(value).map(|params| Job {
+            job_id: String::new(),
+            params,
+        }),
+    }
+    .context(ParseData { path })?;
+    if job.job_id.is_empty() {
+        job.job_id = LOCAL_JOB_ID.to_string();
+    }
+
+    Ok(job)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Error, Worker};
+    use std::collections::HashMap;
+
+    #[test]
+    fn reads_local_params() {
+        let dir = tempfile::tempdir().unwrap();
+        let params_path = dir.path().join("params.json");
+        std::fs::write(
+            &params_path,
+            r#"{"start_date": 1990, "rate": "0.5", "regions": ["us-east-1"]}"#,
+        )
+        .unwrap();
+
+        let mut env = HashMap::new();
+        env.insert("RFT_DATA_PATH", dir.path().join("missing.json"));
+        env.insert("RFT_PARAMS_FILE", params_path);
+        let worker =
+            Worker::from_lookup(|name| env.get(name).map(|v| v.display().to_string())).unwrap();
+
+        assert_eq!(worker.job_id(), "local");
+        assert_eq!(worker.batch_id(), None);
+        assert_eq!(worker.param::<u32>("start_date").unwrap(), 1990);
+        assert_eq!(
+            worker.param::<Vec<String>>("regions").unwrap(),
+            vec!["us-east-1"]
+        );
+        assert_eq!(worker.parse_param::<f64>("rate").unwrap(), 0.5);
+        assert_eq!(worker.param_or("end_date", 2020).unwrap(), 2020);
+        assert!(matches!(
+            worker.param::<String>("start_date"),
+            Err(Error::InvalidParam { .. })
+        ));
+        assert!(matches!(
+            worker.param::<u32>("end_date"),
+            Err(Error::MissingParam { .. })
+        ));
+        assert_eq!(worker.output_dir(), std::path::Path::new("output"));
+    }
+}